@@ -0,0 +1,48 @@
+use serde_json::Value;
+use tauri::Emitter;
+
+/// Where canvas and server events go; the app sends them to the webview, tests can record them.
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &str, payload: &Value) -> anyhow::Result<()>;
+}
+
+impl EventSink for tauri::AppHandle {
+    fn emit(&self, event: &str, payload: &Value) -> anyhow::Result<()> {
+        Emitter::emit(self, event, payload)?;
+        Ok(())
+    }
+}
+
+/// Keeps every event in memory so tests can assert on what the frontend would have received.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default)]
+pub struct RecordingSink(std::sync::Mutex<Vec<(String, Value)>>);
+
+#[cfg(any(test, feature = "testing"))]
+impl RecordingSink {
+    /// The events recorded since the last call, oldest first.
+    pub fn take(&self) -> Vec<(String, Value)> {
+        std::mem::take(
+            &mut *self
+                .0
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        )
+    }
+
+    /// Names of the events recorded since the last `take`, which clears them.
+    pub fn take_names(&self) -> Vec<String> {
+        self.take().into_iter().map(|(event, _)| event).collect()
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl EventSink for RecordingSink {
+    fn emit(&self, event: &str, payload: &Value) -> anyhow::Result<()> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push((event.to_string(), payload.clone()));
+        Ok(())
+    }
+}
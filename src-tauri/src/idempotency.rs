@@ -0,0 +1,265 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Bytes;
+use axum::http::{HeaderMap, StatusCode};
+
+/// Most responses remembered at once; the least recently used is dropped first.
+const CAPACITY: usize = 1024;
+
+/// A response to replay when a client retries with the same key.
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+#[derive(Debug)]
+enum Slot {
+    /// The first request with the key is still running
+    InFlight,
+    Done(StoredResponse),
+}
+
+#[derive(Debug)]
+struct Entry {
+    slot: Slot,
+    /// Hash of the request body the key was first used with
+    fingerprint: u64,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    /// Bumped on every access to order entries by recency
+    clock: u64,
+}
+
+/// What to do with a request carrying an `Idempotency-Key`.
+#[derive(Debug)]
+pub enum Claim {
+    /// First use of the key: run the request, then `complete` the reservation
+    Reserved(Reservation),
+    /// The key already succeeded with this body
+    Replay(StoredResponse),
+    /// The first request with the key hasn't finished yet
+    InFlight,
+    /// The key was first used with a different body
+    Mismatch,
+}
+
+/// Holds a key as in flight; dropping it without `complete` frees the key for a retry.
+#[derive(Debug)]
+pub struct Reservation {
+    cache: Arc<IdempotencyCache>,
+    key: String,
+    completed: bool,
+}
+
+impl Reservation {
+    /// Store `response` for replay to later requests with the key.
+    pub fn complete(mut self, response: StoredResponse) {
+        self.completed = true;
+        let mut entries = self.cache.lock();
+        if let Some(entry) = entries.by_key.get_mut(&self.key) {
+            entry.slot = Slot::Done(response);
+            entry.stored_at = Instant::now();
+        }
+    }
+}
+
+impl Drop for Reservation {
+    // Failed, panicked or cancelled requests leave nothing behind, so retries run again
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+        let mut entries = self.cache.lock();
+        if matches!(
+            entries.by_key.get(&self.key),
+            Some(Entry {
+                slot: Slot::InFlight,
+                ..
+            })
+        ) {
+            entries.by_key.remove(&self.key);
+        }
+    }
+}
+
+/// Responses to recent mutations keyed by `Idempotency-Key`, each kept for `ttl`.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyCache {
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Entries> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Look up `key` for a request whose body hashes to `fingerprint`, reserving it when
+    /// unused so concurrent retries can't run the request twice.
+    pub fn claim(self: &Arc<Self>, key: &str, fingerprint: u64) -> Claim {
+        let mut entries = self.lock();
+        entries.clock += 1;
+        let clock = entries.clock;
+
+        if let Some(entry) = entries.by_key.get_mut(key) {
+            if entry.stored_at.elapsed() < self.ttl {
+                entry.last_used = clock;
+                return match &entry.slot {
+                    _ if entry.fingerprint != fingerprint => Claim::Mismatch,
+                    Slot::InFlight => Claim::InFlight,
+                    Slot::Done(response) => Claim::Replay(response.clone()),
+                };
+            }
+            entries.by_key.remove(key);
+        }
+
+        self.make_room(&mut entries);
+        entries.by_key.insert(
+            key.to_string(),
+            Entry {
+                slot: Slot::InFlight,
+                fingerprint,
+                stored_at: Instant::now(),
+                last_used: clock,
+            },
+        );
+        Claim::Reserved(Reservation {
+            cache: Arc::clone(self),
+            key: key.to_string(),
+            completed: false,
+        })
+    }
+
+    // Drop expired and then stale finished entries; running requests keep their keys
+    fn make_room(&self, entries: &mut Entries) {
+        if entries.by_key.len() >= CAPACITY {
+            let ttl = self.ttl;
+            entries
+                .by_key
+                .retain(|_, entry| entry.stored_at.elapsed() < ttl);
+        }
+        if entries.by_key.len() >= CAPACITY {
+            let least_recent = entries
+                .by_key
+                .iter()
+                .filter(|(_, entry)| matches!(entry.slot, Slot::Done(_)))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(least_recent) = least_recent {
+                entries.by_key.remove(&least_recent);
+            }
+        }
+    }
+}
+
+/// Hash of a request body, so a reused key can be told apart from a retry.
+pub fn fingerprint(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &'static str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from_static(body.as_bytes()),
+        }
+    }
+
+    fn reserve(cache: &Arc<IdempotencyCache>, key: &str, body: &[u8]) -> Reservation {
+        match cache.claim(key, fingerprint(body)) {
+            Claim::Reserved(reservation) => reservation,
+            other => panic!("expected a reservation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stored_responses_are_replayed_until_they_expire() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::from_millis(50)));
+        reserve(&cache, "key", b"body").complete(response("first"));
+        match cache.claim("key", fingerprint(b"body")) {
+            Claim::Replay(stored) => assert_eq!(stored.body, "first"),
+            other => panic!("expected a replay, got {:?}", other),
+        }
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(
+            cache.claim("key", fingerprint(b"body")),
+            Claim::Reserved(_)
+        ));
+    }
+
+    #[test]
+    fn keys_are_held_while_in_flight_and_freed_if_dropped() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::from_secs(60)));
+        let reservation = reserve(&cache, "key", b"body");
+        assert!(matches!(
+            cache.claim("key", fingerprint(b"body")),
+            Claim::InFlight
+        ));
+
+        drop(reservation);
+        assert!(matches!(
+            cache.claim("key", fingerprint(b"body")),
+            Claim::Reserved(_)
+        ));
+    }
+
+    #[test]
+    fn a_key_reused_with_another_body_is_a_mismatch() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::from_secs(60)));
+        reserve(&cache, "key", b"first").complete(response("ok"));
+        assert!(matches!(
+            cache.claim("key", fingerprint(b"second")),
+            Claim::Mismatch
+        ));
+    }
+
+    #[test]
+    fn a_full_cache_drops_the_least_recently_used_entry() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::from_secs(60)));
+        for i in 0..CAPACITY {
+            reserve(&cache, &format!("key-{}", i), b"body").complete(response("ok"));
+        }
+        // Touching the oldest entry makes the second oldest the one to go
+        assert!(matches!(
+            cache.claim("key-0", fingerprint(b"body")),
+            Claim::Replay(_)
+        ));
+        reserve(&cache, "new", b"body").complete(response("ok"));
+
+        assert!(matches!(
+            cache.claim("key-0", fingerprint(b"body")),
+            Claim::Replay(_)
+        ));
+        assert!(matches!(
+            cache.claim("key-1", fingerprint(b"body")),
+            Claim::Reserved(_)
+        ));
+    }
+}
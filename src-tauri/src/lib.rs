@@ -1,31 +1,262 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
+mod events;
+mod idempotency;
+mod metrics;
+mod rate_limit;
 mod server;
 
+pub use events::EventSink;
+#[cfg(feature = "testing")]
+pub use events::RecordingSink;
+#[cfg(feature = "testing")]
+pub use server::test_router;
+pub use server::{create_router, AppState, ServerConfig};
+
+use tauri::Manager;
+use tokio::sync::{oneshot, Mutex};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+/// The background HTTP server task and the token that stops it.
+struct RunningServer {
+    shutdown: CancellationToken,
+    task: tauri::async_runtime::JoinHandle<()>,
+    /// Resolves once the server answers requests or has failed to bind; taken by `server_ready`
+    ready: Option<oneshot::Receiver<server::ServerReady>>,
+}
+
+#[derive(Default)]
+struct ServerHandle(Mutex<Option<RunningServer>>);
+
+/// Log output style, chosen with `EXTAURI_LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Json,
+    Pretty,
+    Compact,
+}
+
+impl LogFormat {
+    /// `json` unless the value names another format; JSON keeps log pipelines working.
+    fn parse(value: Option<&str>) -> Self {
+        match value.map(|v| v.trim().to_ascii_lowercase()).as_deref() {
+            Some("pretty") => LogFormat::Pretty,
+            Some("compact") => LogFormat::Compact,
+            Some("json") | Some("") | None => LogFormat::Json,
+            Some(other) => {
+                // The subscriber isn't installed yet, so this can't go through tracing
+                eprintln!("未知的日志格式 EXTAURI_LOG_FORMAT={}，使用 json", other);
+                LogFormat::Json
+            }
+        }
+    }
+}
 
 #[tauri::command]
 fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
+// Report whether the HTTP server is listening and on which address
+#[tauri::command]
+fn server_info(state: tauri::State<'_, server::AppState>) -> server::ServerInfo {
+    state.server_info()
+}
+
+// Draw elements onto the default scene without going through HTTP
+#[tauri::command]
+async fn push_elements(
+    state: tauri::State<'_, server::AppState>,
+    elements: serde_json::Value,
+) -> Result<(), String> {
+    draw_elements(&state, elements).await
+}
+
+async fn draw_elements(
+    state: &server::AppState,
+    elements: serde_json::Value,
+) -> Result<(), String> {
+    let payload = server::DrawPayload {
+        elements: Some(elements),
+        app_state: None,
+        files: None,
+    };
+    server::apply_draw(state, server::DEFAULT_SCENE, &payload)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+// Render the default scene and write it to `path`, for saving without a browser download
+#[tauri::command]
+async fn export_to_file(
+    state: tauri::State<'_, server::AppState>,
+    path: String,
+    format: String,
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    write_export(&state, &path, &format, width, height).await
+}
+
+async fn write_export(
+    state: &server::AppState,
+    path: &str,
+    format: &str,
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    let export = server::export_scene(state, server::DEFAULT_SCENE, format, width, height)
+        .await
+        .map_err(|err| err.to_string())?;
+    // Text formats are already UTF-8, so every format is written as raw bytes
+    let written = path.to_string();
+    tauri::async_runtime::spawn_blocking(move || std::fs::write(&written, export.body))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("failed to write {}: {}", path, err))?;
+    info!(
+        target: "canvas_export",
+        action = "export_to_file",
+        format = %format,
+        path = %path,
+        "画布已导出到文件"
+    );
+    Ok(())
+}
+
+// Wait until the HTTP server answers requests and return its port, or why it couldn't start
+#[tauri::command]
+async fn server_ready(
+    handle: tauri::State<'_, ServerHandle>,
+    state: tauri::State<'_, server::AppState>,
+) -> Result<u16, String> {
+    // Take the receiver first so the handle isn't locked while waiting
+    let ready = handle
+        .0
+        .lock()
+        .await
+        .as_mut()
+        .and_then(|running| running.ready.take());
+    if let Some(ready) = ready {
+        return match ready.await {
+            Ok(Ok(port)) => Ok(port),
+            Ok(Err(reason)) => Err(format!("server failed to start: {}", reason)),
+            Err(_) => Err("server task ended before it started listening".to_string()),
+        };
+    }
+
+    // Readiness was already reported once; the status has the outcome since
+    let info = state.server_info();
+    match info.error {
+        Some(failure) if failure.stage == "bind" => {
+            Err(format!("server failed to start: {}", failure.message))
+        }
+        Some(failure) => Err(failure.message),
+        None if info.running => Ok(info.port),
+        None => Err("server is not running".to_string()),
+    }
+}
+
+// Stop the HTTP server and wait for it to finish draining requests
+#[tauri::command]
+async fn stop_server(handle: tauri::State<'_, ServerHandle>) -> Result<bool, String> {
+    let Some(running) = handle.0.lock().await.take() else {
+        return Ok(false);
+    };
+    running.shutdown.cancel();
+    running.task.await.map_err(|err| err.to_string())?;
+    Ok(true)
+}
+
+// Start the HTTP server again after it was stopped
+#[tauri::command]
+async fn start_server(
+    app: tauri::AppHandle,
+    handle: tauri::State<'_, ServerHandle>,
+) -> Result<bool, String> {
+    let mut running = handle.0.lock().await;
+    if running
+        .as_ref()
+        .is_some_and(|server| !server.shutdown.is_cancelled())
+    {
+        return Ok(false);
+    }
+    // Shut down through /admin/shutdown; let it finish draining before binding the port again
+    if let Some(stopped) = running.take() {
+        stopped.task.await.map_err(|err| err.to_string())?;
+    }
+    *running = Some(spawn_http_server(app));
+    Ok(true)
+}
+
+fn spawn_http_server(app_handle: tauri::AppHandle) -> RunningServer {
+    let shutdown = CancellationToken::new();
+    let server_shutdown = shutdown.clone();
+    let state = app_handle.state::<server::AppState>().inner().clone();
+    let (ready_tx, ready) = oneshot::channel();
+    let task = tauri::async_runtime::spawn(async move {
+        // The failure also reaches the frontend through server_info and the server_failed event
+        match server::start_http_server(state, server_shutdown, ready_tx).await {
+            Ok(()) => {}
+            Err(err @ server::ServerError::Bind { .. }) => error!(
+                target: "server_startup",
+                error = %err,
+                "HTTP服务器启动失败"
+            ),
+            Err(err) => error!(
+                target: "server_startup",
+                error = %err,
+                "HTTP服务器运行中出错"
+            ),
+        }
+    });
+    RunningServer {
+        shutdown,
+        task,
+        ready: Some(ready),
+    }
+}
+
 fn init_logging() {
+    let format = LogFormat::parse(std::env::var("EXTAURI_LOG_FORMAT").ok().as_deref());
+    let fmt_layer = match format {
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_target(true)
+            .with_thread_ids(true)
+            .with_thread_names(true)
+            .with_file(true)
+            .with_line_number(true)
+            .boxed(),
+        // Human-readable output for local development, without the thread and source noise
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .pretty()
+            .with_target(true)
+            .with_thread_ids(false)
+            .with_thread_names(false)
+            .with_file(false)
+            .with_line_number(false)
+            .boxed(),
+        LogFormat::Compact => tracing_subscriber::fmt::layer()
+            .compact()
+            .with_target(true)
+            .boxed(),
+    };
+
     tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .json()
-                .with_target(true)
-                .with_thread_ids(true)
-                .with_thread_names(true)
-                .with_file(true)
-                .with_line_number(true),
-        )
+        .with(fmt_layer)
         .with(
             tracing_subscriber::EnvFilter::from_default_env()
                 .add_directive("extauri_lib=info".parse().unwrap())
                 .add_directive("http_server=info".parse().unwrap())
+                .add_directive("http_request=info".parse().unwrap())
+                .add_directive("canvas_draw=info".parse().unwrap())
                 .add_directive("canvas_update=info".parse().unwrap())
                 .add_directive("canvas_clear=info".parse().unwrap())
+                .add_directive("canvas_export=info".parse().unwrap())
+                .add_directive("canvas_element=info".parse().unwrap())
+                .add_directive("canvas_event=info".parse().unwrap())
                 .add_directive("server_startup=info".parse().unwrap()),
         )
         .init();
@@ -40,21 +271,133 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .manage(ServerHandle::default())
         .setup(|app| {
-            let app_handle = app.handle().clone();
-            tauri::async_runtime::spawn(async move {
-                // start HTTP server in background
-                if let Err(err) = server::start_http_server(app_handle).await {
-                    error!(
-                        target: "server_startup",
-                        error = %err,
-                        "HTTP服务器启动失败"
-                    );
-                }
-            });
+            // Share the canvas with commands and keep it across server restarts
+            let config = server::ServerConfig::from_env()?;
+            let sink = std::sync::Arc::new(app.handle().clone());
+            app.manage(server::AppState::new(sink, config));
+
+            // start HTTP server in background
+            let running = spawn_http_server(app.handle().clone());
+            *app.state::<ServerHandle>().0.blocking_lock() = Some(running);
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![greet])
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            server_info,
+            server_ready,
+            push_elements,
+            export_to_file,
+            stop_server,
+            start_server
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn export_file(format: &str) -> Vec<u8> {
+        let sink = std::sync::Arc::new(events::RecordingSink::default());
+        let state = server::AppState::new(sink, server::ServerConfig::default());
+        let payload = server::DrawPayload {
+            elements: Some(serde_json::json!([{
+                "id": "a",
+                "type": "rectangle",
+                "x": 10.0,
+                "y": 10.0,
+                "width": 100.0,
+                "height": 50.0,
+                "strokeColor": "#1e1e1e",
+                "backgroundColor": "transparent",
+                "strokeWidth": 2,
+            }])),
+            app_state: None,
+            files: None,
+        };
+        server::apply_draw(&state, server::DEFAULT_SCENE, &payload)
+            .await
+            .expect("draw");
+
+        let path = std::env::temp_dir().join(format!(
+            "extauri-export-{}.{}",
+            uuid::Uuid::new_v4(),
+            format
+        ));
+        let path = path.to_str().expect("UTF-8 temp path").to_string();
+        write_export(&state, &path, format, 800, 600)
+            .await
+            .expect("export written");
+        let written = std::fs::read(&path).expect("export file exists");
+        std::fs::remove_file(&path).expect("remove export file");
+        written
+    }
+
+    #[tokio::test]
+    async fn export_to_file_writes_png() {
+        assert!(export_file("png").await.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[tokio::test]
+    async fn export_to_file_writes_svg() {
+        let svg = String::from_utf8(export_file("svg").await).expect("UTF-8 SVG");
+        assert!(svg.starts_with("<?xml"));
+        assert!(svg.contains("<rect"));
+    }
+
+    #[tokio::test]
+    async fn push_elements_draws_onto_the_default_scene() {
+        let sink = std::sync::Arc::new(events::RecordingSink::default());
+        let state = server::AppState::new(sink.clone(), server::ServerConfig::default());
+        let elements = serde_json::json!([{
+            "id": "pushed",
+            "type": "ellipse",
+            "x": 0.0,
+            "y": 0.0,
+            "width": 40.0,
+            "height": 40.0,
+        }]);
+        draw_elements(&state, elements.clone())
+            .await
+            .expect("elements drawn");
+
+        let events = sink.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "excalidraw_draw");
+        assert_eq!(events[0].1["elements"], elements);
+        let export = server::export_scene(&state, server::DEFAULT_SCENE, "json", 800, 600)
+            .await
+            .expect("scene exports");
+        let scene: serde_json::Value = serde_json::from_slice(&export.body).expect("JSON export");
+        assert_eq!(scene["elements"], elements);
+    }
+
+    #[tokio::test]
+    async fn push_elements_rejects_invalid_elements() {
+        let sink = std::sync::Arc::new(events::RecordingSink::default());
+        let state = server::AppState::new(sink.clone(), server::ServerConfig::default());
+        let err = draw_elements(&state, serde_json::json!([{ "type": "rectangle" }]))
+            .await
+            .expect_err("an element without an id is rejected");
+        assert!(err.contains("'id' must be a string"), "{}", err);
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn log_format_parses_known_values() {
+        assert_eq!(LogFormat::parse(Some("json")), LogFormat::Json);
+        assert_eq!(LogFormat::parse(Some("pretty")), LogFormat::Pretty);
+        assert_eq!(LogFormat::parse(Some("compact")), LogFormat::Compact);
+        assert_eq!(LogFormat::parse(Some(" Pretty ")), LogFormat::Pretty);
+    }
+
+    #[test]
+    fn log_format_defaults_to_json() {
+        assert_eq!(LogFormat::parse(None), LogFormat::Json);
+        assert_eq!(LogFormat::parse(Some("")), LogFormat::Json);
+        assert_eq!(LogFormat::parse(Some("xml")), LogFormat::Json);
+    }
+}
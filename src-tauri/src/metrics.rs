@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Counters served at `/metrics` in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<RequestKey, u64>>,
+    mutations: AtomicU64,
+    emit_failures: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+    method: String,
+    route: String,
+    status: u16,
+}
+
+impl Metrics {
+    /// Count a finished request; `route` is the route template so ids don't explode the label set.
+    pub fn record_request(&self, method: &str, route: &str, status: u16) {
+        let key = RequestKey {
+            method: method.to_string(),
+            route: route.to_string(),
+            status,
+        };
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(key)
+            .or_default() += 1;
+    }
+
+    pub fn record_mutation(&self) {
+        self.mutations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_emit_failure(&self) {
+        self.emit_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render every counter, plus a gauge from the given `(scene, element count)` pairs.
+    pub fn render(&self, element_counts: &[(String, usize)]) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP extauri_http_requests_total HTTP requests handled, by route and status.\n",
+        );
+        out.push_str("# TYPE extauri_http_requests_total counter\n");
+        let requests = self
+            .requests
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (key, count) in requests.iter() {
+            out.push_str(&format!(
+                "extauri_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}\n",
+                escape_label(&key.method),
+                escape_label(&key.route),
+                key.status,
+                count
+            ));
+        }
+        drop(requests);
+
+        out.push_str("# HELP extauri_canvas_mutations_total Canvas changes committed.\n");
+        out.push_str("# TYPE extauri_canvas_mutations_total counter\n");
+        out.push_str(&format!(
+            "extauri_canvas_mutations_total {}\n",
+            self.mutations.load(Ordering::Relaxed)
+        ));
+
+        out.push_str(
+            "# HELP extauri_emit_failures_total Events that could not be sent to the frontend.\n",
+        );
+        out.push_str("# TYPE extauri_emit_failures_total counter\n");
+        out.push_str(&format!(
+            "extauri_emit_failures_total {}\n",
+            self.emit_failures.load(Ordering::Relaxed)
+        ));
+
+        out.push_str("# HELP extauri_canvas_elements Elements currently on each scene.\n");
+        out.push_str("# TYPE extauri_canvas_elements gauge\n");
+        for (scene, count) in element_counts {
+            out.push_str(&format!(
+                "extauri_canvas_elements{{scene=\"{}\"}} {}\n",
+                escape_label(scene),
+                count
+            ));
+        }
+
+        out
+    }
+}
+
+// Label values are quoted, so backslashes, quotes and newlines must be escaped
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_counts_requests_per_route_and_status() {
+        let metrics = Metrics::default();
+        metrics.record_request("POST", "/draw", 200);
+        metrics.record_request("POST", "/draw", 200);
+        metrics.record_request("POST", "/draw", 400);
+        metrics.record_mutation();
+
+        let text = metrics.render(&[("default".to_string(), 3)]);
+        assert!(text.contains(
+            "extauri_http_requests_total{method=\"POST\",route=\"/draw\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "extauri_http_requests_total{method=\"POST\",route=\"/draw\",status=\"400\"} 1\n"
+        ));
+        assert!(text.contains("extauri_canvas_mutations_total 1\n"));
+        assert!(text.contains("extauri_emit_failures_total 0\n"));
+        assert!(text.contains("extauri_canvas_elements{scene=\"default\"} 3\n"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        let metrics = Metrics::default();
+        let text = metrics.render(&[("a\"b\\c\nd".to_string(), 1)]);
+        assert!(text.contains(r#"extauri_canvas_elements{scene="a\"b\\c\nd"} 1"#));
+    }
+}
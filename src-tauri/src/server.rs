@@ -1,30 +1,582 @@
-use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::convert::Infallible;
+use std::future::IntoFuture;
+use std::io::{Cursor, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
+use anyhow::{anyhow, Context};
 use axum::{
-    extract::{Path, Query, State},
-    http::{header, StatusCode},
-    response::{IntoResponse, Response},
-    routing::{delete, get, post},
+    body::{Body, Bytes},
+    extract::{
+        rejection::JsonRejection,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, DefaultBodyLimit, FromRequest, MatchedPath, Path, Query, Request, State,
+    },
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post, put},
     Json, Router,
 };
 use base64::{engine::general_purpose, Engine as _};
+use image::codecs::{avif::AvifEncoder, jpeg::JpegEncoder};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use resvg::{tiny_skia, usvg};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tauri::Emitter;
-use tower_http::cors::CorsLayer;
-use tracing::{error, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, oneshot, Notify, RwLock};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, ReceiverStream},
+    Stream, StreamExt,
+};
+use tokio_util::sync::CancellationToken;
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, Any, CorsLayer},
+};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument};
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
+use crate::events::EventSink;
+use crate::idempotency::{fingerprint, Claim, IdempotencyCache, StoredResponse};
+use crate::metrics::Metrics;
+use crate::rate_limit::RateLimiter;
+
+/// Emitted with a scene-tagged `DrawPayload` whenever more than a single element changes.
 const EVENT_DRAW: &str = "excalidraw_draw";
+/// Emitted with a scene-tagged `ElementEventPayload` when one element is added.
+const EVENT_ELEMENT_ADDED: &str = "excalidraw_element_added";
+/// Emitted with a scene-tagged `ElementEventPayload` when one element is replaced or patched.
+const EVENT_ELEMENT_UPDATED: &str = "excalidraw_element_updated";
+/// Emitted with a scene-tagged `ElementEventPayload`, without `element`, when one is removed.
+const EVENT_ELEMENT_REMOVED: &str = "excalidraw_element_removed";
+/// Emitted with a scene-tagged `ClearPayload` when a canvas is cleared.
+const EVENT_CLEAR: &str = "excalidraw_clear";
+/// Emitted with a `ServerFailure` when the HTTP server can't bind or stops with an error.
+const EVENT_SERVER_FAILED: &str = "server_failed";
+/// Times an event is sent before the request fails with `emit_failed`.
+const EMIT_ATTEMPTS: u32 = 3;
+/// Wait before the first emit retry, doubled for each one after.
+const EMIT_RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// Scene used by the unprefixed canvas routes.
+pub const DEFAULT_SCENE: &str = "default";
 const DEFAULT_PORT: u16 = 31337;
+/// Pause between startup probes of `/health` while the serve loop comes up.
+const READINESS_PROBE_INTERVAL: Duration = Duration::from_millis(20);
+/// Longest a single startup probe may take before it is retried.
+const READINESS_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How many canvas updates an SSE subscriber may fall behind before skipping ahead.
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+/// Margin kept around the elements when an export is fitted to their bounds.
+const DEFAULT_EXPORT_PADDING: f64 = 20.0;
+/// Export background for dark-themed scenes without an explicit background colour.
+const DARK_BACKGROUND: &str = "#121212";
+/// Default per-client limit on mutating requests per second.
+const DEFAULT_RATE_LIMIT: u32 = 50;
+/// Size of the chunks streamed JSON bodies are sent in.
+const JSON_STREAM_CHUNK_BYTES: usize = 64 * 1024;
+/// Chunks a streamed JSON body encodes ahead of the client before waiting for it.
+const JSON_STREAM_BUFFERED_CHUNKS: usize = 4;
+/// Default time a response is replayed for a repeated `Idempotency-Key`, in seconds.
+const DEFAULT_IDEMPOTENCY_TTL_SECS: usize = 600;
+/// Request header carrying the key for the `/admin` routes.
+const API_KEY_HEADER: &str = "x-api-key";
+/// Response header marking a replay of an earlier response.
+const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+/// appState keys stored with a scene by default; the rest are transient UI state.
+const DEFAULT_APP_STATE_KEYS: &[&str] = &[
+    "viewBackgroundColor",
+    "theme",
+    "gridSize",
+    "zoom",
+    "scrollX",
+    "scrollY",
+];
+/// Default number of undo steps kept per scene.
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+/// Changes listed by `GET /canvas/history` per scene; older ones are dropped.
+const CHANGE_LOG_LIMIT: usize = 100;
+/// Excalidraw's default text line height, as a multiple of the font size.
+const DEFAULT_LINE_HEIGHT: f64 = 1.25;
+/// Corner radius of large adaptive-rounded rectangles, as in Excalidraw.
+const ADAPTIVE_CORNER_RADIUS: f64 = 32.0;
+/// Share of the shorter side used as the radius for proportional rounding.
+const PROPORTIONAL_CORNER_RATIO: f64 = 0.25;
+/// Element types the SVG converter draws; anything else is handled as `UnknownTypes` says.
+const SVG_ELEMENT_TYPES: &[&str] = &[
+    "rectangle",
+    "ellipse",
+    "diamond",
+    "arrow",
+    "line",
+    "freedraw",
+    "text",
+    "image",
+    "embeddable",
+    "iframe",
+    "frame",
+    "magicframe",
+];
+/// How much wider a highlighter freedraw is drawn than its `strokeWidth`.
+const HIGHLIGHTER_WIDTH_SCALE: f64 = 4.0;
+/// Stroke opacity of highlighter freedraws, so what they cover stays readable.
+const HIGHLIGHTER_OPACITY: f64 = 0.4;
+/// Excalidraw's numbered fonts; other ids are drawn in a generic family.
+const EXCALIDRAW_FONTS: &[(i64, &str)] = &[
+    (1, "Virgil"),
+    (2, "Helvetica"),
+    (3, "Cascadia"),
+    (5, "Excalifont"),
+    (6, "Nunito"),
+    (7, "Lilita One"),
+    (8, "Comic Shanns"),
+    (9, "Liberation Sans"),
+    (10, "Assistant"),
+];
+/// Element types with nothing to show, which exports skip by default.
+const NON_VISUAL_ELEMENT_TYPES: &[&str] = &["selection"];
+/// Rough glyph width as a share of the font size, for fitting text without font metrics.
+const AVG_GLYPH_WIDTH_EM: f64 = 0.6;
+/// Font size of the link label drawn inside embed boxes.
+const EMBED_LABEL_FONT_SIZE: f64 = 14.0;
+/// Largest `scale` honoured by exports; bigger values are clamped to keep pixmaps bounded.
+const MAX_EXPORT_SCALE: f64 = 5.0;
+/// Most pixels a raster export may have after scaling; about 160 MB of RGBA.
+const MAX_EXPORT_PIXELS: u64 = 40_000_000;
+/// Outline colour of frames, which Excalidraw draws lighter than regular shapes.
+const FRAME_STROKE: &str = "#bbb";
+/// Font size of the frame name drawn above each frame.
+const FRAME_NAME_FONT_SIZE: f64 = 14.0;
+/// Formats accepted by `GET /canvas/export`.
+const EXPORT_FORMATS: &[&str] = &["svg", "json", "toDataURL", "png", "jpeg", "webp", "avif"];
+/// Formats `export_to_file` can write; a data URL is JSON wrapping an SVG, not a file.
+const FILE_EXPORT_FORMATS: &[&str] = &["svg", "json", "png", "jpeg", "webp", "avif"];
+/// Decimal places kept in optimized SVG export coordinates unless asked otherwise.
+const DEFAULT_SVG_PRECISION: u32 = 2;
+/// Most decimal places an export keeps; f64 has no more meaningful digits at canvas scale.
+const MAX_SVG_PRECISION: u32 = 8;
+/// Element fields rounded by JSON exports given a `precision`.
+const ROUNDED_ELEMENT_FIELDS: &[&str] = &["x", "y", "width", "height"];
+/// Attributes holding only numbers, whose values SVG exports round.
+const SVG_NUMERIC_ATTRIBUTES: &[&str] = &[
+    "x",
+    "y",
+    "x1",
+    "y1",
+    "x2",
+    "y2",
+    "cx",
+    "cy",
+    "r",
+    "rx",
+    "ry",
+    "dx",
+    "dy",
+    "width",
+    "height",
+    "points",
+    "d",
+    "transform",
+    "patternTransform",
+    "viewBox",
+    "stroke-width",
+    "stroke-dasharray",
+    "font-size",
+    "refX",
+    "refY",
+];
+/// Attributes optimized SVGs drop because they restate the SVG default.
+const SVG_DEFAULT_ATTRIBUTES: &[(&str, &str)] = &[
+    ("stroke-width", "1"),
+    ("opacity", "1"),
+    ("text-anchor", "start"),
+    ("dy", "0"),
+];
+/// Lossy encoding quality used when an export doesn't ask for one.
+const DEFAULT_EXPORT_QUALITY: u8 = 80;
+/// rav1e speed preset for AVIF exports; the slower presets take seconds per canvas.
+const AVIF_SPEED: u8 = 8;
+/// Default cap on `/draw` and `/canvas` bodies, which may carry embedded image files.
+const DEFAULT_MAX_BODY_BYTES: usize = 50 * 1024 * 1024;
+/// Cap on bodies for the element routes, which never carry files.
+const ELEMENT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Runtime settings for the HTTP server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Origins allowed by CORS; empty keeps the permissive local-dev behavior
+    pub allowed_origins: Vec<String>,
+    /// Largest request body accepted by `/draw` and `/canvas`, in bytes
+    pub max_body_bytes: usize,
+    /// Compress responses for clients sending `Accept-Encoding`; disable behind a compressing proxy
+    pub compression: bool,
+    /// Undo steps kept per scene; 0 disables undo
+    pub history_limit: usize,
+    /// Interface to listen on; anything but loopback exposes the API to the network
+    pub bind_address: IpAddr,
+    /// Mutating requests allowed per client IP per second; 0 disables rate limiting
+    pub rate_limit: u32,
+    /// Clear a scene's elements once it has gone this long without changes; `None` never does
+    pub idle_clear: Option<Duration>,
+    /// Coalesce draw events so each scene emits at most one per interval; `None` emits every one
+    pub draw_debounce: Option<Duration>,
+    /// How long a retried POST or PUT with the same `Idempotency-Key` gets the first response;
+    /// `None` disables idempotency keys
+    pub idempotency_ttl: Option<Duration>,
+    /// Most elements a canvas may hold; `None` leaves canvases unbounded
+    pub max_elements: Option<usize>,
+    /// appState keys stored with a scene, dropping the rest; `None` stores appState as sent
+    pub app_state_keys: Option<Vec<String>>,
+    /// Key the `/admin` routes require in `X-API-Key`; `None` turns every admin request away
+    pub api_key: Option<String>,
+    /// How exports draw element types the SVG converter doesn't know
+    pub unknown_types: UnknownTypes,
+    /// Namespace for emitted events, which become `{prefix}:excalidraw_draw` and so on.
+    /// The bundled frontend listens for the unprefixed names.
+    pub event_prefix: Option<String>,
+}
+
+/// How the SVG converter treats element types; types in neither list that it has no shape
+/// for are drawn as a dashed placeholder outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownTypes {
+    /// Drawn as nothing at all, whether or not the converter knows them
+    pub skip: Vec<String>,
+    /// Drawn as their bounding box in the element's own stroke and fill
+    pub best_effort: Vec<String>,
+}
+
+impl Default for UnknownTypes {
+    fn default() -> Self {
+        UnknownTypes {
+            skip: NON_VISUAL_ELEMENT_TYPES
+                .iter()
+                .map(|element_type| element_type.to_string())
+                .collect(),
+            best_effort: Vec::new(),
+        }
+    }
+}
+
+impl UnknownTypes {
+    fn skips(&self, element_type: &str) -> bool {
+        self.skip.iter().any(|skipped| skipped == element_type)
+    }
+
+    fn best_effort(&self, element_type: &str) -> bool {
+        self.best_effort.iter().any(|listed| listed == element_type)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            allowed_origins: Vec::new(),
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            compression: true,
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            rate_limit: DEFAULT_RATE_LIMIT,
+            idle_clear: None,
+            draw_debounce: None,
+            idempotency_ttl: Some(Duration::from_secs(DEFAULT_IDEMPOTENCY_TTL_SECS as u64)),
+            max_elements: None,
+            app_state_keys: Some(
+                DEFAULT_APP_STATE_KEYS
+                    .iter()
+                    .map(|key| key.to_string())
+                    .collect(),
+            ),
+            api_key: None,
+            unknown_types: UnknownTypes::default(),
+            event_prefix: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Read settings from `EXTAURI_*` environment variables, defaulting anything unset.
+    /// Fails on a malformed `EXTAURI_BIND` rather than guessing which interface was meant.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let allowed_origins = std::env::var("EXTAURI_ALLOWED_ORIGINS")
+            .map(|origins| {
+                origins
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|origin| !origin.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let max_body_bytes = env_usize("EXTAURI_MAX_BODY_BYTES", DEFAULT_MAX_BODY_BYTES);
+        let history_limit = env_usize("EXTAURI_HISTORY_LIMIT", DEFAULT_HISTORY_LIMIT);
+        let rate_limit = env_usize("EXTAURI_RATE_LIMIT", DEFAULT_RATE_LIMIT as usize)
+            .try_into()
+            .unwrap_or(u32::MAX);
+        let idle_clear = match env_usize("EXTAURI_IDLE_CLEAR_SECS", 0) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64)),
+        };
+        let draw_debounce = match env_usize("EXTAURI_DRAW_DEBOUNCE_MS", 0) {
+            0 => None,
+            millis => Some(Duration::from_millis(millis as u64)),
+        };
+        let idempotency_ttl =
+            match env_usize("EXTAURI_IDEMPOTENCY_TTL_SECS", DEFAULT_IDEMPOTENCY_TTL_SECS) {
+                0 => None,
+                secs => Some(Duration::from_secs(secs as u64)),
+            };
+        let max_elements = match env_usize("EXTAURI_MAX_ELEMENTS", 0) {
+            0 => None,
+            max => Some(max),
+        };
+
+        // Replaces the default list; `*` keeps every key
+        let app_state_keys = match std::env::var("EXTAURI_APP_STATE_KEYS") {
+            Ok(keys) if keys.trim() == "*" => None,
+            Ok(keys) => Some(
+                keys.split(',')
+                    .map(str::trim)
+                    .filter(|key| !key.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            Err(_) => ServerConfig::default().app_state_keys,
+        };
+
+        // Each list replaces its default; an empty value clears it
+        let type_list = |name: &str| {
+            std::env::var(name).ok().map(|types| {
+                types
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|element_type| !element_type.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let unknown_types = UnknownTypes {
+            skip: type_list("EXTAURI_SKIP_TYPES").unwrap_or_else(|| UnknownTypes::default().skip),
+            best_effort: type_list("EXTAURI_BEST_EFFORT_TYPES").unwrap_or_default(),
+        };
+
+        let event_prefix = match std::env::var("EXTAURI_EVENT_PREFIX") {
+            Ok(prefix) if prefix.trim().is_empty() => None,
+            Ok(prefix) => {
+                let prefix = prefix.trim().to_string();
+                // Tauri rejects event names with any other characters
+                anyhow::ensure!(
+                    prefix
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '/' | ':' | '_')),
+                    "invalid EXTAURI_EVENT_PREFIX '{}': use letters, digits, '-', '/', ':' or '_'",
+                    prefix
+                );
+                Some(prefix)
+            }
+            Err(_) => None,
+        };
+
+        let api_key = std::env::var("EXTAURI_API_KEY")
+            .ok()
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty());
+
+        let compression = std::env::var("EXTAURI_COMPRESSION")
+            .map(|value| {
+                !matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "0" | "false" | "off"
+                )
+            })
+            .unwrap_or(true);
+
+        let bind_address = match std::env::var("EXTAURI_BIND") {
+            Ok(value) => value
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid EXTAURI_BIND address '{}'", value))?,
+            Err(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+
+        Ok(ServerConfig {
+            allowed_origins,
+            max_body_bytes,
+            compression,
+            history_limit,
+            bind_address,
+            rate_limit,
+            idle_clear,
+            draw_debounce,
+            idempotency_ttl,
+            max_elements,
+            app_state_keys,
+            api_key,
+            unknown_types,
+            event_prefix,
+        })
+    }
+}
+
+// Keep only the configured appState keys, so transient fields like `collaborators` aren't stored
+fn retain_app_state(state: &AppState, app_state: &Value) -> Value {
+    match (&state.config.app_state_keys, app_state.as_object()) {
+        (Some(keys), Some(fields)) => Value::Object(
+            fields
+                .iter()
+                .filter(|(key, _)| keys.iter().any(|kept| kept == *key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => app_state.clone(),
+    }
+}
+
+// Parse a numeric setting, warning and falling back to the default when it is malformed
+fn env_usize(name: &str, default: usize) -> usize {
+    match std::env::var(name) {
+        Ok(value) => value.trim().parse().unwrap_or_else(|err| {
+            warn!(
+                target: "http_server",
+                action = "invalid_env_setting",
+                variable = name,
+                value = %value,
+                error = %err,
+                "忽略无效的环境变量配置"
+            );
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+/// OpenAPI description of the core routes, served at `/openapi.json`. Every canvas
+/// route is also available per scene under `/scene/{scene}`.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Extauri canvas API"),
+    paths(
+        health,
+        draw_canvas,
+        get_canvas,
+        update_canvas,
+        replace_canvas,
+        clear_canvas,
+        canvas_history,
+        export_canvas,
+        add_element,
+        get_element,
+        remove_element,
+        update_element
+    )
+)]
+struct ApiDoc;
+
+/// Snapshot of the HTTP server's listening state for the frontend.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServerInfo {
+    pub running: bool,
+    pub port: u16,
+    pub address: String,
+    /// Why the last run failed; cleared when the server starts listening again
+    pub error: Option<ServerFailure>,
+}
+
+/// A server failure as shown to the frontend.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerFailure {
+    /// `bind` when the server never started listening, `serve` when it failed afterwards
+    pub stage: &'static str,
+    pub message: String,
+}
+
+/// Why the HTTP server stopped other than by being shut down.
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("could not listen on {address}: {source}")]
+    Bind {
+        address: SocketAddr,
+        source: std::io::Error,
+    },
+    #[error("server stopped unexpectedly: {0}")]
+    Serve(std::io::Error),
+}
+
+impl ServerError {
+    fn failure(&self) -> ServerFailure {
+        ServerFailure {
+            stage: match self {
+                ServerError::Bind { .. } => "bind",
+                ServerError::Serve(_) => "serve",
+            },
+            message: self.to_string(),
+        }
+    }
+}
+
+/// Sent once the server answers requests (its port) or has failed to bind (the reason).
+pub type ServerReady = Result<u16, String>;
+
+/// Holder for `ServerInfo`, updated as the server starts and stops.
+#[derive(Debug, Default)]
+struct ServerStatus(std::sync::RwLock<ServerInfo>);
+
+impl ServerStatus {
+    fn info(&self) -> ServerInfo {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, info: ServerInfo) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = info;
+    }
+}
 
 #[derive(Clone)]
 pub struct AppState {
-    app: tauri::AppHandle,
-    canvas: Arc<Mutex<CanvasData>>,
+    /// Receives every event meant for the frontend
+    sink: Arc<dyn EventSink>,
+    /// Number of the last event sent through `sink`, across all scenes
+    seq: Arc<AtomicU64>,
+    status: Arc<ServerStatus>,
+    config: Arc<ServerConfig>,
+    scenes: Arc<RwLock<HashMap<String, CanvasData>>>,
+    /// Undo history and activity log keyed by scene id, only locked while holding a `scenes` lock
+    history: Arc<Mutex<HashMap<String, SceneHistory>>>,
+    events: broadcast::Sender<SceneEvent<CanvasData>>,
+    started_at: Instant,
+    /// Stops the server currently serving this state; set for each run, like `started_at`
+    shutdown: Option<CancellationToken>,
+    limiter: Option<Arc<RateLimiter>>,
+    metrics: Arc<Metrics>,
+    /// Set when draw events are debounced; see `flush_draws`
+    pending_draws: Option<Arc<PendingDraws>>,
+    idempotency: Option<Arc<IdempotencyCache>>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+/// Scenes whose latest canvas still has to be sent to the frontend.
+#[derive(Debug, Default)]
+struct PendingDraws {
+    scenes: Mutex<HashSet<String>>,
+    notify: Notify,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, ToSchema)]
 pub struct CanvasData {
     pub elements: Option<Value>,
     #[serde(default, rename = "appState")]
@@ -34,7 +586,154 @@ pub struct CanvasData {
     pub updated_at: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+impl CanvasData {
+    fn empty() -> Self {
+        CanvasData {
+            elements: None,
+            app_state: None,
+            files: None,
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+/// Committed states of one scene, oldest first, with `cursor` at the live one.
+#[derive(Debug)]
+struct SceneHistory {
+    states: VecDeque<CanvasData>,
+    cursor: usize,
+    /// Recent changes for the activity log, oldest first, independent of the undo limit
+    changes: VecDeque<ChangeRecord>,
+    /// `revision` of the most recent change
+    last_change: u64,
+}
+
+impl Default for SceneHistory {
+    // Scenes always start out empty
+    fn default() -> Self {
+        SceneHistory {
+            states: VecDeque::from([CanvasData::empty()]),
+            cursor: 0,
+            changes: VecDeque::new(),
+            last_change: 0,
+        }
+    }
+}
+
+impl SceneHistory {
+    // Record a new live state, discarding anything that could have been redone
+    fn record(&mut self, canvas: &CanvasData, limit: usize) {
+        self.states.truncate(self.cursor + 1);
+        self.states.push_back(canvas.clone());
+        while self.states.len() > limit + 1 {
+            self.states.pop_front();
+        }
+        self.cursor = self.states.len() - 1;
+    }
+
+    // Add an entry to the activity log, dropping the oldest beyond `CHANGE_LOG_LIMIT`
+    fn log_change(&mut self, kind: ChangeKind, element_count: usize) {
+        self.last_change += 1;
+        self.changes.push_back(ChangeRecord {
+            revision: self.last_change,
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            kind,
+            element_count,
+        });
+        while self.changes.len() > CHANGE_LOG_LIMIT {
+            self.changes.pop_front();
+        }
+    }
+
+    fn undo(&mut self) -> Option<CanvasData> {
+        self.cursor = self.cursor.checked_sub(1)?;
+        self.states.get(self.cursor).cloned()
+    }
+
+    fn redo(&mut self) -> Option<CanvasData> {
+        let state = self.states.get(self.cursor + 1).cloned()?;
+        self.cursor += 1;
+        Some(state)
+    }
+}
+
+/// What a change to a scene did, as listed by `GET /canvas/history`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Draw,
+    Update,
+    Replace,
+    Patch,
+    Import,
+    Reset,
+    Clear,
+    Transform,
+    Undo,
+    Redo,
+    AddElement,
+    UpdateElement,
+    PatchElement,
+    RemoveElement,
+    DuplicateElement,
+    ReorderElement,
+    Batch,
+    Stream,
+    RemoveGroup,
+    TranslateGroup,
+}
+
+/// One entry of `GET /canvas/history`; payloads are left out to keep the log cheap.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChangeRecord {
+    /// Increases by one per change to this scene; unrelated to the event `seq`
+    pub revision: u64,
+    pub timestamp: String,
+    pub kind: ChangeKind,
+    /// Elements on the canvas after the change
+    pub element_count: usize,
+}
+
+/// Body of `GET /canvas`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CanvasResponse {
+    pub canvas: CanvasData,
+    /// Changes whenever the elements do, whatever order their fields were sent in
+    pub hash: String,
+    /// `seq` of the latest frontend event; a listener that saw a lower one missed events
+    pub seq: u64,
+}
+
+/// An event payload tagged with the scene it belongs to.
+#[derive(Debug, Serialize, Clone)]
+pub struct SceneEvent<T> {
+    pub scene: String,
+    #[serde(flatten)]
+    pub payload: T,
+}
+
+/// Path parameters for the canvas routes; unprefixed routes use the default scene.
+#[derive(Debug, Deserialize)]
+pub struct ScenePath {
+    #[serde(default = "default_scene")]
+    pub scene: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ElementPath {
+    #[serde(default = "default_scene")]
+    pub scene: String,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupPath {
+    #[serde(default = "default_scene")]
+    pub scene: String,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct DrawPayload {
     #[serde(default)]
     pub elements: Option<Value>,
@@ -44,567 +743,7860 @@ pub struct DrawPayload {
     pub files: Option<Value>,
 }
 
+/// A saved `.excalidraw` file, as produced by `format=json` exports.
 #[derive(Debug, Deserialize)]
+pub struct ImportPayload {
+    #[serde(default, rename = "type")]
+    pub file_type: Option<String>,
+    #[serde(default)]
+    pub elements: Option<Value>,
+    #[serde(default, rename = "appState")]
+    pub app_state: Option<Value>,
+    #[serde(default)]
+    pub files: Option<Value>,
+}
+
+/// A change to a single element; `id` is the element as addressed, which a PUT may rename.
+#[derive(Debug, Serialize, Clone)]
+pub struct ElementEventPayload {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub element: Option<Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ClearPayload {
+    pub updated_at: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ExportQuery {
-    #[serde(default = "default_format")]
-    pub format: String,
+    /// Output format; taken from the `Accept` header when omitted, svg if that names none
+    #[serde(default)]
+    pub format: Option<String>,
     #[serde(default = "default_width")]
     pub width: u32,
     #[serde(default = "default_height")]
     pub height: u32,
+    /// Crop the export to the bounding box of the elements
+    #[serde(default)]
+    pub fit: bool,
+    /// Margin added around the elements on every side with `fit`, in canvas pixels
+    #[serde(default = "default_padding")]
+    pub padding: f64,
+    /// Comma-separated element ids to export instead of the whole scene; unknown ids are ignored
+    #[serde(default)]
+    pub ids: Option<String>,
+    /// `transparent`, a colour name or a hex colour; defaults to the scene's own background
+    #[serde(default)]
+    pub background: Option<String>,
+    /// Encoding quality from 0 to 100 for jpeg and avif; webp is always lossless
+    #[serde(default)]
+    pub quality: Option<i64>,
+    /// Output size multiplier for high-DPI exports; the viewBox is unchanged
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Return a JSON summary of whether the export would succeed instead of the export itself
+    #[serde(default)]
+    pub validate_only: bool,
+    /// With `format=json`, return the bare elements array instead of an `.excalidraw` file
+    #[serde(default)]
+    pub elements_only: bool,
+    /// Minify SVG output by dropping whitespace and default attributes and rounding coordinates
+    #[serde(default)]
+    pub optimize: bool,
+    /// Decimal places kept in coordinates, at most 8. Exports are only rounded when this is
+    /// given, except optimized SVGs, which keep 2 by default.
+    #[serde(default)]
+    pub precision: Option<u32>,
+}
+
+/// The region of canvas coordinates shown by an exported SVG.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ViewBox {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+/// Query for `GET /canvas/elements`; give all of `x`, `y`, `w`, `h` or none of them.
+#[derive(Debug, Deserialize)]
+pub struct RegionQuery {
+    pub x: Option<f64>,
+    pub y: Option<f64>,
+    pub w: Option<f64>,
+    pub h: Option<f64>,
+    /// Also return elements without numeric geometry when filtering by region
+    #[serde(default)]
+    pub include_unplaced: bool,
+}
+
+/// Body of `PUT /canvas/group/:id/translate`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TranslatePayload {
+    pub dx: f64,
+    pub dy: f64,
 }
 
+/// Body of `POST /canvas/transform`: every element is scaled about the origin, then moved.
 #[derive(Debug, Deserialize, Serialize)]
+pub struct TransformPayload {
+    #[serde(default)]
+    pub dx: f64,
+    #[serde(default)]
+    pub dy: f64,
+    /// Uniform factor for positions, sizes, points, font sizes and stroke widths
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+/// Optional body of `POST /canvas/element/:id/duplicate`: where to place the copy.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct DuplicatePayload {
+    #[serde(default)]
+    pub dx: f64,
+    #[serde(default)]
+    pub dy: f64,
+}
+
+/// Edits accepted over `GET /ws`, tagged by `type`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SocketMessage {
+    Draw {
+        #[serde(default = "default_scene")]
+        scene: String,
+        #[serde(flatten)]
+        payload: DrawPayload,
+    },
+    Update {
+        #[serde(default = "default_scene")]
+        scene: String,
+        id: String,
+        element: Value,
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Remove {
+        #[serde(default = "default_scene")]
+        scene: String,
+        id: String,
+    },
+}
+
+/// Query for `GET /canvas/search`.
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    pub q: String,
+    /// Element type to search, `text` unless given
+    #[serde(default = "default_search_type", rename = "type")]
+    pub element_type: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
 pub struct UpdateElementPayload {
     pub element: Value,
+    /// Expected `version` of the stored element; omit for last-write-wins
+    #[serde(default)]
+    pub version: Option<i64>,
 }
 
-fn default_format() -> String {
-    "svg".to_string()
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct AddElementPayload {
+    pub element: Value,
 }
 
-fn default_width() -> u32 {
-    800
+/// Body of `POST /canvas/element/:id/order`: one of `front`, `back`, `forward`, `backward`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OrderPayload {
+    pub action: String,
 }
 
-fn default_height() -> u32 {
-    600
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchAction {
+    Add,
+    Update,
+    Remove,
 }
 
-pub async fn start_http_server(app: tauri::AppHandle) -> anyhow::Result<()> {
-    let canvas = Arc::new(Mutex::new(CanvasData {
-        elements: None,
-        app_state: None,
-        files: None,
-        updated_at: chrono::Utc::now().to_rfc3339(),
-    }));
-    let state = AppState { app, canvas };
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchOperation {
+    pub action: BatchAction,
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub element: Option<Value>,
+}
 
-    let router = create_router(state);
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchPayload {
+    pub ops: Vec<BatchOperation>,
+}
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    let server_addr = listener.local_addr()?;
+fn default_scene() -> String {
+    DEFAULT_SCENE.to_string()
+}
 
-    info!(
-        target: "http_server",
-        action = "server_start",
-        address = %server_addr,
-        port = DEFAULT_PORT,
-        "HTTP服务器启动成功"
-    );
+fn default_search_type() -> String {
+    "text".to_string()
+}
 
-    axum::serve(listener, router).await?;
-    Ok(())
+// Export format for the most preferred media type in an Accept header, svg when none match
+fn format_from_accept(headers: &HeaderMap) -> String {
+    let Some(accept) = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return "svg".to_string();
+    };
+
+    let mut media_types: Vec<(&str, f32)> = accept
+        .split(',')
+        .map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or("");
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (media_type, quality)
+        })
+        .collect();
+    // Stable, so equally weighted types keep the client's order
+    media_types.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    media_types
+        .into_iter()
+        .find_map(|(media_type, _)| match media_type {
+            "image/svg+xml" => Some("svg"),
+            "application/json" => Some("json"),
+            "image/png" => Some("png"),
+            "image/jpeg" => Some("jpeg"),
+            "image/webp" => Some("webp"),
+            "image/avif" => Some("avif"),
+            _ => None,
+        })
+        .unwrap_or("svg")
+        .to_string()
 }
 
-pub fn create_router(state: AppState) -> Router {
-    Router::new()
-        .route("/health", get(health))
-        .route("/draw", post(draw_canvas))
-        .route("/canvas", get(get_canvas).put(update_canvas))
-        .route("/canvas/clear", post(clear_canvas))
-        .route("/canvas/export", get(export_canvas))
-        .route(
-            "/canvas/element/:id",
-            delete(remove_element).put(update_element),
-        )
-        .with_state(state)
-        .layer(CorsLayer::permissive())
+fn default_scale() -> f64 {
+    1.0
 }
 
-// Health check endpoint
-async fn health() -> &'static str {
-    "ok"
+fn default_padding() -> f64 {
+    DEFAULT_EXPORT_PADDING
 }
 
-// Draw to canvas and emit event
-async fn draw_canvas(
-    State(state): State<AppState>,
-    Json(payload): Json<DrawPayload>,
-) -> impl IntoResponse {
-    println!("🎨 收到绘制请求: {:?}", payload);
+fn default_width() -> u32 {
+    800
+}
 
-    // Update canvas data
-    {
-        let mut canvas = state.canvas.lock().unwrap();
-        if let Some(elements) = &payload.elements {
-            canvas.elements = Some(elements.clone());
-        }
-        if let Some(app_state) = &payload.app_state {
-            canvas.app_state = Some(app_state.clone());
-        }
-        if let Some(files) = &payload.files {
-            canvas.files = Some(files.clone());
+fn default_height() -> u32 {
+    600
+}
+
+/// A JSON request body whose rejections use the `ApiError` body instead of axum's plain text.
+#[derive(Debug, Default, FromRequest)]
+#[from_request(via(Json), rejection(ApiError))]
+pub struct JsonBody<T>(pub T);
+
+/// Errors returned by handlers, rendered as `{ "error": { "code", "message" } }`.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("Scene '{0}' not found")]
+    SceneNotFound(String),
+    #[error("Element with ID '{0}' not found")]
+    ElementNotFound(String),
+    #[error("No elements belong to group '{0}'")]
+    GroupNotFound(String),
+    #[error("Frame with ID '{0}' not found")]
+    FrameNotFound(String),
+    #[error("Element with ID '{0}' already exists")]
+    ElementExists(String),
+    #[error("Canvas is limited to {max} elements and holds {current}")]
+    TooManyElements { current: usize, max: usize },
+    #[error("Invalid elements: {}", .0.join("; "))]
+    InvalidElements(Vec<String>),
+    #[error("Not an Excalidraw file: {0}")]
+    InvalidImport(String),
+    #[error("Export scale must be a positive number, got {0}")]
+    InvalidScale(f64),
+    #[error("Export padding must be zero or more, got {0}")]
+    InvalidPadding(f64),
+    #[error("A {width}x{height} export exceeds {max} pixels; lower width, height or scale")]
+    ExportTooLarge { width: u32, height: u32, max: u64 },
+    #[error("The response could not be stored for the idempotency key")]
+    IdempotencyFailed,
+    #[error("A request with this Idempotency-Key is still in progress; retry once it completes")]
+    IdempotencyInProgress,
+    #[error("This Idempotency-Key was already used with a different request body")]
+    IdempotencyKeyReused,
+    #[error("Transform scale must be a positive number, got {0}")]
+    InvalidTransformScale(f64),
+    #[error("Background '{0}' is not 'transparent', a colour name or a hex colour")]
+    InvalidBackground(String),
+    #[error("{0}")]
+    InvalidJson(String),
+    #[error("{0}")]
+    InvalidBody(String),
+    #[error("Request body must be JSON sent with 'Content-Type: application/json'")]
+    UnsupportedMediaType,
+    #[error("Request body could not be read: {1}")]
+    BodyRejected(StatusCode, String),
+    #[error("Invalid socket message: {0}")]
+    InvalidMessage(String),
+    #[error("Unknown order action '{0}'. Supported actions: front, back, forward, backward")]
+    InvalidOrderAction(String),
+    #[error("Region queries need all of x, y, w and h")]
+    IncompleteRegion,
+    #[error("Nothing to undo in scene '{0}'")]
+    NothingToUndo(String),
+    #[error("Nothing to redo in scene '{0}'")]
+    NothingToRedo(String),
+    #[error("Element '{id}' is not at version {expected}")]
+    VersionConflict {
+        id: String,
+        expected: i64,
+        current: Value,
+    },
+    #[error("Search query 'q' must not be empty")]
+    EmptySearch,
+    #[error("Admin routes are disabled; set EXTAURI_API_KEY to enable them")]
+    AdminDisabled,
+    #[error("Admin endpoints only answer clients on this machine")]
+    AdminNotLocal,
+    #[error("Missing or invalid X-API-Key header")]
+    InvalidApiKey,
+    #[error("Method {method} is not allowed on {path}; see the Allow header")]
+    MethodNotAllowed { method: String, path: String },
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+    #[error("Unsupported format: {0}. Supported formats: {}", EXPORT_FORMATS.join(", "))]
+    UnsupportedFormat(String),
+    #[error("Cannot export {0} to a file. Supported formats: {}", FILE_EXPORT_FORMATS.join(", "))]
+    UnsupportedFileFormat(String),
+    #[error("Failed to render {0}")]
+    RenderFailed(String),
+    #[error("Patch operation {operation} failed: {reason}")]
+    PatchFailed { operation: usize, reason: String },
+    #[error("Batch operation {index} failed: {reason}")]
+    BatchFailed { index: usize, reason: String },
+    #[error("Failed to emit {0} event")]
+    EmitFailed(String),
+}
+
+impl ApiError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::SceneNotFound(_)
+            | ApiError::ElementNotFound(_)
+            | ApiError::GroupNotFound(_)
+            | ApiError::FrameNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::ElementExists(_)
+            | ApiError::BatchFailed { .. }
+            | ApiError::NothingToUndo(_)
+            | ApiError::NothingToRedo(_)
+            | ApiError::VersionConflict { .. }
+            | ApiError::IdempotencyInProgress => StatusCode::CONFLICT,
+            ApiError::InvalidElements(_)
+            | ApiError::InvalidImport(_)
+            | ApiError::InvalidMessage(_)
+            | ApiError::InvalidJson(_)
+            | ApiError::InvalidScale(_)
+            | ApiError::InvalidPadding(_)
+            | ApiError::ExportTooLarge { .. }
+            | ApiError::InvalidTransformScale(_)
+            | ApiError::InvalidBackground(_)
+            | ApiError::InvalidOrderAction(_)
+            | ApiError::IncompleteRegion
+            | ApiError::EmptySearch
+            | ApiError::UnsupportedFormat(_)
+            | ApiError::UnsupportedFileFormat(_)
+            | ApiError::PatchFailed { .. } => StatusCode::BAD_REQUEST,
+            ApiError::InvalidBody(_)
+            | ApiError::TooManyElements { .. }
+            | ApiError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::AdminDisabled | ApiError::AdminNotLocal => StatusCode::FORBIDDEN,
+            ApiError::InvalidApiKey => StatusCode::UNAUTHORIZED,
+            ApiError::BodyRejected(status, _) => *status,
+            ApiError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::RenderFailed(_) | ApiError::EmitFailed(_) | ApiError::IdempotencyFailed => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
         }
-        canvas.updated_at = chrono::Utc::now().to_rfc3339();
     }
 
-    // Emit draw event to frontend
-    if let Err(err) = state.app.emit(EVENT_DRAW, &payload) {
-        eprintln!("❌ 发送事件失败: {err:?}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": "Failed to emit draw event"})),
-        );
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::SceneNotFound(_) => "scene_not_found",
+            ApiError::ElementNotFound(_) => "element_not_found",
+            ApiError::GroupNotFound(_) => "group_not_found",
+            ApiError::FrameNotFound(_) => "frame_not_found",
+            ApiError::ElementExists(_) => "element_exists",
+            ApiError::TooManyElements { .. } => "too_many_elements",
+            ApiError::InvalidElements(_) => "invalid_elements",
+            ApiError::InvalidImport(_) => "invalid_import",
+            ApiError::InvalidMessage(_) => "invalid_message",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::InvalidBody(_) => "invalid_body",
+            ApiError::UnsupportedMediaType => "unsupported_media_type",
+            ApiError::BodyRejected(..) => "body_rejected",
+            ApiError::InvalidScale(_) | ApiError::InvalidTransformScale(_) => "invalid_scale",
+            ApiError::InvalidPadding(_) => "invalid_padding",
+            ApiError::ExportTooLarge { .. } => "export_too_large",
+            ApiError::InvalidBackground(_) => "invalid_background",
+            ApiError::InvalidOrderAction(_) => "invalid_order_action",
+            ApiError::IncompleteRegion => "incomplete_region",
+            ApiError::EmptySearch => "empty_search",
+            ApiError::AdminDisabled => "admin_disabled",
+            ApiError::AdminNotLocal => "admin_not_local",
+            ApiError::InvalidApiKey => "invalid_api_key",
+            ApiError::MethodNotAllowed { .. } => "method_not_allowed",
+            ApiError::RateLimited(_) => "rate_limited",
+            ApiError::NothingToUndo(_) => "nothing_to_undo",
+            ApiError::NothingToRedo(_) => "nothing_to_redo",
+            ApiError::VersionConflict { .. } => "version_conflict",
+            ApiError::UnsupportedFormat(_) | ApiError::UnsupportedFileFormat(_) => {
+                "unsupported_format"
+            }
+            ApiError::RenderFailed(_) => "render_failed",
+            ApiError::PatchFailed { .. } => "patch_failed",
+            ApiError::BatchFailed { .. } => "batch_failed",
+            ApiError::EmitFailed(_) => "emit_failed",
+            ApiError::IdempotencyFailed => "idempotency_failed",
+            ApiError::IdempotencyInProgress => "idempotency_in_progress",
+            ApiError::IdempotencyKeyReused => "idempotency_key_reused",
+        }
     }
-
-    println!("✅ 已发送绘制事件到前端");
-    (StatusCode::OK, Json(json!({"success": true})))
 }
 
-// Get current canvas data
-async fn get_canvas(State(state): State<AppState>) -> impl IntoResponse {
-    let canvas = state.canvas.lock().unwrap();
-    (StatusCode::OK, Json(json!({"canvas": canvas.clone()})))
+impl ApiError {
+    /// The `{ "error": ... }` body shared by HTTP responses and socket error frames.
+    fn body(&self) -> Value {
+        let mut error = json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        });
+        match &self {
+            ApiError::InvalidElements(problems) => error["details"] = json!(problems),
+            ApiError::PatchFailed { operation, .. } => error["operation"] = json!(operation),
+            ApiError::BatchFailed { index, .. } => error["index"] = json!(index),
+            ApiError::TooManyElements { current, max } => {
+                error["current"] = json!(current);
+                error["max"] = json!(max);
+            }
+            ApiError::ExportTooLarge { width, height, max } => {
+                error["width"] = json!(width);
+                error["height"] = json!(height);
+                error["max"] = json!(max);
+            }
+            // Hand back the stored element so the client can rebase its change
+            ApiError::VersionConflict { current, .. } => error["current"] = current.clone(),
+            _ => {}
+        }
+        json!({ "error": error })
+    }
 }
 
-// Update canvas data
-async fn update_canvas(
-    State(state): State<AppState>,
-    Json(payload): Json<DrawPayload>,
-) -> impl IntoResponse {
-    let payload_json =
-        serde_json::to_string(&payload).unwrap_or_else(|_| "无法序列化数据".to_string());
-    info!(
-        target: "canvas_update",
-        action = "update_canvas_start",
-        canvas_data = %payload_json,
-        "接收到画布更新数据"
-    );
-
-    let updated_at = chrono::Utc::now().to_rfc3339();
-    {
-        let mut canvas = state.canvas.lock().unwrap();
-        if let Some(elements) = &payload.elements {
-            canvas.elements = Some(elements.clone());
-        }
-        if let Some(app_state) = &payload.app_state {
-            canvas.app_state = Some(app_state.clone());
+impl From<JsonRejection> for ApiError {
+    // body_text carries serde's message, which points at the line and column that failed
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonSyntaxError(err) => ApiError::InvalidJson(err.body_text()),
+            JsonRejection::JsonDataError(err) => ApiError::InvalidBody(err.body_text()),
+            JsonRejection::MissingJsonContentType(_) => ApiError::UnsupportedMediaType,
+            other => ApiError::BodyRejected(other.status(), other.body_text()),
         }
-        if let Some(files) = &payload.files {
-            canvas.files = Some(files.clone());
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), Json(self.body())).into_response();
+        if let ApiError::RateLimited(retry_after) = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
         }
-        canvas.updated_at = updated_at.clone();
+        response
     }
+}
 
-    // Emit draw event to frontend
-    if let Err(err) = state.app.emit(EVENT_DRAW, &payload) {
+// Emit a scene-tagged event to the frontend, logging failures once for every handler.
+// With draw debouncing on, draw events are only queued and failures go to the log alone.
+async fn emit_event<S: Serialize>(
+    state: &AppState,
+    scene: &str,
+    event: &str,
+    payload: S,
+) -> Result<(), ApiError> {
+    if let (EVENT_DRAW, Some(pending)) = (event, &state.pending_draws) {
+        pending
+            .scenes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(scene.to_string());
+        pending.notify.notify_one();
+        return Ok(());
+    }
+    send_event(state, scene, event, payload).await
+}
+
+async fn send_event<S: Serialize>(
+    state: &AppState,
+    scene: &str,
+    event: &str,
+    payload: S,
+) -> Result<(), ApiError> {
+    let payload = SceneEvent {
+        scene: scene.to_string(),
+        payload,
+    };
+    let event = state.event_name(event);
+    // Numbered only once it serializes, so a skipped number always means a missed event
+    let result = match serde_json::to_value(payload) {
+        Ok(mut payload) => {
+            if let Value::Object(fields) = &mut payload {
+                let seq = state.seq.fetch_add(1, Ordering::SeqCst) + 1;
+                fields.insert("seq".to_string(), json!(seq));
+            }
+            emit_with_retry(state, &event, scene, &payload).await
+        }
+        Err(err) => Err(err.into()),
+    };
+    // The canvas is already updated, so a later GET still shows the change
+    result.map_err(|err| {
+        state.metrics.record_emit_failure();
         error!(
-            target: "canvas_update",
+            target: "canvas_event",
             action = "emit_event_failed",
+            event = %event,
+            scene = scene,
             error = %err,
-            "发送更新事件到前端失败"
+            "发送事件到前端失败"
         );
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": "Failed to emit draw event"})),
-        );
-    }
+        ApiError::EmitFailed(event.into_owned())
+    })
+}
 
-    let final_canvas_data = {
-        let canvas = state.canvas.lock().unwrap();
-        serde_json::to_string(&*canvas).unwrap_or_else(|_| "无法序列化画布数据".to_string())
-    };
-    info!(
-        target: "canvas_update",
-        action = "update_canvas_success",
-        updated_at = %updated_at,
-        final_canvas_data = %final_canvas_data,
-        "画布数据已成功更新并发送到前端"
-    );
-    (StatusCode::OK, Json(json!({"success": true})))
+// Emits fail briefly while the webview reloads, so retry a few times before giving up
+async fn emit_with_retry(
+    state: &AppState,
+    event: &str,
+    scene: &str,
+    payload: &Value,
+) -> anyhow::Result<()> {
+    let mut backoff = EMIT_RETRY_BACKOFF;
+    for attempt in 1..EMIT_ATTEMPTS {
+        match state.sink.emit(event, payload) {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                warn!(
+                    target: "canvas_event",
+                    action = "emit_event_retry",
+                    event = event,
+                    scene = scene,
+                    attempt = attempt,
+                    error = %err,
+                    "发送事件到前端失败，稍后重试"
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+    }
+    state.sink.emit(event, payload)
 }
 
-// Clear canvas
-async fn clear_canvas(State(state): State<AppState>) -> impl IntoResponse {
-    info!(
-        target: "canvas_clear",
-        action = "clear_canvas_start",
-        "开始清除画布"
-    );
+impl AppState {
+    /// Create the shared canvas state; clones share scenes, history and subscribers.
+    pub fn new(sink: Arc<dyn EventSink>, config: ServerConfig) -> Self {
+        // Seed the default scene so the unprefixed routes behave as before
+        let scenes = Arc::new(RwLock::new(HashMap::from([(
+            DEFAULT_SCENE.to_string(),
+            CanvasData::empty(),
+        )])));
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let limiter =
+            (config.rate_limit > 0).then(|| Arc::new(RateLimiter::new(config.rate_limit)));
+        let draw_debounce = config.draw_debounce;
+        let idempotency = config
+            .idempotency_ttl
+            .map(|ttl| Arc::new(IdempotencyCache::new(ttl)));
+        let state = AppState {
+            sink,
+            seq: Arc::default(),
+            status: Arc::default(),
+            config: Arc::new(config),
+            scenes,
+            history: Arc::default(),
+            events,
+            started_at: Instant::now(),
+            shutdown: None,
+            limiter,
+            metrics: Arc::default(),
+            pending_draws: draw_debounce.map(|_| Arc::default()),
+            idempotency,
+        };
+        // Draws are shown even while the HTTP server is stopped, so this lives as long as the app
+        if let (Some(interval), Some(pending)) = (draw_debounce, state.pending_draws.clone()) {
+            tauri::async_runtime::spawn(flush_draws(state.clone(), pending, interval));
+        }
+        state
+    }
 
-    let clear_payload = DrawPayload {
-        elements: Some(json!([])),
-        app_state: None,
-        files: None,
-    };
+    /// Whether the HTTP server is listening, and why it isn't if it failed.
+    pub fn server_info(&self) -> ServerInfo {
+        self.status.info()
+    }
 
-    let updated_at = chrono::Utc::now().to_rfc3339();
-    {
-        let mut canvas = state.canvas.lock().unwrap();
-        canvas.elements = Some(json!([]));
-        canvas.app_state = None;
-        canvas.files = None;
-        canvas.updated_at = updated_at.clone();
+    /// The name `event` is emitted under, with the configured prefix.
+    fn event_name<'a>(&self, event: &'a str) -> Cow<'a, str> {
+        match &self.config.event_prefix {
+            Some(prefix) => Cow::Owned(format!("{}:{}", prefix, event)),
+            None => Cow::Borrowed(event),
+        }
     }
+}
 
-    // Emit clear event to frontend
-    if let Err(err) = state.app.emit(EVENT_DRAW, &clear_payload) {
-        error!(
-            target: "canvas_clear",
-            action = "emit_clear_event_failed",
-            error = %err,
-            "发送清除事件到前端失败"
-        );
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": "Failed to emit clear event"})),
+/// Serve the HTTP API until `shutdown` is cancelled, then drain in-flight requests.
+/// Canvases live in `state`, so they survive the server being stopped and restarted.
+/// `ready` learns whether the server came up, once it answers `/health`; failures are also recorded for `server_info`
+/// and emitted as `server_failed`.
+pub async fn start_http_server(
+    state: AppState,
+    shutdown: CancellationToken,
+    ready: oneshot::Sender<ServerReady>,
+) -> Result<(), ServerError> {
+    let (sink, status) = (state.sink.clone(), state.status.clone());
+    let failed_event = state.event_name(EVENT_SERVER_FAILED).into_owned();
+    let result = run_http_server(state, shutdown, ready).await;
+    if let Err(err) = &result {
+        let failure = err.failure();
+        status.set(ServerInfo {
+            error: Some(failure.clone()),
+            ..ServerInfo::default()
+        });
+        if let Err(emit_err) = sink.emit(&failed_event, &json!(failure)) {
+            error!(
+                target: "http_server",
+                action = "emit_event_failed",
+                event = %failed_event,
+                error = %emit_err,
+                "发送事件到前端失败"
+            );
+        }
+    }
+    result
+}
+
+async fn run_http_server(
+    state: AppState,
+    shutdown: CancellationToken,
+    ready: oneshot::Sender<ServerReady>,
+) -> Result<(), ServerError> {
+    let addr = SocketAddr::new(state.config.bind_address, DEFAULT_PORT);
+    if !addr.ip().is_loopback() {
+        warn!(
+            target: "http_server",
+            action = "non_loopback_bind",
+            address = %addr,
+            "⚠️ HTTP服务器监听非本机地址，同一网络中的任何设备都可以读取和修改画布"
         );
     }
 
-    let clear_payload_json =
-        serde_json::to_string(&clear_payload).unwrap_or_else(|_| "无法序列化清除数据".to_string());
-    let final_canvas_data = {
-        let canvas = state.canvas.lock().unwrap();
-        serde_json::to_string(&*canvas).unwrap_or_else(|_| "无法序列化画布数据".to_string())
+    let bound = tokio::net::TcpListener::bind(addr)
+        .await
+        .and_then(|listener| Ok((listener.local_addr()?, listener)));
+    let (server_addr, listener) = match bound {
+        Ok(bound) => bound,
+        Err(source) => {
+            let err = ServerError::Bind {
+                address: addr,
+                source,
+            };
+            // Nobody may be waiting for readiness, which is fine
+            let _ = ready.send(Err(err.to_string()));
+            return Err(err);
+        }
     };
-    info!(
-        target: "canvas_clear",
-        action = "clear_canvas_success",
-        updated_at = %updated_at,
-        clear_data = %clear_payload_json,
-        final_canvas_data = %final_canvas_data,
-        "画布已成功清除"
-    );
-    (StatusCode::OK, Json(json!({"success": true})))
+    serve_http(state, listener, server_addr, shutdown, ready).await
 }
 
-// Export canvas as SVG or other formats
-async fn export_canvas(
-    State(state): State<AppState>,
-    Query(params): Query<ExportQuery>,
-) -> impl IntoResponse {
-    println!(
-        "📤 导出画布: format={}, width={}, height={}",
-        params.format, params.width, params.height
-    );
+// Serve on a bound listener until `shutdown`; split from binding so tests can use any port
+async fn serve_http(
+    mut state: AppState,
+    listener: tokio::net::TcpListener,
+    server_addr: SocketAddr,
+    shutdown: CancellationToken,
+    ready: oneshot::Sender<ServerReady>,
+) -> Result<(), ServerError> {
+    let status = state.status.clone();
+    state.started_at = Instant::now();
+    state.shutdown = Some(shutdown.clone());
+    // Runs until the server shuts down; a restart starts a fresh one
+    if let Some(ttl) = state.config.idle_clear {
+        tokio::spawn(clear_idle_scenes(state.clone(), ttl, shutdown.clone()));
+    }
+    let router = create_router(state);
 
-    let canvas = state.canvas.lock().unwrap();
-    let default_elements = json!([]);
-    let elements = canvas.elements.as_ref().unwrap_or(&default_elements);
+    // Connect info gives the rate limiter each client's address
+    let serve = axum::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.cancelled_owned())
+    .into_future();
+    tokio::pin!(serve);
 
-    match params.format.as_str() {
-        "svg" => {
-            let svg_content = generate_svg(elements, params.width, params.height);
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "image/svg+xml")
-                .header(
-                    header::CONTENT_DISPOSITION,
-                    "inline; filename=\"canvas.svg\"",
-                )
-                .body(svg_content)
-                .unwrap()
-        }
-        "json" => {
-            let export_data = json!({
-                "elements": elements,
-                "appState": canvas.app_state,
-                "files": canvas.files,
-                "exported_at": chrono::Utc::now().to_rfc3339(),
-                "format": "excalidraw"
-            });
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .header(
-                    header::CONTENT_DISPOSITION,
-                    "attachment; filename=\"canvas.excalidraw\"",
-                )
-                .body(export_data.to_string())
-                .unwrap()
-        }
-        "toDataURL" => {
-            // Generate SVG first, then convert to base64 data URL
-            let svg_content = generate_svg(elements, params.width, params.height);
-            let base64_svg = general_purpose::STANDARD.encode(svg_content.as_bytes());
-            let data_url = format!("data:image/svg+xml;base64,{}", base64_svg);
+    // Only report the server as started once it has answered a request, so clients acting
+    // on readiness never race the serve loop. If it stops first, `ready` is dropped unsent.
+    let result = tokio::select! {
+        result = &mut serve => result,
+        () = wait_until_serving(server_addr) => {
+            info!(
+                target: "http_server",
+                action = "server_start",
+                address = %server_addr,
+                port = DEFAULT_PORT,
+                "HTTP服务器启动成功"
+            );
 
-            let response_data = json!({
-                "dataURL": data_url,
-                "width": params.width,
-                "height": params.height,
-                "format": "svg",
-                "exported_at": chrono::Utc::now().to_rfc3339()
+            status.set(ServerInfo {
+                running: true,
+                port: server_addr.port(),
+                address: format!("http://{}", server_addr),
+                error: None,
             });
+            let _ = ready.send(Ok(server_addr.port()));
 
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(response_data.to_string())
-                .unwrap()
-        }
-        "png" | "jpeg" | "webp" => {
-            // For now, return a placeholder response for raster formats
-            // In a real implementation, you would use a library like resvg or headless browser
-            let placeholder = format!(
-                "{{\"error\": \"Format '{}' not yet implemented. Use 'svg' or 'json' instead.\"}}",
-                params.format
-            );
-            Response::builder()
-                .status(StatusCode::NOT_IMPLEMENTED)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(placeholder)
-                .unwrap()
-        }
-        _ => {
-            let error = json!({"error": format!("Unsupported format: {}. Supported formats: svg, json, toDataURL, png, jpeg, webp", params.format)});
-            Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(error.to_string())
-                .unwrap()
+            serve.await
         }
-    }
-}
+    };
 
-fn generate_svg(elements: &Value, width: u32, height: u32) -> String {
-    let mut svg_elements = Vec::new();
+    status.set(ServerInfo::default());
+    result.map_err(ServerError::Serve)?;
 
-    if let Some(elements_array) = elements.as_array() {
-        for element in elements_array {
-            if let Some(svg_element) = convert_element_to_svg(element) {
-                svg_elements.push(svg_element);
+    info!(
+        target: "http_server",
+        action = "server_stop",
+        address = %server_addr,
+        "HTTP服务器已停止"
+    );
+    Ok(())
+}
+
+// Probe `/health` on the freshly bound address until the serve loop answers it
+async fn wait_until_serving(addr: SocketAddr) {
+    // A wildcard bind is reachable through loopback of the same family
+    let target = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), addr.port())
+        }
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            SocketAddr::new(Ipv6Addr::LOCALHOST.into(), addr.port())
+        }
+        _ => addr,
+    };
+    loop {
+        match tokio::time::timeout(READINESS_PROBE_TIMEOUT, probe_health(target)).await {
+            Ok(Ok(true)) => return,
+            Ok(Ok(false)) | Ok(Err(_)) | Err(_) => {
+                debug!(
+                    target: "http_server",
+                    action = "readiness_probe_retry",
+                    address = %target,
+                    "HTTP服务器尚未就绪，稍后重试"
+                );
+                tokio::time::sleep(READINESS_PROBE_INTERVAL).await;
             }
         }
     }
+}
 
-    format!(
-        r#"<?xml version="1.0" encoding="UTF-8"?>
-<svg width="{}" height="{}" viewBox="0 0 {} {}" xmlns="http://www.w3.org/2000/svg">
-  <rect width="100%" height="100%" fill="white"/>
-  {}
-</svg>"#,
-        width,
-        height,
-        width,
-        height,
-        svg_elements.join("\n  ")
-    )
+// Whether one `GET /health` over a fresh connection gets a 200
+async fn probe_health(addr: SocketAddr) -> std::io::Result<bool> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let request = format!(
+        "GET /health HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        addr
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    Ok(response.starts_with(b"HTTP/1.1 200"))
 }
 
-fn convert_element_to_svg(element: &Value) -> Option<String> {
-    let element_type = element.get("type")?.as_str()?;
-    let x = element.get("x")?.as_f64().unwrap_or(0.0);
-    let y = element.get("y")?.as_f64().unwrap_or(0.0);
-    let width = element.get("width")?.as_f64().unwrap_or(0.0);
-    let height = element.get("height")?.as_f64().unwrap_or(0.0);
-    let stroke_color = element.get("strokeColor")?.as_str().unwrap_or("#000000");
-    let background_color = element
-        .get("backgroundColor")?
-        .as_str()
-        .unwrap_or("transparent");
-    let stroke_width = element.get("strokeWidth")?.as_f64().unwrap_or(1.0);
+/// A router over an empty default scene that sends events to `sink`, for driving the API
+/// in-process (e.g. with `tower::ServiceExt::oneshot`) without a socket or Tauri app.
+/// Rate limiting is off, since every in-process request counts as the same client.
+#[cfg(feature = "testing")]
+pub fn test_router(sink: Arc<dyn EventSink>) -> Router {
+    let config = ServerConfig {
+        rate_limit: 0,
+        ..ServerConfig::default()
+    };
+    create_router(AppState::new(sink, config))
+}
 
-    match element_type {
-        "rectangle" => Some(format!(
-            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" stroke="{}" stroke-width="{}"/>"#,
-            x, y, width, height, background_color, stroke_color, stroke_width
-        )),
-        "ellipse" => {
-            let cx = x + width / 2.0;
-            let cy = y + height / 2.0;
-            let rx = width / 2.0;
-            let ry = height / 2.0;
-            Some(format!(
-                r#"<ellipse cx="{}" cy="{}" rx="{}" ry="{}" fill="{}" stroke="{}" stroke-width="{}"/>"#,
-                cx, cy, rx, ry, background_color, stroke_color, stroke_width
-            ))
-        }
-        "arrow" | "line" => {
-            let x2 = x + width;
-            let y2 = y + height;
-            Some(format!(
-                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}"/>"#,
-                x, y, x2, y2, stroke_color, stroke_width
-            ))
-        }
-        "text" => {
-            let text_content = element
-                .get("text")
-                .and_then(|v| v.as_str())
-                .unwrap_or("[text]");
-            let font_size = element
-                .get("fontSize")
-                .and_then(|v| v.as_f64())
-                .unwrap_or(16.0);
-            let text_align = element
-                .get("textAlign")
-                .and_then(|v| v.as_str())
-                .unwrap_or("left");
-            let font_family = element
-                .get("fontFamily")
-                .and_then(|v| v.as_i64())
-                .unwrap_or(1);
-
-            let font_family_name = match font_family {
-                1 => "Virgil",
-                2 => "Helvetica",
-                3 => "Cascadia",
-                _ => "Virgil",
-            };
+pub fn create_router(state: AppState) -> Router {
+    let cors = cors_layer(&state.config.allowed_origins);
+    let compression = state.config.compression;
 
-            let anchor = match text_align {
-                "center" => "middle",
-                "right" => "end",
-                _ => "start",
-            };
+    let router = Router::new()
+        .route("/health", get(health))
+        .route("/health/detailed", get(health_detailed))
+        .route("/openapi.json", get(openapi_spec))
+        .route("/metrics", get(metrics))
+        .route("/events", get(canvas_events))
+        .route("/ws", get(canvas_socket))
+        .route("/admin/shutdown", post(admin_shutdown))
+        .merge(canvas_routes(state.config.max_body_bytes))
+        .nest("/scene/:scene", canvas_routes(state.config.max_body_bytes))
+        // Must come after every route, as it only applies to routes already added
+        .method_not_allowed_fallback(method_not_allowed)
+        .layer(middleware::from_fn_with_state(state.clone(), idempotency))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+        .layer(middleware::from_fn_with_state(state.clone(), count_request))
+        .with_state(state)
+        .layer(cors)
+        .layer(middleware::from_fn(log_request));
 
-            Some(format!(
-                r#"<text x="{}" y="{}" font-size="{}" font-family="{}" text-anchor="{}" fill="{}" dominant-baseline="hanging">{}</text>"#,
-                x,
-                y,
-                font_size,
-                font_family_name,
-                anchor,
-                stroke_color,
-                text_content
-                    .replace('&', "&amp;")
-                    .replace('<', "&lt;")
-                    .replace('>', "&gt;")
-                    .replace('"', "&quot;")
-                    .replace('\'', "&#39;")
-            ))
-        }
-        _ => {
-            // For unsupported elements, create a placeholder rectangle
-            Some(format!(
-                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-width="{}" stroke-dasharray="5,5"/>"#,
-                x, y, width, height, stroke_color, stroke_width
-            ))
-        }
+    // The default predicate already skips SSE streams and already-compressed raster images
+    if compression {
+        router.layer(CompressionLayer::new())
+    } else {
+        router
     }
 }
 
-// Remove element by ID
-async fn remove_element(
-    State(state): State<AppState>,
-    Path(element_id): Path<String>,
-) -> impl IntoResponse {
-    println!("🗑️ 移除元素: {}", element_id);
-
-    let mut updated_elements = Vec::new();
-    let mut element_found = false;
+// Routes operating on a single scene, mounted both unprefixed and under /scene/:scene.
+// Oversized bodies are rejected with 413; only /draw and /canvas may carry image files,
+// and the NDJSON stream shares their larger limit since it carries elements in bulk.
+fn canvas_routes(max_body_bytes: usize) -> Router<AppState> {
+    let file_limit = DefaultBodyLimit::max(max_body_bytes);
 
-    {
-        let canvas = state.canvas.lock().unwrap();
-        if let Some(elements) = &canvas.elements {
-            if let Some(elements_array) = elements.as_array() {
-                for element in elements_array {
-                    if let Some(id) = element.get("id").and_then(|v| v.as_str()) {
-                        if id != element_id {
-                            updated_elements.push(element.clone());
-                        } else {
-                            element_found = true;
-                        }
-                    } else {
-                        updated_elements.push(element.clone());
-                    }
-                }
-            }
-        }
-    }
+    Router::new()
+        .route("/draw", post(draw_canvas).layer(file_limit))
+        .route(
+            "/canvas",
+            get(get_canvas)
+                .put(update_canvas)
+                .patch(patch_canvas)
+                .delete(reset_canvas)
+                .layer(file_limit),
+        )
+        .route("/canvas/replace", post(replace_canvas).layer(file_limit))
+        .route("/canvas/stats", get(canvas_stats))
+        .route("/canvas/clear", post(clear_canvas))
+        .route("/canvas/undo", post(undo_canvas))
+        .route("/canvas/redo", post(redo_canvas))
+        .route("/canvas/history", get(canvas_history))
+        .route("/canvas/import", post(import_canvas).layer(file_limit))
+        .route("/canvas/export", get(export_canvas))
+        .route("/canvas/frame/:id/export", get(export_frame))
+        .route("/canvas/batch", post(batch_elements))
+        .route("/canvas/elements", get(query_elements))
+        .route("/canvas/elements/ids", get(element_ids))
+        .route(
+            "/canvas/elements/stream",
+            post(stream_elements).layer(file_limit),
+        )
+        .route("/canvas/search", get(search_elements))
+        .route("/canvas/element", post(add_element))
+        .route(
+            "/canvas/element/:id",
+            get(get_element)
+                .delete(remove_element)
+                .put(update_element)
+                .patch(patch_element),
+        )
+        .route("/canvas/element/:id/order", post(reorder_element))
+        .route("/canvas/element/:id/duplicate", post(duplicate_element))
+        .route("/canvas/group/:id", delete(remove_group))
+        .route("/canvas/group/:id/translate", put(translate_group))
+        .route("/canvas/transform", post(transform_canvas))
+        .layer(DefaultBodyLimit::max(
+            ELEMENT_BODY_LIMIT.min(max_body_bytes),
+        ))
+}
 
-    if !element_found {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": format!("Element with ID '{}' not found", element_id)})),
-        );
+// Known path, wrong method. axum still adds the Allow header listing the route's methods.
+async fn method_not_allowed(method: Method, uri: Uri) -> ApiError {
+    ApiError::MethodNotAllowed {
+        method: method.to_string(),
+        path: uri.path().to_string(),
     }
+}
 
-    let draw_payload = DrawPayload {
-        elements: Some(json!(updated_elements)),
-        app_state: None,
-        files: None,
+// Throttle mutating requests per client IP; reads are cheap and stay unlimited
+async fn rate_limit(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(limiter) = &state.limiter else {
+        return next.run(request).await;
     };
-
-    // Update canvas data
-    {
-        let mut canvas = state.canvas.lock().unwrap();
-        canvas.elements = Some(json!(updated_elements));
-        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
     }
 
-    // Emit update event to frontend
-    if let Err(err) = state.app.emit(EVENT_DRAW, &draw_payload) {
-        eprintln!("❌ 发送移除事件失败: {err:?}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": "Failed to emit remove event"})),
+    let client = client_ip(connect_info);
+    if let Err(retry_after) = limiter.check(client) {
+        warn!(
+            target: "http_server",
+            action = "rate_limited",
+            client = %client,
+            method = %request.method(),
+            path = %request.uri().path(),
+            "请求过于频繁，已限流"
         );
+        return ApiError::RateLimited(retry_after_secs(retry_after)).into_response();
     }
+    next.run(request).await
+}
 
-    println!("✅ 元素已移除: {}", element_id);
-    (
-        StatusCode::OK,
-        Json(json!({"success": true, "message": format!("Element '{}' removed", element_id)})),
-    )
+// The client a request or socket came from; requests without connect info can only come
+// from in-process callers
+fn client_ip(connect_info: Option<ConnectInfo<SocketAddr>>) -> IpAddr {
+    connect_info.map_or(IpAddr::V4(Ipv4Addr::LOCALHOST), |ConnectInfo(addr)| {
+        addr.ip()
+    })
 }
 
-// Update element by ID
-async fn update_element(
-    State(state): State<AppState>,
-    Path(element_id): Path<String>,
-    Json(payload): Json<UpdateElementPayload>,
-) -> impl IntoResponse {
-    println!("🔄 更新元素: {} -> {:?}", element_id, payload.element);
+// Whole seconds for Retry-After, never 0 so clients don't retry at once
+fn retry_after_secs(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
 
-    let mut updated_elements = Vec::new();
-    let mut element_found = false;
+// Count requests per route template for /metrics, leaving out the scrapes themselves
+async fn count_request(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let response = next.run(request).await;
 
-    {
-        let canvas = state.canvas.lock().unwrap();
-        if let Some(elements) = &canvas.elements {
-            if let Some(elements_array) = elements.as_array() {
-                for element in elements_array {
-                    if let Some(id) = element.get("id").and_then(|v| v.as_str()) {
-                        if id == element_id {
-                            updated_elements.push(payload.element.clone());
-                            element_found = true;
-                        } else {
-                            updated_elements.push(element.clone());
-                        }
-                    } else {
-                        updated_elements.push(element.clone());
-                    }
-                }
-            }
-        }
+    if let Some(route) = route.filter(|route| route != "/metrics") {
+        state
+            .metrics
+            .record_request(method.as_str(), &route, response.status().as_u16());
     }
+    response
+}
 
-    if !element_found {
-        return (
-            StatusCode::NOT_FOUND,
-            Json(json!({"error": format!("Element with ID '{}' not found", element_id)})),
+// Log every request with its status and latency; health probes only at debug level
+async fn log_request(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let started = Instant::now();
+    // Handler spans nest under this one, so their logs carry the request too
+    let span =
+        info_span!(target: "http_request", "http_request", method = %method, path = %uri.path());
+    let response = next.run(request).instrument(span).await;
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    let status = response.status().as_u16();
+
+    if uri.path().starts_with("/health") {
+        debug!(
+            target: "http_request",
+            method = %method,
+            uri = %uri,
+            status = status,
+            latency_ms = latency_ms,
+            "HTTP请求"
+        );
+    } else {
+        info!(
+            target: "http_request",
+            method = %method,
+            uri = %uri,
+            status = status,
+            latency_ms = latency_ms,
+            "HTTP请求"
         );
     }
+    response
+}
 
-    let draw_payload = DrawPayload {
-        elements: Some(json!(updated_elements)),
-        app_state: None,
-        files: None,
+// Replay the first successful response when a POST or PUT is retried with the same
+// Idempotency-Key. Failures aren't stored, so a failed attempt can simply be retried.
+// The key is reserved while the first request runs, and only replays for the same body.
+async fn idempotency(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let Some(cache) = &state.idempotency else {
+        return next.run(request).await;
     };
+    if !matches!(*request.method(), Method::POST | Method::PUT) {
+        return next.run(request).await;
+    }
+    let Some(key) = request
+        .headers()
+        .get("idempotency-key")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return next.run(request).await;
+    };
+    // Scope keys to the route so a reused key can't replay another endpoint's response
+    let key = format!("{} {} {}", request.method(), request.uri().path(), key);
 
-    // Update canvas data
-    {
-        let mut canvas = state.canvas.lock().unwrap();
-        canvas.elements = Some(json!(updated_elements));
-        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+    // Bodies past the largest route limit would be rejected by the handler anyway
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, state.config.max_body_bytes).await {
+        Ok(body) => body,
+        Err(err) => {
+            return ApiError::BodyRejected(StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+                .into_response()
+        }
+    };
+
+    let reservation = match cache.claim(&key, fingerprint(&body)) {
+        Claim::Reserved(reservation) => reservation,
+        Claim::Replay(stored) => {
+            info!(
+                target: "http_server",
+                action = "idempotent_replay",
+                key = %key,
+                "重复的幂等键，返回之前的响应"
+            );
+            let mut response = (stored.status, stored.headers, stored.body).into_response();
+            response.headers_mut().insert(
+                HeaderName::from_static(IDEMPOTENT_REPLAYED),
+                HeaderValue::from_static("true"),
+            );
+            return response;
+        }
+        Claim::InFlight => return ApiError::IdempotencyInProgress.into_response(),
+        Claim::Mismatch => {
+            warn!(
+                target: "http_server",
+                action = "idempotency_key_reused",
+                key = %key,
+                "幂等键已用于不同的请求体"
+            );
+            return ApiError::IdempotencyKeyReused.into_response();
+        }
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !response.status().is_success() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => {
+            reservation.complete(StoredResponse {
+                status: parts.status,
+                headers: parts.headers.clone(),
+                body: body.clone(),
+            });
+            Response::from_parts(parts, Body::from(body))
+        }
+        Err(err) => {
+            error!(
+                target: "http_server",
+                action = "idempotent_buffer_failed",
+                error = %err,
+                "无法缓存幂等响应"
+            );
+            ApiError::IdempotencyFailed.into_response()
+        }
     }
+}
 
-    // Emit update event to frontend
-    if let Err(err) = state.app.emit(EVENT_DRAW, &draw_payload) {
-        eprintln!("❌ 发送更新事件失败: {err:?}");
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": "Failed to emit update event"})),
-        );
+// Restrict CORS to the configured origins, or allow everything when none are set
+fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
+    if allowed_origins.is_empty() {
+        return CorsLayer::permissive();
     }
 
-    println!("✅ 元素已更新: {}", element_id);
-    (
-        StatusCode::OK,
-        Json(json!({"success": true, "message": format!("Element '{}' updated", element_id)})),
+    let origins: Vec<HeaderValue> = allowed_origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(value) => Some(value),
+            Err(err) => {
+                warn!(
+                    target: "http_server",
+                    action = "invalid_cors_origin",
+                    origin = %origin,
+                    error = %err,
+                    "忽略无效的CORS来源"
+                );
+                None
+            }
+        })
+        .collect();
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers(Any)
+        .expose_headers([
+            header::ETAG,
+            header::ALLOW,
+            HeaderName::from_static(IDEMPOTENT_REPLAYED),
+        ])
+}
+
+// Health check endpoint
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "server",
+    responses((status = 200, description = "The server is up", body = String))
+)]
+async fn health() -> &'static str {
+    "ok"
+}
+
+// Stop the HTTP server from a script. In-flight requests, this one included, still complete.
+// Only loopback clients may call it, even when the server listens on other interfaces.
+async fn admin_shutdown(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let Some(api_key) = state.config.api_key.as_deref() else {
+        return Err(ApiError::AdminDisabled);
+    };
+    let client = client_ip(connect_info);
+    if !client.is_loopback() {
+        warn!(
+            target: "http_server",
+            action = "admin_remote_rejected",
+            client = %client,
+            "管理接口拒绝了非本机请求"
+        );
+        return Err(ApiError::AdminNotLocal);
+    }
+    let provided = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if !keys_match(provided, api_key) {
+        warn!(
+            target: "http_server",
+            action = "admin_key_rejected",
+            "管理接口收到无效的API密钥"
+        );
+        return Err(ApiError::InvalidApiKey);
+    }
+
+    info!(
+        target: "http_server",
+        action = "admin_shutdown",
+        "通过管理接口关闭HTTP服务器"
+    );
+    if let Some(shutdown) = &state.shutdown {
+        shutdown.cancel();
+    }
+    Ok((StatusCode::ACCEPTED, Json(json!({"success": true}))))
+}
+
+// Compare without stopping at the first difference, so timing doesn't reveal the key
+fn keys_match(provided: &str, expected: &str) -> bool {
+    provided.len() == expected.len()
+        && provided
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Machine-readable API description for client generators
+async fn openapi_spec() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// Prometheus scrape target
+async fn metrics(State(state): State<AppState>) -> Response {
+    let mut element_counts: Vec<(String, usize)> = {
+        let scenes = state.scenes.read().await;
+        scenes
+            .iter()
+            .map(|(scene, canvas)| {
+                let count = canvas
+                    .elements
+                    .as_ref()
+                    .and_then(|elements| elements.as_array())
+                    .map_or(0, |elements| elements.len());
+                (scene.clone(), count)
+            })
+            .collect()
+    };
+    element_counts.sort();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(&element_counts),
+    )
+        .into_response()
+}
+
+// Diagnostics for humans; probes should keep using the plain /health
+async fn health_detailed(State(state): State<AppState>) -> Json<Value> {
+    let scenes = state.scenes.read().await;
+    let default_canvas = scenes.get(DEFAULT_SCENE);
+    let element_count = default_canvas
+        .and_then(|canvas| canvas.elements.as_ref())
+        .and_then(|elements| elements.as_array())
+        .map_or(0, |elements| elements.len());
+
+    Json(json!({
+        "status": "ok",
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime_secs": state.started_at.elapsed().as_secs_f64(),
+        "updated_at": default_canvas.map(|canvas| &canvas.updated_at),
+        "element_count": element_count,
+        "scene_count": scenes.len(),
+    }))
+}
+
+// Check every element carries the fields the exporter and element routes rely on.
+// Unknown extra fields are allowed so newer Excalidraw versions pass through.
+fn validate_elements(elements: &Value) -> Result<(), Vec<String>> {
+    let Some(elements_array) = elements.as_array() else {
+        return Err(vec!["elements must be an array".to_string()]);
+    };
+
+    let problems: Vec<String> = elements_array
+        .iter()
+        .enumerate()
+        .flat_map(|(index, element)| {
+            element_problems(element)
+                .into_iter()
+                .map(move |problem| format!("elements[{}]: {}", index, problem))
+        })
+        .collect();
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn validate_element(element: &Value) -> Result<(), Vec<String>> {
+    let problems = element_problems(element);
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
+}
+
+fn element_problems(element: &Value) -> Vec<String> {
+    if !element.is_object() {
+        return vec!["element must be an object".to_string()];
+    }
+
+    let mut problems = Vec::new();
+    for field in ["id", "type"] {
+        if !element.get(field).is_some_and(|v| v.is_string()) {
+            problems.push(format!("'{}' must be a string", field));
+        }
+    }
+    for field in ["x", "y"] {
+        if !element.get(field).is_some_and(|v| v.is_number()) {
+            problems.push(format!("'{}' must be a number", field));
+        }
+    }
+    problems
+}
+
+// Send queued draw events: the first goes out at once, then at most one per `interval`
+// per scene, always with the scene's latest canvas so the final state is never lost
+async fn flush_draws(state: AppState, pending: Arc<PendingDraws>, interval: Duration) {
+    loop {
+        pending.notify.notified().await;
+        loop {
+            let scenes = std::mem::take(
+                &mut *pending
+                    .scenes
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            );
+            if scenes.is_empty() {
+                break;
+            }
+            for scene in scenes {
+                let draw_payload = {
+                    let canvases = state.scenes.read().await;
+                    canvases.get(&scene).map(|canvas| DrawPayload {
+                        elements: canvas.elements.clone(),
+                        app_state: canvas.app_state.clone(),
+                        files: canvas.files.clone(),
+                    })
+                };
+                if let Some(draw_payload) = draw_payload {
+                    // send_event already logs failures and there is no caller to report them to
+                    let _ = send_event(&state, &scene, EVENT_DRAW, &draw_payload).await;
+                }
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+// Record a mutated canvas in the undo history and activity log and push it to SSE subscribers.
+// Call with the scenes write lock held so history stays in step with the canvas.
+// Elements sharing an id are collapsed first; returns how many duplicates were dropped.
+fn commit_canvas(
+    state: &AppState,
+    scene: &str,
+    canvas: &mut CanvasData,
+    kind: ChangeKind,
+) -> usize {
+    state.metrics.record_mutation();
+    let duplicates = canvas.elements.as_mut().map_or(0, dedupe_elements);
+    if duplicates > 0 {
+        warn!(
+            target: "canvas_update",
+            action = "duplicate_elements_removed",
+            scene = scene,
+            duplicates = duplicates,
+            "已移除重复ID的元素"
+        );
+    }
+
+    {
+        let mut history = state
+            .history
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let scene_history = history.entry(scene.to_string()).or_default();
+        scene_history.record(canvas, state.config.history_limit);
+        scene_history.log_change(kind, count_elements(canvas.elements.as_ref()));
+    }
+    broadcast_canvas(state, scene, canvas);
+    duplicates
+}
+
+// Refuse a change taking a canvas from `current` to `count` elements if that's over the cap
+fn check_element_limit(state: &AppState, current: usize, count: usize) -> Result<(), ApiError> {
+    match state.config.max_elements {
+        Some(max) if count > max => Err(ApiError::TooManyElements { current, max }),
+        _ => Ok(()),
+    }
+}
+
+fn count_elements(elements: Option<&Value>) -> usize {
+    elements
+        .and_then(|elements| elements.as_array())
+        .map_or(0, Vec::len)
+}
+
+// Keep only the last element for each id; element routes act on the first match, so
+// duplicates would make them miss the element that is actually drawn on top
+fn dedupe_elements(elements: &mut Value) -> usize {
+    let Some(elements_array) = elements.as_array_mut() else {
+        return 0;
+    };
+    let mut last_index = HashMap::new();
+    for (index, element) in elements_array.iter().enumerate() {
+        if let Some(id) = element.get("id").and_then(|v| v.as_str()) {
+            last_index.insert(id.to_string(), index);
+        }
+    }
+    if last_index.len() == elements_array.len() {
+        return 0;
+    }
+
+    let before = elements_array.len();
+    let mut index = 0;
+    elements_array.retain(|element| {
+        let keep = element
+            .get("id")
+            .and_then(|v| v.as_str())
+            .is_none_or(|id| last_index.get(id) == Some(&index));
+        index += 1;
+        keep
+    });
+    before - elements_array.len()
+}
+
+// Push the latest canvas to SSE subscribers; a send error only means nobody is listening
+fn broadcast_canvas(state: &AppState, scene: &str, canvas: &CanvasData) {
+    if state.events.receiver_count() > 0 {
+        let _ = state.events.send(SceneEvent {
+            scene: scene.to_string(),
+            payload: canvas.clone(),
+        });
+    }
+}
+
+// Stream canvas changes to non-Tauri clients as Server-Sent Events
+async fn canvas_events(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.events.subscribe()).filter_map(|message| {
+        match message {
+            Ok(canvas) => match Event::default().event("canvas").json_data(&canvas) {
+                Ok(event) => Some(Ok(event)),
+                Err(err) => {
+                    error!(
+                        target: "canvas_event",
+                        action = "sse_serialize_failed",
+                        error = %err,
+                        "SSE事件序列化失败"
+                    );
+                    None
+                }
+            },
+            // Every message carries the full canvas, so a lagging client just skips ahead
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                warn!(
+                    target: "canvas_event",
+                    action = "sse_subscriber_lagged",
+                    skipped = skipped,
+                    "SSE订阅者落后，已跳过部分事件"
+                );
+                None
+            }
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// Two-way canvas channel: pushes every change like /events and applies inbound edits
+async fn canvas_socket(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let client = client_ip(connect_info);
+    ws.on_upgrade(move |socket| handle_socket(socket, state, client))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: AppState, client: IpAddr) {
+    // Dropped when the loop ends, which removes the subscription
+    let mut updates = state.events.subscribe();
+    info!(
+        target: "canvas_event",
+        action = "socket_connected",
+        "WebSocket客户端已连接"
+    );
+
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(canvas) => {
+                    let frame = match serde_json::to_string(&canvas) {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            error!(
+                                target: "canvas_event",
+                                action = "socket_serialize_failed",
+                                error = %err,
+                                "WebSocket事件序列化失败"
+                            );
+                            continue;
+                        }
+                    };
+                    if socket.send(Message::Text(frame)).await.is_err() {
+                        break;
+                    }
+                }
+                // Every message carries the full canvas, so a lagging client just skips ahead
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(
+                        target: "canvas_event",
+                        action = "socket_subscriber_lagged",
+                        skipped = skipped,
+                        "WebSocket订阅者落后，已跳过部分事件"
+                    );
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    // Report bad edits on the socket instead of dropping the connection
+                    if let Err(err) = apply_socket_message(&state, client, &text).await {
+                        let frame = err.body().to_string();
+                        if socket.send(Message::Text(frame)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum; binary frames carry nothing we understand
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    info!(
+        target: "canvas_event",
+        action = "socket_disconnected",
+        "WebSocket客户端已断开"
+    );
+}
+
+// Run an inbound socket edit through the same code as the matching HTTP route. Each edit
+// takes a rate limit token like an HTTP mutation; idempotency keys don't apply, as socket
+// messages carry no headers and a client resending over the socket owns the retry.
+async fn apply_socket_message(
+    state: &AppState,
+    client: IpAddr,
+    text: &str,
+) -> Result<(), ApiError> {
+    if let Some(limiter) = &state.limiter {
+        if let Err(retry_after) = limiter.check(client) {
+            warn!(
+                target: "canvas_event",
+                action = "socket_rate_limited",
+                client = %client,
+                "WebSocket编辑过于频繁，已限流"
+            );
+            return Err(ApiError::RateLimited(retry_after_secs(retry_after)));
+        }
+    }
+    let message: SocketMessage =
+        serde_json::from_str(text).map_err(|err| ApiError::InvalidMessage(err.to_string()))?;
+    match message {
+        SocketMessage::Draw { scene, payload } => apply_draw(state, &scene, &payload).await,
+        SocketMessage::Update {
+            scene,
+            id,
+            element,
+            version,
+        } => update_element(
+            State(state.clone()),
+            Path(ElementPath { scene, id }),
+            HeaderMap::new(),
+            JsonBody(UpdateElementPayload { element, version }),
+        )
+        .await
+        .map(|_| ()),
+        SocketMessage::Remove { scene, id } => {
+            remove_element(State(state.clone()), Path(ElementPath { scene, id }))
+                .await
+                .map(|_| ())
+        }
+    }
+}
+
+// Draw to canvas and emit event
+#[utoipa::path(
+    post,
+    path = "/draw",
+    tag = "canvas",
+    request_body = DrawPayload,
+    responses(
+        (status = 200, description = "Canvas updated and frontend notified"),
+        (status = 400, description = "Elements failed validation")
+    )
+)]
+async fn draw_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<DrawPayload>,
+) -> Result<Json<Value>, ApiError> {
+    apply_draw(&state, &scene, &payload).await?;
+    Ok(Json(json!({"success": true})))
+}
+
+/// Merge a draw payload into a scene and notify the frontend; shared by `/draw` and the
+/// `push_elements` command.
+pub async fn apply_draw(
+    state: &AppState,
+    scene: &str,
+    payload: &DrawPayload,
+) -> Result<(), ApiError> {
+    if let Some(elements) = &payload.elements {
+        validate_elements(elements).map_err(ApiError::InvalidElements)?;
+    }
+
+    let element_count = payload
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .map_or(0, |elements| elements.len());
+    info!(
+        target: "canvas_draw",
+        action = "draw_canvas_start",
+        element_count = element_count,
+        has_app_state = payload.app_state.is_some(),
+        has_files = payload.files.is_some(),
+        "🎨 收到绘制请求"
+    );
+
+    // Update canvas data
+    let emitted = {
+        let mut scenes = state.scenes.write().await;
+        if payload.elements.is_some() {
+            let current = count_elements(
+                scenes
+                    .get(scene)
+                    .and_then(|canvas| canvas.elements.as_ref()),
+            );
+            check_element_limit(state, current, element_count)?;
+        }
+        let canvas = scenes
+            .entry(scene.to_string())
+            .or_insert_with(CanvasData::empty);
+        if let Some(elements) = &payload.elements {
+            canvas.elements = Some(elements.clone());
+        }
+        if let Some(app_state) = &payload.app_state {
+            canvas.app_state = Some(retain_app_state(state, app_state));
+        }
+        if let Some(files) = &payload.files {
+            canvas.files = Some(files.clone());
+        }
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        let duplicates = commit_canvas(state, scene, canvas, ChangeKind::Draw);
+        // Send what was stored: deduplicated elements and only the retained appState keys
+        DrawPayload {
+            elements: if duplicates > 0 {
+                canvas.elements.clone()
+            } else {
+                payload.elements.clone()
+            },
+            app_state: payload.app_state.as_ref().and(canvas.app_state.clone()),
+            files: payload.files.clone(),
+        }
+    };
+
+    // Emit draw event to frontend
+    emit_event(state, scene, EVENT_DRAW, &emitted).await?;
+
+    info!(
+        target: "canvas_draw",
+        action = "draw_canvas_success",
+        element_count = element_count,
+        "✅ 已发送绘制事件到前端"
+    );
+    Ok(())
+}
+
+// Get current canvas data
+#[utoipa::path(
+    get,
+    path = "/canvas",
+    tag = "canvas",
+    responses(
+        (status = 200, description = "The current canvas, with an ETag", body = CanvasResponse),
+        (status = 304, description = "Unchanged since the If-None-Match tag"),
+        (status = 404, description = "Unknown scene")
+    )
+)]
+async fn get_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // Clone under the guard and stream the encoding after releasing it; scenes with
+    // embedded files can take a while to encode and writers should not wait on that
+    let (etag, canvas) = {
+        let scenes = state.scenes.read().await;
+        let canvas = scenes
+            .get(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        // Every mutation refreshes updated_at, so it identifies the canvas version
+        let etag = format!("\"{}\"", canvas.updated_at);
+        if etag_matches(&headers, &etag) {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+        }
+        (etag, canvas.clone())
+    };
+    let hash = content_hash(canvas.elements.as_ref());
+    let seq = state.seq.load(Ordering::SeqCst);
+    Ok((
+        [
+            (header::ETAG, etag),
+            (header::CONTENT_TYPE, "application/json".to_string()),
+        ],
+        json_stream(CanvasResponse { canvas, hash, seq }),
+    )
+        .into_response())
+}
+
+// Encode `value` on a blocking thread and stream it out in chunks, so a large scene is
+// neither encoded on the async runtime nor buffered whole before the first byte is sent
+fn json_stream<T: Serialize + Send + 'static>(value: T) -> Body {
+    let (sender, receiver) = mpsc::channel(JSON_STREAM_BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let mut writer =
+            std::io::BufWriter::with_capacity(JSON_STREAM_CHUNK_BYTES, ChunkWriter(sender.clone()));
+        let written = serde_json::to_writer(&mut writer, &value)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writer.flush());
+        // A closed channel means the client went away, and nobody is left to tell
+        if let Err(err) = written {
+            let _ = sender.blocking_send(Err(err));
+        }
+    });
+    Body::from_stream(ReceiverStream::new(receiver))
+}
+
+/// Hands each buffered chunk of a streamed body to the response.
+struct ChunkWriter(mpsc::Sender<std::io::Result<Bytes>>);
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// 64-bit FNV-1a of the elements written with sorted object keys. Array order is kept,
+// since it is the drawing order.
+fn content_hash(elements: Option<&Value>) -> String {
+    let mut canonical = String::new();
+    write_canonical(elements.unwrap_or(&Value::Null), &mut canonical);
+    let hash = canonical
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (index, key) in keys.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&fields[key], out);
+            }
+            out.push('}');
+        }
+        scalar => out.push_str(&scalar.to_string()),
+    }
+}
+
+// Whether an If-None-Match header names the current entity tag
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Summarize a scene without sending its elements
+async fn canvas_stats(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    let scenes = state.scenes.read().await;
+    let canvas = scenes
+        .get(&scene)
+        .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+
+    let elements = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut by_type: BTreeMap<&str, usize> = BTreeMap::new();
+    for element in elements {
+        let element_type = element
+            .get("type")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+        *by_type.entry(element_type).or_default() += 1;
+    }
+    let approx_bytes = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| serde_json::to_vec(elements).ok())
+        .map_or(0, |bytes| bytes.len());
+
+    Ok(Json(json!({
+        "total": elements.len(),
+        "by_type": by_type,
+        "updated_at": canvas.updated_at,
+        "approx_bytes": approx_bytes,
+    })))
+}
+
+// List element ids without sending the elements themselves
+async fn element_ids(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    let scenes = state.scenes.read().await;
+    let canvas = scenes
+        .get(&scene)
+        .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+
+    let elements = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let ids: Vec<&str> = elements
+        .iter()
+        .filter_map(|element| element.get("id").and_then(|v| v.as_str()))
+        .collect();
+
+    Ok(Json(json!({
+        "ids": ids,
+        "count": ids.len(),
+        "unkeyed": elements.len() - ids.len(),
+    })))
+}
+
+// Update canvas data, keeping whatever the payload leaves out
+#[utoipa::path(
+    put,
+    path = "/canvas",
+    tag = "canvas",
+    request_body = DrawPayload,
+    description = "Merges the payload into the canvas: fields that are absent or null keep their \
+        current value. Use `POST /canvas/replace` to overwrite every field.",
+    responses(
+        (status = 200, description = "Canvas updated and frontend notified"),
+        (status = 400, description = "Elements failed validation")
+    )
+)]
+async fn update_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(mut payload): JsonBody<DrawPayload>,
+) -> Result<Json<Value>, ApiError> {
+    if let Some(elements) = &payload.elements {
+        validate_elements(elements).map_err(ApiError::InvalidElements)?;
+    }
+
+    // Filter up front so the stored and emitted appState are the same
+    if let Some(app_state) = &mut payload.app_state {
+        *app_state = retain_app_state(&state, app_state);
+    }
+    let app_state_keys: Vec<&String> = payload
+        .app_state
+        .as_ref()
+        .and_then(|app_state| app_state.as_object())
+        .map(|fields| fields.keys().collect())
+        .unwrap_or_default();
+    info!(
+        target: "canvas_update",
+        action = "update_canvas_start",
+        element_count = count_elements(payload.elements.as_ref()),
+        app_state_keys = ?app_state_keys,
+        "接收到画布更新数据"
+    );
+
+    let updated_at = chrono::Utc::now().to_rfc3339();
+    {
+        let mut scenes = state.scenes.write().await;
+        if payload.elements.is_some() {
+            let current = count_elements(
+                scenes
+                    .get(&scene)
+                    .and_then(|canvas| canvas.elements.as_ref()),
+            );
+            check_element_limit(&state, current, count_elements(payload.elements.as_ref()))?;
+        }
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        if let Some(elements) = &payload.elements {
+            canvas.elements = Some(elements.clone());
+        }
+        if let Some(app_state) = &payload.app_state {
+            canvas.app_state = Some(app_state.clone());
+        }
+        if let Some(files) = &payload.files {
+            canvas.files = Some(files.clone());
+        }
+        canvas.updated_at = updated_at.clone();
+        if commit_canvas(&state, &scene, canvas, ChangeKind::Update) > 0 {
+            payload.elements = canvas.elements.clone();
+        }
+    }
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "update_canvas_success",
+        updated_at = %updated_at,
+        element_count = count_elements(payload.elements.as_ref()),
+        "画布数据已成功更新并发送到前端"
+    );
+    Ok(Json(json!({"success": true})))
+}
+
+// Overwrite the whole canvas; unlike PUT, anything the payload leaves out is cleared
+#[utoipa::path(
+    post,
+    path = "/canvas/replace",
+    tag = "canvas",
+    request_body = DrawPayload,
+    description = "Sets elements, appState and files to exactly what the payload holds, \
+        clearing any that are absent or null. `PUT /canvas` merges instead.",
+    responses(
+        (status = 200, description = "Canvas replaced and frontend notified"),
+        (status = 400, description = "Elements failed validation")
     )
+)]
+async fn replace_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<DrawPayload>,
+) -> Result<Json<Value>, ApiError> {
+    if let Some(elements) = &payload.elements {
+        validate_elements(elements).map_err(ApiError::InvalidElements)?;
+    }
+    info!(
+        target: "canvas_update",
+        action = "replace_canvas_start",
+        scene = %scene,
+        element_count = count_elements(payload.elements.as_ref()),
+        has_app_state = payload.app_state.is_some(),
+        has_files = payload.files.is_some(),
+        "替换整个画布"
+    );
+
+    let draw_payload = {
+        let mut scenes = state.scenes.write().await;
+        let current = count_elements(
+            scenes
+                .get(&scene)
+                .and_then(|canvas| canvas.elements.as_ref()),
+        );
+        check_element_limit(&state, current, count_elements(payload.elements.as_ref()))?;
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        canvas.elements = payload.elements;
+        canvas.app_state = payload
+            .app_state
+            .as_ref()
+            .map(|app_state| retain_app_state(&state, app_state));
+        canvas.files = payload.files;
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::Replace);
+
+        DrawPayload {
+            elements: canvas.elements.clone(),
+            app_state: canvas.app_state.clone(),
+            files: canvas.files.clone(),
+        }
+    };
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "replace_canvas_success",
+        scene = %scene,
+        "画布已替换"
+    );
+    Ok(Json(json!({"success": true})))
+}
+
+// Apply an RFC 6902 JSON Patch to the canvas
+async fn patch_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(patch): JsonBody<json_patch::Patch>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_update",
+        action = "patch_canvas_start",
+        op_count = patch.len(),
+        "接收到画布补丁"
+    );
+
+    let draw_payload = {
+        let mut scenes = state.scenes.write().await;
+
+        // Patch a JSON copy so a failed operation leaves the canvas unchanged
+        let current = scenes
+            .get(&scene)
+            .cloned()
+            .unwrap_or_else(CanvasData::empty);
+        let mut document = json!({
+            "elements": current.elements,
+            "appState": current.app_state,
+            "files": current.files,
+        });
+        json_patch::patch(&mut document, &patch).map_err(|err| ApiError::PatchFailed {
+            operation: err.operation,
+            reason: err.to_string(),
+        })?;
+
+        let field = |name: &str| document.get(name).filter(|v| !v.is_null()).cloned();
+        let elements = field("elements");
+        if let Some(elements) = &elements {
+            validate_elements(elements).map_err(ApiError::InvalidElements)?;
+        }
+        check_element_limit(
+            &state,
+            count_elements(current.elements.as_ref()),
+            count_elements(elements.as_ref()),
+        )?;
+
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        canvas.elements = elements;
+        canvas.app_state = field("appState").map(|app_state| retain_app_state(&state, &app_state));
+        canvas.files = field("files");
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::Patch);
+
+        DrawPayload {
+            elements: canvas.elements.clone(),
+            app_state: canvas.app_state.clone(),
+            files: canvas.files.clone(),
+        }
+    };
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "patch_canvas_success",
+        op_count = patch.len(),
+        "画布补丁已应用"
+    );
+    Ok(Json(json!({"success": true})))
+}
+
+// Replace the canvas with the contents of a saved .excalidraw file
+async fn import_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<ImportPayload>,
+) -> Result<Json<Value>, ApiError> {
+    match payload.file_type.as_deref() {
+        Some("excalidraw") => {}
+        Some(other) => return Err(ApiError::InvalidImport(format!("type is '{}'", other))),
+        None => return Err(ApiError::InvalidImport("missing type".to_string())),
+    }
+    let elements = payload.elements.unwrap_or(Value::Null);
+    validate_elements(&elements).map_err(ApiError::InvalidElements)?;
+
+    let element_count = elements.as_array().map_or(0, |elements| elements.len());
+    info!(
+        target: "canvas_update",
+        action = "import_canvas_start",
+        scene = %scene,
+        element_count = element_count,
+        "导入画布文件"
+    );
+
+    let mut draw_payload = DrawPayload {
+        elements: Some(elements),
+        app_state: payload.app_state,
+        files: payload.files,
+    };
+    {
+        let mut scenes = state.scenes.write().await;
+        let current = count_elements(
+            scenes
+                .get(&scene)
+                .and_then(|canvas| canvas.elements.as_ref()),
+        );
+        check_element_limit(&state, current, element_count)?;
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        canvas.elements = draw_payload.elements.clone();
+        canvas.app_state = draw_payload
+            .app_state
+            .as_ref()
+            .map(|app_state| retain_app_state(&state, app_state));
+        canvas.files = draw_payload.files.clone();
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        if commit_canvas(&state, &scene, canvas, ChangeKind::Import) > 0 {
+            draw_payload.elements = canvas.elements.clone();
+        }
+    }
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "import_canvas_success",
+        scene = %scene,
+        element_count = element_count,
+        "画布文件已导入"
+    );
+    Ok(Json(json!({"success": true, "elements": element_count})))
+}
+
+// Reset a scene to a pristine canvas, dropping appState and files as well as elements
+async fn reset_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_clear",
+        action = "reset_canvas_start",
+        scene = %scene,
+        "开始重置画布"
+    );
+
+    let updated_at = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        *canvas = CanvasData::empty();
+        commit_canvas(&state, &scene, canvas, ChangeKind::Reset);
+        canvas.updated_at.clone()
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(json!([])),
+        app_state: None,
+        files: None,
+    };
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_clear",
+        action = "reset_canvas_success",
+        scene = %scene,
+        updated_at = %updated_at,
+        "画布已重置"
+    );
+    Ok(Json(json!({"success": true, "updated_at": updated_at})))
+}
+
+// Clear canvas
+#[utoipa::path(
+    post,
+    path = "/canvas/clear",
+    tag = "canvas",
+    responses(
+        (status = 200, description = "Elements removed; appState and files kept"),
+        (status = 404, description = "Unknown scene")
+    )
+)]
+async fn clear_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_clear",
+        action = "clear_canvas_start",
+        "开始清除画布"
+    );
+
+    let updated_at = chrono::Utc::now().to_rfc3339();
+    {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        clear_elements(&state, &scene, canvas, &updated_at);
+    }
+
+    let clear_payload = ClearPayload {
+        updated_at: updated_at.clone(),
+    };
+
+    // Emit clear event to frontend
+    emit_event(&state, &scene, EVENT_CLEAR, &clear_payload).await?;
+
+    let clear_payload_json =
+        serde_json::to_string(&clear_payload).unwrap_or_else(|_| "无法序列化清除数据".to_string());
+    let final_canvas_data = {
+        let scenes = state.scenes.read().await;
+        serde_json::to_string(&scenes.get(&scene))
+            .unwrap_or_else(|_| "无法序列化画布数据".to_string())
+    };
+    info!(
+        target: "canvas_clear",
+        action = "clear_canvas_success",
+        updated_at = %updated_at,
+        clear_data = %clear_payload_json,
+        final_canvas_data = %final_canvas_data,
+        "画布已成功清除"
+    );
+    Ok(Json(json!({"success": true})))
+}
+
+// Only the elements go; appState and files survive, unlike DELETE /canvas
+fn clear_elements(state: &AppState, scene: &str, canvas: &mut CanvasData, updated_at: &str) {
+    canvas.elements = Some(json!([]));
+    canvas.updated_at = updated_at.to_string();
+    commit_canvas(state, scene, canvas, ChangeKind::Clear);
+}
+
+// Kiosk mode: clear scenes left unchanged for `ttl`. Every mutation refreshes
+// updated_at, so any edit restarts the countdown.
+async fn clear_idle_scenes(state: AppState, ttl: Duration, shutdown: CancellationToken) {
+    // Check a few times per TTL so scenes clear close to on time
+    let period = (ttl / 4).clamp(Duration::from_millis(100), Duration::from_secs(60));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {}
+        }
+
+        let now = chrono::Utc::now();
+        let updated_at = now.to_rfc3339();
+        let cleared: Vec<String> = {
+            let mut scenes = state.scenes.write().await;
+            let mut cleared = Vec::new();
+            for (scene, canvas) in scenes.iter_mut() {
+                // Already-empty scenes stay as they are instead of being cleared every tick
+                let empty = canvas
+                    .elements
+                    .as_ref()
+                    .and_then(|elements| elements.as_array())
+                    .is_none_or(|elements| elements.is_empty());
+                let idle = chrono::DateTime::parse_from_rfc3339(&canvas.updated_at)
+                    .ok()
+                    .and_then(|changed| (now - changed.to_utc()).to_std().ok())
+                    .is_some_and(|idle| idle >= ttl);
+                if empty || !idle {
+                    continue;
+                }
+                clear_elements(&state, scene, canvas, &updated_at);
+                cleared.push(scene.clone());
+            }
+            cleared
+        };
+
+        for scene in cleared {
+            let clear_payload = ClearPayload {
+                updated_at: updated_at.clone(),
+            };
+            // emit_event already logs failures and there is no caller to report them to
+            let _ = emit_event(&state, &scene, EVENT_CLEAR, &clear_payload).await;
+            info!(
+                target: "canvas_clear",
+                action = "idle_clear",
+                scene = %scene,
+                idle_secs = ttl.as_secs(),
+                "画布长时间未变动，已自动清除"
+            );
+        }
+    }
+}
+
+// Recent changes to a scene, oldest first
+#[utoipa::path(
+    get,
+    path = "/canvas/history",
+    tag = "canvas",
+    responses(
+        (status = 200, description = "Recent changes to the scene, oldest first", body = [ChangeRecord]),
+        (status = 404, description = "Unknown scene")
+    )
+)]
+async fn canvas_history(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Vec<ChangeRecord>>, ApiError> {
+    let scenes = state.scenes.read().await;
+    if !scenes.contains_key(&scene) {
+        return Err(ApiError::SceneNotFound(scene));
+    }
+    let changes = state
+        .history
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&scene)
+        .map(|history| history.changes.iter().cloned().collect())
+        .unwrap_or_default();
+    Ok(Json(changes))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HistoryStep {
+    Undo,
+    Redo,
+}
+
+// Undo the last change to a scene
+async fn undo_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    step_history(state, scene, HistoryStep::Undo).await
+}
+
+// Re-apply the last undone change to a scene
+async fn redo_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+) -> Result<Json<Value>, ApiError> {
+    step_history(state, scene, HistoryStep::Redo).await
+}
+
+async fn step_history(
+    state: AppState,
+    scene: String,
+    step: HistoryStep,
+) -> Result<Json<Value>, ApiError> {
+    let draw_payload = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+
+        let restored = {
+            let mut history = state
+                .history
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let scene_history = history.entry(scene.clone()).or_default();
+            let (restored, kind) = match step {
+                HistoryStep::Undo => (scene_history.undo(), ChangeKind::Undo),
+                HistoryStep::Redo => (scene_history.redo(), ChangeKind::Redo),
+            };
+            if let Some(restored) = &restored {
+                scene_history.log_change(kind, count_elements(restored.elements.as_ref()));
+            }
+            restored
+        };
+        let Some(restored) = restored else {
+            return Err(match step {
+                HistoryStep::Undo => ApiError::NothingToUndo(scene),
+                HistoryStep::Redo => ApiError::NothingToRedo(scene),
+            });
+        };
+
+        *canvas = restored;
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        // Moving through history is not itself a change, so broadcast without recording
+        broadcast_canvas(&state, &scene, canvas);
+
+        DrawPayload {
+            elements: Some(canvas.elements.clone().unwrap_or_else(|| json!([]))),
+            app_state: canvas.app_state.clone(),
+            files: canvas.files.clone(),
+        }
+    };
+
+    // Emit draw event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "history_step",
+        scene = %scene,
+        step = ?step,
+        "画布历史已切换"
+    );
+    Ok(Json(json!({"success": true})))
+}
+
+// Export canvas as SVG or other formats
+#[utoipa::path(
+    get,
+    path = "/canvas/export",
+    tag = "canvas",
+    params(ExportQuery),
+    responses(
+        (status = 200, description = "The exported canvas in the requested format"),
+        (status = 400, description = "Unsupported format"),
+        (status = 500, description = "Rendering failed")
+    )
+)]
+async fn export_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    Query(params): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    // An explicit format wins over content negotiation
+    let format = params
+        .format
+        .clone()
+        .unwrap_or_else(|| format_from_accept(&headers));
+    info!(
+        target: "canvas_export",
+        action = "export_canvas_start",
+        format = %format,
+        width = params.width,
+        height = params.height,
+        fit = params.fit,
+        scale = params.scale,
+        "📤 导出画布"
+    );
+
+    // Clone what we need and release the read guard before rendering
+    let (elements, app_state, files) = {
+        let scenes = state.scenes.read().await;
+        let canvas = scenes
+            .get(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        (
+            canvas.elements.clone().unwrap_or_else(|| json!([])),
+            canvas.app_state.clone(),
+            canvas.files.clone(),
+        )
+    };
+
+    // Export a selection; with fit this crops to just those elements
+    let elements = match &params.ids {
+        Some(ids) => {
+            let ids: HashSet<&str> = ids.split(',').map(str::trim).collect();
+            let selected = elements
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .filter(|element| {
+                    element
+                        .get("id")
+                        .and_then(|v| v.as_str())
+                        .is_some_and(|id| ids.contains(id))
+                })
+                .cloned()
+                .collect();
+            Value::Array(selected)
+        }
+        None => elements,
+    };
+
+    render_export(
+        params,
+        format,
+        elements,
+        app_state,
+        files,
+        None,
+        state.config.unknown_types.clone(),
+    )
+    .await
+}
+
+// Export a frame's contents cropped to the frame: elements assigned to it by frameId
+// and any that lie entirely inside it. The frame itself, and so its name, is left out.
+async fn export_frame(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: frame_id,
+    }): Path<ElementPath>,
+    Query(params): Query<ExportQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = params
+        .format
+        .clone()
+        .unwrap_or_else(|| format_from_accept(&headers));
+    info!(
+        target: "canvas_export",
+        action = "export_frame_start",
+        frame_id = %frame_id,
+        format = %format,
+        scale = params.scale,
+        "📤 导出画框"
+    );
+
+    let (elements, app_state, files) = {
+        let scenes = state.scenes.read().await;
+        let canvas = scenes
+            .get(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        (
+            canvas.elements.clone().unwrap_or_else(|| json!([])),
+            canvas.app_state.clone(),
+            canvas.files.clone(),
+        )
+    };
+    let elements_array = elements.as_array().map(Vec::as_slice).unwrap_or_default();
+
+    let (left, top, right, bottom) = elements_array
+        .iter()
+        .find(|element| {
+            element.get("id").and_then(|v| v.as_str()) == Some(&frame_id)
+                && matches!(
+                    element.get("type").and_then(|v| v.as_str()),
+                    Some("frame" | "magicframe")
+                )
+        })
+        .and_then(element_rect)
+        .ok_or_else(|| ApiError::FrameNotFound(frame_id.clone()))?;
+    let region = ViewBox {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+    };
+
+    let contents: Vec<Value> = elements_array
+        .iter()
+        .filter(|element| element.get("id").and_then(|v| v.as_str()) != Some(&frame_id))
+        .filter(|element| {
+            element.get("frameId").and_then(|v| v.as_str()) == Some(&frame_id)
+                || element_rect(element)
+                    .is_some_and(|(l, t, r, b)| l >= left && t >= top && r <= right && b <= bottom)
+        })
+        .cloned()
+        .collect();
+
+    render_export(
+        params,
+        format,
+        Value::Array(contents),
+        app_state,
+        files,
+        Some(region),
+        state.config.unknown_types.clone(),
+    )
+    .await
+}
+
+// Render elements in `format`; `region` crops to those canvas coordinates, otherwise the
+// query's fit, width and height decide what is shown
+async fn render_export(
+    params: ExportQuery,
+    format: String,
+    elements: Value,
+    app_state: Option<Value>,
+    files: Option<Value>,
+    region: Option<ViewBox>,
+    unknown: UnknownTypes,
+) -> Result<Response, ApiError> {
+    let export =
+        render_export_blocking(params, format, elements, app_state, files, region, unknown).await?;
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, export.content_type);
+    if let Some(disposition) = export.disposition {
+        response = response.header(header::CONTENT_DISPOSITION, disposition);
+    }
+    Ok(response.body(Body::from(export.body)).unwrap())
+}
+
+/// An export rendered to memory, ready to be sent or written to disk.
+#[derive(Debug)]
+pub struct RenderedExport {
+    pub content_type: String,
+    /// `Content-Disposition` for HTTP responses; `None` for results meant to be shown inline
+    pub disposition: Option<String>,
+    pub body: Vec<u8>,
+}
+
+// Rasterising and encoding take long enough to stall a runtime worker, so they run on the
+// blocking pool
+async fn render_export_blocking(
+    params: ExportQuery,
+    format: String,
+    elements: Value,
+    app_state: Option<Value>,
+    files: Option<Value>,
+    region: Option<ViewBox>,
+    unknown: UnknownTypes,
+) -> Result<RenderedExport, ApiError> {
+    let failed = format.clone();
+    tokio::task::spawn_blocking(move || {
+        render_export_bytes(
+            &params,
+            format,
+            &elements,
+            app_state.as_ref(),
+            files.as_ref(),
+            region,
+            &unknown,
+        )
+    })
+    .await
+    .map_err(|err| {
+        error!(target: "canvas_export", action = "export_task_failed", error = %err, "导出任务失败");
+        ApiError::RenderFailed(failed)
+    })?
+}
+
+fn render_export_bytes(
+    params: &ExportQuery,
+    format: String,
+    elements: &Value,
+    app_state: Option<&Value>,
+    files: Option<&Value>,
+    region: Option<ViewBox>,
+    unknown: &UnknownTypes,
+) -> Result<RenderedExport, ApiError> {
+    if !(params.scale.is_finite() && params.scale > 0.0) {
+        return Err(ApiError::InvalidScale(params.scale));
+    }
+    if !(params.padding.is_finite() && params.padding >= 0.0) {
+        return Err(ApiError::InvalidPadding(params.padding));
+    }
+    if let Some(background) = params.background.as_deref() {
+        if !is_color(background) {
+            return Err(ApiError::InvalidBackground(background.to_string()));
+        }
+    }
+    let scale = params.scale.min(MAX_EXPORT_SCALE);
+
+    // An explicit background overrides viewBackgroundColor and the theme
+    let background = params
+        .background
+        .as_deref()
+        .unwrap_or_else(|| background_color(app_state));
+
+    // Fit to the element bounds when asked, falling back to the requested size for empty canvases
+    let fitted = if let Some(region) = region {
+        Some(region)
+    } else if params.fit {
+        element_bounds(elements).map(|(min_x, min_y, max_x, max_y)| ViewBox {
+            x: min_x - params.padding,
+            y: min_y - params.padding,
+            width: max_x - min_x + params.padding * 2.0,
+            height: max_y - min_y + params.padding * 2.0,
+        })
+    } else {
+        None
+    };
+    let view_box = fitted.unwrap_or(ViewBox {
+        x: 0.0,
+        y: 0.0,
+        width: params.width as f64,
+        height: params.height as f64,
+    });
+    // Scaling the output size but not the viewBox renders the same region at higher DPI
+    let width = (view_box.width * scale).ceil().max(1.0) as u32;
+    let height = (view_box.height * scale).ceil().max(1.0) as u32;
+
+    if params.validate_only {
+        if !EXPORT_FORMATS.contains(&format.as_str()) {
+            return Err(ApiError::UnsupportedFormat(format));
+        }
+        let svg_content = generate_svg(
+            elements, background, files, width, height, view_box, unknown,
+        );
+        let summary = export_preflight(elements, files, &svg_content, &format, unknown);
+        info!(
+            target: "canvas_export",
+            action = "export_canvas_validated",
+            format = %format,
+            valid = %summary["valid"],
+            "导出预检完成"
+        );
+        return Ok(RenderedExport {
+            content_type: "application/json".to_string(),
+            disposition: None,
+            body: summary.to_string().into_bytes(),
+        });
+    }
+
+    // Integers such as version and seed are never rounded, so this only trims float tails
+    let rounded;
+    let elements = match params.precision {
+        Some(precision) if format == "json" => {
+            rounded = round_geometry(elements, precision);
+            &rounded
+        }
+        _ => elements,
+    };
+    let export = match format.as_str() {
+        "svg" => {
+            let svg_content = finish_svg(
+                &generate_svg(
+                    elements, background, files, width, height, view_box, unknown,
+                ),
+                params,
+            );
+            RenderedExport {
+                content_type: "image/svg+xml".to_string(),
+                disposition: Some("inline; filename=\"canvas.svg\"".to_string()),
+                body: svg_content.into_bytes(),
+            }
+        }
+        // Shown rather than downloaded, as it isn't a file Excalidraw can open
+        "json" if params.elements_only => RenderedExport {
+            content_type: "application/json".to_string(),
+            disposition: None,
+            body: elements.to_string().into_bytes(),
+        },
+        "json" => {
+            let export_data = json!({
+                "elements": elements,
+                "appState": app_state,
+                "files": files,
+                "exported_at": chrono::Utc::now().to_rfc3339(),
+                "format": "excalidraw",
+                "type": "excalidraw",
+                "version": 2
+            });
+            RenderedExport {
+                content_type: "application/json".to_string(),
+                disposition: Some("attachment; filename=\"canvas.excalidraw\"".to_string()),
+                body: export_data.to_string().into_bytes(),
+            }
+        }
+        "toDataURL" => {
+            // Generate SVG first, then convert to base64 data URL
+            let svg_content = finish_svg(
+                &generate_svg(
+                    elements, background, files, width, height, view_box, unknown,
+                ),
+                params,
+            );
+            let base64_svg = general_purpose::STANDARD.encode(svg_content.as_bytes());
+            let data_url = format!("data:image/svg+xml;base64,{}", base64_svg);
+
+            let response_data = json!({
+                "dataURL": data_url,
+                "width": width,
+                "height": height,
+                "format": "svg",
+                "exported_at": chrono::Utc::now().to_rfc3339()
+            });
+
+            RenderedExport {
+                content_type: "application/json".to_string(),
+                disposition: None,
+                body: response_data.to_string().into_bytes(),
+            }
+        }
+        "png" | "jpeg" | "webp" | "avif" => {
+            // Checked before allocating: the pixmap alone is four bytes a pixel
+            if u64::from(width) * u64::from(height) > MAX_EXPORT_PIXELS {
+                return Err(ApiError::ExportTooLarge {
+                    width,
+                    height,
+                    max: MAX_EXPORT_PIXELS,
+                });
+            }
+            // Rasterize the generated SVG with resvg
+            let svg_content = generate_svg(
+                elements, background, files, width, height, view_box, unknown,
+            );
+            // Out-of-range qualities are clamped rather than rejected; encoders need at least 1
+            let quality = params.quality.map_or(DEFAULT_EXPORT_QUALITY, |quality| {
+                quality.clamp(1, 100) as u8
+            });
+            let bytes =
+                render_raster(&svg_content, &format, width, height, quality).map_err(|err| {
+                    error!(
+                        target: "canvas_export",
+                        action = "render_raster_failed",
+                        format = %format,
+                        error = %err,
+                        "栅格化导出失败"
+                    );
+                    ApiError::RenderFailed(format.clone())
+                })?;
+            RenderedExport {
+                content_type: format!("image/{}", format),
+                disposition: Some(format!("inline; filename=\"canvas.{}\"", format)),
+                body: bytes,
+            }
+        }
+        _ => return Err(ApiError::UnsupportedFormat(format)),
+    };
+    Ok(export)
+}
+
+/// Render a scene in one of `FILE_EXPORT_FORMATS` at the given size; used by the
+/// `export_to_file` command.
+pub async fn export_scene(
+    state: &AppState,
+    scene: &str,
+    format: &str,
+    width: u32,
+    height: u32,
+) -> Result<RenderedExport, ApiError> {
+    if !FILE_EXPORT_FORMATS.contains(&format) {
+        return Err(ApiError::UnsupportedFileFormat(format.to_string()));
+    }
+    let params = ExportQuery {
+        format: Some(format.to_string()),
+        width,
+        height,
+        fit: false,
+        padding: default_padding(),
+        ids: None,
+        background: None,
+        quality: None,
+        scale: default_scale(),
+        validate_only: false,
+        elements_only: false,
+        optimize: false,
+        precision: None,
+    };
+
+    let (elements, app_state, files) = {
+        let scenes = state.scenes.read().await;
+        let canvas = scenes
+            .get(scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.to_string()))?;
+        (
+            canvas.elements.clone().unwrap_or_else(|| json!([])),
+            canvas.app_state.clone(),
+            canvas.files.clone(),
+        )
+    };
+    render_export_blocking(
+        params,
+        format.to_string(),
+        elements,
+        app_state,
+        files,
+        None,
+        state.config.unknown_types.clone(),
+    )
+    .await
+}
+
+// `background` is any SVG colour; `transparent` leaves the background rect out
+fn generate_svg(
+    elements: &Value,
+    background: &str,
+    files: Option<&Value>,
+    width: u32,
+    height: u32,
+    view_box: ViewBox,
+    unknown: &UnknownTypes,
+) -> String {
+    let mut svg_elements = Vec::new();
+    // Keyed by id so elements sharing a fill style and colour share one pattern
+    let mut patterns = BTreeMap::new();
+
+    if let Some(elements_array) = elements.as_array() {
+        for element in draw_order(elements_array) {
+            if let Some((id, style, color)) = hatch_fill(element) {
+                patterns
+                    .entry(id)
+                    .or_insert_with_key(|id| hatch_pattern(id, style, color));
+            }
+            if let Some(svg_element) = convert_element_to_svg(element, files, unknown) {
+                svg_elements.push(svg_element);
+            }
+        }
+    }
+    let defs = if patterns.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<defs>{}</defs>",
+            patterns.into_values().collect::<Vec<_>>().join("")
+        )
+    };
+
+    let background_rect = if background == "transparent" {
+        String::new()
+    } else {
+        format!(
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+            view_box.x,
+            view_box.y,
+            view_box.width,
+            view_box.height,
+            escape_xml(background)
+        )
+    };
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<svg width="{}" height="{}" viewBox="{} {} {} {}" xmlns="http://www.w3.org/2000/svg">
+  {}
+  {}
+  {}
+</svg>"#,
+        width,
+        height,
+        view_box.x,
+        view_box.y,
+        view_box.width,
+        view_box.height,
+        background_rect,
+        defs,
+        svg_elements.join("\n  ")
+    )
+}
+
+// Round an export's coordinates to the requested precision, minifying it too with `optimize`.
+// Without either the SVG is returned exactly as generated.
+fn finish_svg(svg: &str, params: &ExportQuery) -> String {
+    match (params.precision, params.optimize) {
+        (None, false) => svg.to_string(),
+        (precision, minify) => rewrite_svg(svg, precision.unwrap_or(DEFAULT_SVG_PRECISION), minify),
+    }
+}
+
+// Round coordinates to `precision` decimals; with `minify`, whitespace between tags and
+// attributes restating SVG defaults go too. Relies on `generate_svg` always quoting
+// attributes with `"` and escaping text, so it is not a general-purpose SVG parser.
+fn rewrite_svg(svg: &str, precision: u32, minify: bool) -> String {
+    let precision = precision.min(MAX_SVG_PRECISION) as usize;
+    let mut rewritten = String::with_capacity(svg.len());
+    let mut rest = svg;
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        // Indentation between tags; text inside tspans is never whitespace-only
+        if !minify || !text.trim().is_empty() {
+            rewritten.push_str(text);
+        }
+        let end = match rest[start..].find('>') {
+            Some(offset) => start + offset + 1,
+            None => rest.len(),
+        };
+        rewritten.push_str(&rewrite_tag(&rest[start..end], precision, minify));
+        rest = &rest[end..];
+    }
+    if !minify || !rest.trim().is_empty() {
+        rewritten.push_str(rest);
+    }
+    rewritten
+}
+
+// Rewrite one start tag's attributes; end tags, declarations and malformed tags pass through
+fn rewrite_tag(tag: &str, precision: usize, minify: bool) -> String {
+    if tag.starts_with("</") || tag.starts_with("<?") || !tag.ends_with('>') {
+        return tag.to_string();
+    }
+    let (body, close) = match tag.strip_suffix("/>") {
+        Some(body) => (body, "/>"),
+        None => (&tag[..tag.len() - 1], ">"),
+    };
+    let name_end = body.find(|c: char| c.is_whitespace()).unwrap_or(body.len());
+    let mut optimized = body[..name_end].to_string();
+
+    let mut attributes = body[name_end..].trim_start();
+    while let Some((name, value_start)) = attributes.split_once("=\"") {
+        let Some((value, remainder)) = value_start.split_once('"') else {
+            return tag.to_string();
+        };
+        let name = name.trim();
+        let value = if SVG_NUMERIC_ATTRIBUTES.contains(&name) {
+            round_numbers(value, precision)
+        } else {
+            value.to_string()
+        };
+        if !minify || !SVG_DEFAULT_ATTRIBUTES.contains(&(name, value.as_str())) {
+            optimized.push_str(&format!(" {}=\"{}\"", name, value));
+        }
+        attributes = remainder.trim_start();
+    }
+    optimized.push_str(close);
+    optimized
+}
+
+// Copy of `elements` with fractional geometry rounded; points are rounded as well
+fn round_geometry(elements: &Value, precision: u32) -> Value {
+    let factor = 10f64.powi(precision.min(MAX_SVG_PRECISION) as i32);
+    let round = |value: &mut Value| {
+        if let Some(float) = value.as_f64().filter(|_| value.is_f64()) {
+            *value = json!((float * factor).round() / factor);
+        }
+    };
+
+    let mut elements = elements.clone();
+    for element in elements.as_array_mut().into_iter().flatten() {
+        for field in ROUNDED_ELEMENT_FIELDS {
+            if let Some(value) = element.get_mut(*field) {
+                round(value);
+            }
+        }
+        if let Some(points) = element
+            .get_mut("points")
+            .and_then(|points| points.as_array_mut())
+        {
+            for point in points.iter_mut().filter_map(|point| point.as_array_mut()) {
+                point.iter_mut().for_each(round);
+            }
+        }
+    }
+    elements
+}
+
+// Round every decimal number in an attribute value, dropping trailing zeros
+fn round_numbers(value: &str, precision: usize) -> String {
+    let mut rounded = String::with_capacity(value.len());
+    let mut number = String::new();
+    let flush = |number: &mut String, rounded: &mut String| {
+        if number.is_empty() {
+            return;
+        }
+        match number.parse::<f64>() {
+            Ok(parsed) => {
+                let formatted = format!("{:.*}", precision, parsed);
+                let formatted = if formatted.contains('.') {
+                    formatted.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    &formatted
+                };
+                rounded.push_str(if formatted == "-0" { "0" } else { formatted });
+            }
+            Err(_) => rounded.push_str(number),
+        }
+        number.clear();
+    };
+    for c in value.chars() {
+        // A minus only starts a number; "1-2" is two numbers in path data
+        if c.is_ascii_digit() || c == '.' || (c == '-' && number.is_empty()) {
+            number.push(c);
+        } else {
+            flush(&mut number, &mut rounded);
+            if c == '-' {
+                number.push(c);
+            } else {
+                rounded.push(c);
+            }
+        }
+    }
+    flush(&mut number, &mut rounded);
+    rounded
+}
+
+// Elements in the order they are painted. When every element has an `index`, either numbers
+// or Excalidraw's fractional-index strings (which sort bytewise), that decides; otherwise the
+// array order does. The sort is stable, so equal indices keep their array order.
+fn draw_order(elements: &[Value]) -> Vec<&Value> {
+    fn index(element: &Value) -> Option<&Value> {
+        element.get("index")
+    }
+
+    let mut ordered: Vec<&Value> = elements.iter().collect();
+    if elements
+        .iter()
+        .all(|element| index(element).is_some_and(Value::is_number))
+    {
+        ordered.sort_by(|a, b| {
+            let (a, b) = (
+                index(a).and_then(Value::as_f64),
+                index(b).and_then(Value::as_f64),
+            );
+            a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+        });
+    } else if elements
+        .iter()
+        .all(|element| index(element).is_some_and(Value::is_string))
+    {
+        ordered.sort_by_key(|element| index(element).and_then(Value::as_str));
+    }
+    ordered
+}
+
+// Explicit viewBackgroundColor wins, then the theme, then Excalidraw's white default
+fn background_color(app_state: Option<&Value>) -> &str {
+    let Some(app_state) = app_state else {
+        return "white";
+    };
+    if let Some(color) = app_state
+        .get("viewBackgroundColor")
+        .and_then(|v| v.as_str())
+        .filter(|color| !color.is_empty())
+    {
+        return color;
+    }
+    match app_state.get("theme").and_then(|v| v.as_str()) {
+        Some("dark") => DARK_BACKGROUND,
+        _ => "white",
+    }
+}
+
+// Colour names (including `transparent`) and #rgb, #rgba, #rrggbb or #rrggbbaa hex values
+fn is_color(value: &str) -> bool {
+    match value.strip_prefix('#') {
+        Some(hex) => {
+            matches!(hex.len(), 3 | 4 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => !value.is_empty() && value.chars().all(|c| c.is_ascii_alphabetic()),
+    }
+}
+
+// Bounding box (min_x, min_y, max_x, max_y) of every element with numeric geometry
+fn element_bounds(elements: &Value) -> Option<(f64, f64, f64, f64)> {
+    let mut bounds: Option<(f64, f64, f64, f64)> = None;
+
+    for element in elements.as_array()? {
+        let Some((left, top, right, bottom)) = element_rect(element) else {
+            continue;
+        };
+        bounds = Some(match bounds {
+            Some((min_x, min_y, max_x, max_y)) => (
+                min_x.min(left),
+                min_y.min(top),
+                max_x.max(right),
+                max_y.max(bottom),
+            ),
+            None => (left, top, right, bottom),
+        });
+    }
+
+    bounds
+}
+
+// Bounding box of one element, normalized for negative width or height
+fn element_rect(element: &Value) -> Option<(f64, f64, f64, f64)> {
+    let x = element.get("x")?.as_f64()?;
+    let y = element.get("y")?.as_f64()?;
+    let (width, height) = element_size(element);
+    Some((
+        x.min(x + width),
+        y.min(y + height),
+        x.max(x + width),
+        y.max(y + height),
+    ))
+}
+
+// An element's width and height, derived from its content where missing: text from its
+// lines and font size, point-based shapes from the extent of their points
+fn element_size(element: &Value) -> (f64, f64) {
+    let width = element.get("width").and_then(|v| v.as_f64());
+    let height = element.get("height").and_then(|v| v.as_f64());
+    if let (Some(width), Some(height)) = (width, height) {
+        return (width, height);
+    }
+
+    let derived = match element.get("type").and_then(|v| v.as_str()) {
+        Some("text") => {
+            let text = element.get("text").and_then(|v| v.as_str()).unwrap_or("");
+            let font_size = element
+                .get("fontSize")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(16.0);
+            let line_height = element
+                .get("lineHeight")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(DEFAULT_LINE_HEIGHT);
+            let longest = text
+                .split('\n')
+                .map(|line| line.trim_end_matches('\r').chars().count())
+                .max()
+                .unwrap_or(0);
+            (
+                longest as f64 * font_size * AVG_GLYPH_WIDTH_EM,
+                text.split('\n').count() as f64 * font_size * line_height,
+            )
+        }
+        Some("freedraw" | "line" | "arrow") => {
+            let points = element_points(element, 0.0, 0.0);
+            let extent = |coordinates: Vec<f64>| {
+                let min = coordinates.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = coordinates
+                    .iter()
+                    .copied()
+                    .fold(f64::NEG_INFINITY, f64::max);
+                if min.is_finite() {
+                    max - min
+                } else {
+                    0.0
+                }
+            };
+            (
+                extent(points.iter().map(|&(px, _)| px).collect()),
+                extent(points.iter().map(|&(_, py)| py).collect()),
+            )
+        }
+        _ => (0.0, 0.0),
+    };
+    (width.unwrap_or(derived.0), height.unwrap_or(derived.1))
+}
+
+// Dry run of an export: which elements would be skipped or drawn as placeholders, and
+// whether raster formats could parse the generated SVG
+fn export_preflight(
+    elements: &Value,
+    files: Option<&Value>,
+    svg: &str,
+    format: &str,
+    unknown: &UnknownTypes,
+) -> Value {
+    let elements_array = elements.as_array().map(Vec::as_slice).unwrap_or_default();
+    let mut unsupported_types = BTreeSet::new();
+    let mut warnings = Vec::new();
+    let mut rendered = 0;
+
+    for element in elements_array {
+        let id = element.get("id").and_then(|v| v.as_str()).unwrap_or("?");
+        let element_type = element.get("type").and_then(|v| v.as_str()).unwrap_or("");
+        if unknown.skips(element_type) {
+            warnings.push(format!(
+                "Element '{}' has type '{}', which exports leave out",
+                id, element_type
+            ));
+            continue;
+        }
+        if convert_element_to_svg(element, files, unknown).is_none() {
+            warnings.push(format!(
+                "Element '{}' is missing required fields and will be skipped",
+                id
+            ));
+            continue;
+        }
+        rendered += 1;
+        if !SVG_ELEMENT_TYPES.contains(&element_type) {
+            unsupported_types.insert(element_type.to_string());
+        } else if element_type == "image" && image_data_url(element, files).is_none() {
+            warnings.push(format!(
+                "Image '{}' has no file data and will be drawn as a placeholder",
+                id
+            ));
+        }
+    }
+
+    if elements_array.is_empty() {
+        warnings.push("Canvas has no elements".to_string());
+    }
+    let mut valid = rendered > 0;
+    if matches!(format, "png" | "jpeg" | "webp" | "avif") {
+        if let Err(err) = usvg::Tree::from_str(svg, &svg_options()) {
+            warnings.push(format!("Generated SVG could not be parsed: {}", err));
+            valid = false;
+        }
+    }
+
+    json!({
+        "valid": valid,
+        "element_count": elements_array.len(),
+        "unsupported_types": unsupported_types,
+        "warnings": warnings,
+    })
+}
+
+// Parse options shared by every raster export; loading system fonts is slow so do it once
+fn svg_options() -> usvg::Options<'static> {
+    static FONT_DB: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    let fontdb = FONT_DB
+        .get_or_init(|| {
+            let mut db = usvg::fontdb::Database::new();
+            db.load_system_fonts();
+            Arc::new(db)
+        })
+        .clone();
+    usvg::Options {
+        fontdb,
+        ..Default::default()
+    }
+}
+
+// Render SVG markup to PNG, JPEG, WebP or AVIF bytes at the given pixel size
+fn render_raster(
+    svg: &str,
+    format: &str,
+    width: u32,
+    height: u32,
+    quality: u8,
+) -> anyhow::Result<Vec<u8>> {
+    let tree = usvg::Tree::from_str(svg, &svg_options())?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| anyhow!("invalid image size {}x{}", width, height))?;
+
+    // JPEG has no alpha channel, so flatten onto white instead of black
+    if format == "jpeg" {
+        pixmap.fill(tiny_skia::Color::WHITE);
+    }
+
+    let size = tree.size();
+    let transform = tiny_skia::Transform::from_scale(
+        width as f32 / size.width(),
+        height as f32 / size.height(),
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+
+    // tiny-skia stores premultiplied pixels; the image crate expects straight alpha
+    let rgba: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let image = RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| anyhow!("pixel buffer does not match {}x{}", width, height))?;
+
+    let mut bytes = Cursor::new(Vec::new());
+    match format {
+        "png" => DynamicImage::ImageRgba8(image).write_to(&mut bytes, ImageFormat::Png)?,
+        "jpeg" => DynamicImage::ImageRgba8(image)
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?,
+        // The image crate only encodes lossless WebP, so quality doesn't apply
+        "webp" => DynamicImage::ImageRgba8(image).write_to(&mut bytes, ImageFormat::WebP)?,
+        "avif" => DynamicImage::ImageRgba8(image).write_with_encoder(
+            AvifEncoder::new_with_speed_quality(&mut bytes, AVIF_SPEED, quality),
+        )?,
+        _ => return Err(anyhow!("unsupported raster format: {}", format)),
+    }
+    Ok(bytes.into_inner())
+}
+
+fn convert_element_to_svg(
+    element: &Value,
+    files: Option<&Value>,
+    unknown: &UnknownTypes,
+) -> Option<String> {
+    let element_type = element.get("type")?.as_str()?;
+    if unknown.skips(element_type) {
+        return None;
+    }
+    let x = element.get("x").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let y = element.get("y").and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let (width, height) = element_size(element);
+    let (stroke_color, stroke_alpha) = split_alpha(
+        element
+            .get("strokeColor")
+            .and_then(|v| v.as_str())
+            .unwrap_or("#000000"),
+    );
+    let (background_color, background_alpha) = split_alpha(
+        element
+            .get("backgroundColor")
+            .and_then(|v| v.as_str())
+            .unwrap_or("transparent"),
+    );
+    let stroke_width = element
+        .get("strokeWidth")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(1.0);
+    let stroke_opacity = opacity_attribute("stroke-opacity", stroke_alpha);
+    // Text and arrowheads are painted in the stroke colour, so they take its alpha as fill-opacity
+    let text_opacity = opacity_attribute("fill-opacity", stroke_alpha);
+    // Hatch patterns apply the background alpha to their own lines
+    let (fill, fill_opacity) = match hatch_fill(element) {
+        Some((id, _, _)) => (format!("url(#{})", id), String::new()),
+        None => (
+            background_color.to_string(),
+            opacity_attribute("fill-opacity", background_alpha),
+        ),
+    };
+    let dash = stroke_dasharray(element, stroke_width);
+
+    let shape = match element_type {
+        "rectangle" => Some(format!(
+            r#"<rect x="{}" y="{}" width="{}" height="{}"{} fill="{}"{} stroke="{}"{} stroke-width="{}"{}/>"#,
+            x,
+            y,
+            width,
+            height,
+            corner_radius(element, width, height),
+            fill,
+            fill_opacity,
+            stroke_color,
+            stroke_opacity,
+            stroke_width,
+            dash
+        )),
+        "ellipse" => {
+            let cx = x + width / 2.0;
+            let cy = y + height / 2.0;
+            let rx = width / 2.0;
+            let ry = height / 2.0;
+            Some(format!(
+                r#"<ellipse cx="{}" cy="{}" rx="{}" ry="{}" fill="{}"{} stroke="{}"{} stroke-width="{}"{}/>"#,
+                cx,
+                cy,
+                rx,
+                ry,
+                fill,
+                fill_opacity,
+                stroke_color,
+                stroke_opacity,
+                stroke_width,
+                dash
+            ))
+        }
+        "diamond" => {
+            // Vertices at the midpoints of the bounding box edges: top, right, bottom, left
+            let points = [
+                (x + width / 2.0, y),
+                (x + width, y + height / 2.0),
+                (x + width / 2.0, y + height),
+                (x, y + height / 2.0),
+            ];
+            Some(format!(
+                r#"<polygon points="{}" fill="{}"{} stroke="{}"{} stroke-width="{}"{}/>"#,
+                format_points(&points),
+                fill,
+                fill_opacity,
+                stroke_color,
+                stroke_opacity,
+                stroke_width,
+                dash
+            ))
+        }
+        "arrow" | "line" => {
+            // Fall back to a straight segment across the bounding box when points are absent
+            let mut points = element_points(element, x, y);
+            if points.len() < 2 {
+                points = vec![(x, y), (x + width, y + height)];
+            }
+
+            let mut defs = String::new();
+            let mut markers = String::new();
+            if element_type == "arrow" {
+                let marker_id = format!("arrowhead-{}", svg_id(element));
+                // Arrows default to an end arrowhead; an explicit null removes it
+                let has_end = element
+                    .get("endArrowhead")
+                    .is_none_or(|arrowhead| !arrowhead.is_null());
+                let has_start = element
+                    .get("startArrowhead")
+                    .is_some_and(|arrowhead| !arrowhead.is_null());
+                if has_end || has_start {
+                    defs = format!(
+                        r#"<defs><marker id="{}" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="6" markerHeight="6" orient="auto-start-reverse"><path d="M 0 0 L 10 5 L 0 10 z" fill="{}"{}/></marker></defs>"#,
+                        marker_id, stroke_color, text_opacity
+                    );
+                }
+                if has_start {
+                    markers.push_str(&format!(r#" marker-start="url(#{})""#, marker_id));
+                }
+                if has_end {
+                    markers.push_str(&format!(r#" marker-end="url(#{})""#, marker_id));
+                }
+            }
+
+            // Rounded lines with a bend become a smooth path; markers orient to it the same way
+            let curved = points.len() > 2
+                && element
+                    .get("roundness")
+                    .is_some_and(|roundness| !roundness.is_null());
+            // Closed lines are filled shapes; the repeated end point is implied by closing
+            if element_type == "line" && is_closed_line(element, &points) {
+                if points.first() == points.last() {
+                    points.pop();
+                }
+                let geometry = if curved {
+                    format!(r#"path d="{} Z""#, smooth_path(&points))
+                } else {
+                    format!(r#"polygon points="{}""#, format_points(&points))
+                };
+                Some(format!(
+                    r#"<{} fill="{}"{} stroke="{}"{} stroke-width="{}"{}/>"#,
+                    geometry, fill, fill_opacity, stroke_color, stroke_opacity, stroke_width, dash
+                ))
+            } else {
+                let geometry = if curved {
+                    format!(r#"path d="{}""#, smooth_path(&points))
+                } else {
+                    format!(r#"polyline points="{}""#, format_points(&points))
+                };
+                Some(format!(
+                    r#"{}<{} fill="none" stroke="{}"{} stroke-width="{}"{}{}/>"#,
+                    defs, geometry, stroke_color, stroke_opacity, stroke_width, dash, markers
+                ))
+            }
+        }
+        "freedraw" => {
+            // Pencil strokes need at least two points to draw anything meaningful
+            let points = element_points(element, x, y);
+            if points.len() < 2 {
+                return None;
+            }
+            // Highlighter strokes are wide and translucent on top of any alpha in the colour
+            let (stroke_width, stroke_opacity) = if is_highlighter(element) {
+                (
+                    stroke_width * HIGHLIGHTER_WIDTH_SCALE,
+                    opacity_attribute(
+                        "stroke-opacity",
+                        Some(stroke_alpha.unwrap_or(1.0) * HIGHLIGHTER_OPACITY),
+                    ),
+                )
+            } else {
+                (stroke_width, stroke_opacity)
+            };
+            Some(format!(
+                r#"<polyline points="{}" fill="none" stroke="{}"{} stroke-width="{}" stroke-linecap="round" stroke-linejoin="round"/>"#,
+                format_points(&points),
+                stroke_color,
+                stroke_opacity,
+                stroke_width
+            ))
+        }
+        "text" => {
+            let text_content = element
+                .get("text")
+                .and_then(|v| v.as_str())
+                .unwrap_or("[text]");
+            let font_size = element
+                .get("fontSize")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(16.0);
+            let text_align = element
+                .get("textAlign")
+                .and_then(|v| v.as_str())
+                .unwrap_or("left");
+            let font_family_name = font_family(element);
+
+            let anchor = match text_align {
+                "center" => "middle",
+                "right" => "end",
+                _ => "start",
+            };
+
+            // One tspan per line; lineHeight is a multiple of fontSize as in Excalidraw
+            let line_height = element
+                .get("lineHeight")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(DEFAULT_LINE_HEIGHT);
+            let line_advance = font_size * line_height;
+            // Text bound to a container wraps to its width; free text is already measured to fit
+            let bound = element
+                .get("containerId")
+                .is_some_and(|container_id| !container_id.is_null());
+            let wrap_chars = (bound && width > 0.0)
+                .then(|| ((width / (font_size * AVG_GLYPH_WIDTH_EM)).floor() as usize).max(1));
+            let lines: String = text_content
+                .split('\n')
+                .map(|line| line.trim_end_matches('\r'))
+                .flat_map(|line| match wrap_chars {
+                    Some(max_chars) => wrap_line(line, max_chars),
+                    None => vec![line.to_string()],
+                })
+                .enumerate()
+                .map(|(index, line)| {
+                    format!(
+                        r#"<tspan x="{}" dy="{}">{}</tspan>"#,
+                        x,
+                        if index == 0 { 0.0 } else { line_advance },
+                        escape_xml(&line)
+                    )
+                })
+                .collect();
+
+            Some(format!(
+                r#"<text x="{}" y="{}" font-size="{}" font-family="{}" text-anchor="{}" fill="{}"{} dominant-baseline="hanging">{}</text>"#,
+                x, y, font_size, font_family_name, anchor, stroke_color, text_opacity, lines
+            ))
+        }
+        "frame" | "magicframe" => {
+            // Unfilled so contained elements stay visible; unnamed frames read "Frame"
+            let name = element
+                .get("name")
+                .and_then(|v| v.as_str())
+                .unwrap_or("Frame");
+            Some(format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}" stroke-width="1"/><text x="{}" y="{}" font-size="{}" font-family="Helvetica" fill="{}">{}</text>"#,
+                x,
+                y,
+                width,
+                height,
+                FRAME_STROKE,
+                x,
+                y - FRAME_NAME_FONT_SIZE / 2.0,
+                FRAME_NAME_FONT_SIZE,
+                FRAME_STROKE,
+                escape_xml(name)
+            ))
+        }
+        "embeddable" | "iframe" => {
+            // SVG can't host an iframe, so show the box and where it points
+            let link = element.get("link").and_then(|v| v.as_str()).unwrap_or("");
+            let label = truncate_to_width(link, width, EMBED_LABEL_FONT_SIZE);
+            Some(format!(
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"{} stroke="{}"{} stroke-width="{}"{}/><text x="{}" y="{}" font-size="{}" font-family="Helvetica" text-anchor="middle" dominant-baseline="middle" fill="{}"{}>{}</text>"#,
+                x,
+                y,
+                width,
+                height,
+                fill,
+                fill_opacity,
+                stroke_color,
+                stroke_opacity,
+                stroke_width,
+                dash,
+                x + width / 2.0,
+                y + height / 2.0,
+                EMBED_LABEL_FONT_SIZE,
+                stroke_color,
+                text_opacity,
+                escape_xml(&label)
+            ))
+        }
+        "image" => match image_data_url(element, files) {
+            Some(data_url) => Some(format!(
+                r#"<image x="{}" y="{}" width="{}" height="{}" href="{}" preserveAspectRatio="none"/>"#,
+                x,
+                y,
+                width,
+                height,
+                escape_xml(data_url)
+            )),
+            None => Some(placeholder_svg(
+                x,
+                y,
+                width,
+                height,
+                stroke_color,
+                &stroke_opacity,
+                stroke_width,
+            )),
+        },
+        _ if unknown.best_effort(element_type) => Some(format!(
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"{} stroke="{}"{} stroke-width="{}"{}/>"#,
+            x,
+            y,
+            width,
+            height,
+            fill,
+            fill_opacity,
+            stroke_color,
+            stroke_opacity,
+            stroke_width,
+            dash
+        )),
+        _ => {
+            // For unsupported elements, create a placeholder rectangle
+            Some(placeholder_svg(
+                x,
+                y,
+                width,
+                height,
+                stroke_color,
+                &stroke_opacity,
+                stroke_width,
+            ))
+        }
+    }?;
+
+    // Excalidraw stores opacity as 0-100 and angle in radians around the element center
+    let opacity = element
+        .get("opacity")
+        .and_then(|v| v.as_f64())
+        .unwrap_or(100.0);
+    let angle = element.get("angle").and_then(|v| v.as_f64()).unwrap_or(0.0);
+
+    let mut group_attributes = Vec::new();
+    if angle != 0.0 {
+        group_attributes.push(format!(
+            r#"transform="rotate({} {} {})""#,
+            angle.to_degrees(),
+            x + width / 2.0,
+            y + height / 2.0
+        ));
+    }
+    if opacity < 100.0 {
+        group_attributes.push(format!(
+            r#"opacity="{}""#,
+            (opacity / 100.0).clamp(0.0, 1.0)
+        ));
+    }
+
+    if group_attributes.is_empty() {
+        Some(shape)
+    } else {
+        Some(format!("<g {}>{}</g>", group_attributes.join(" "), shape))
+    }
+}
+
+// Image bytes live in the scene's `files` map, keyed by the element's fileId
+fn image_data_url<'a>(element: &Value, files: Option<&'a Value>) -> Option<&'a str> {
+    let file_id = element.get("fileId")?.as_str()?;
+    files?.get(file_id)?.get("dataURL")?.as_str()
+}
+
+// Dashed outline drawn where an element can't be rendered faithfully
+fn placeholder_svg(
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    stroke_color: &str,
+    stroke_opacity: &str,
+    stroke_width: f64,
+) -> String {
+    format!(
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="{}"{} stroke-width="{}" stroke-dasharray="5,5"/>"#,
+        x, y, width, height, stroke_color, stroke_opacity, stroke_width
+    )
+}
+
+// Shorten text with an ellipsis so it fits in `width`
+fn truncate_to_width(text: &str, width: f64, font_size: f64) -> String {
+    let max_chars = ((width - font_size) / (font_size * AVG_GLYPH_WIDTH_EM))
+        .floor()
+        .max(1.0) as usize;
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+// Greedy word wrap to at most `max_chars` per line; words longer than a line are split
+fn wrap_line(line: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in line.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let current_len = current.chars().count();
+        if current_len > 0 && current_len + 1 + word.len() <= max_chars {
+            current.push(' ');
+            current.extend(&word);
+            continue;
+        }
+        if current_len > 0 {
+            lines.push(std::mem::take(&mut current));
+        }
+        while word.len() > max_chars {
+            lines.push(word.drain(..max_chars).collect());
+        }
+        current.extend(word);
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Read an element's relative `points` array as absolute coordinates
+fn element_points(element: &Value, x: f64, y: f64) -> Vec<(f64, f64)> {
+    element
+        .get("points")
+        .and_then(|v| v.as_array())
+        .map(|points| {
+            points
+                .iter()
+                .filter_map(|point| {
+                    let point = point.as_array()?;
+                    let px = point.first()?.as_f64()?;
+                    let py = point.get(1)?.as_f64()?;
+                    Some((x + px, y + py))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// An element id reduced to characters that are safe inside an SVG id attribute
+fn svg_id(element: &Value) -> String {
+    element
+        .get("id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+// `rx`/`ry` attributes for rounded rectangles, matching Excalidraw's corner radius rules
+fn corner_radius(element: &Value, width: f64, height: f64) -> String {
+    let Some(roundness) = element.get("roundness").filter(|v| !v.is_null()) else {
+        return String::new();
+    };
+    let size = width.abs().min(height.abs());
+    let radius = match roundness.get("type").and_then(|v| v.as_i64()) {
+        // Adaptive: proportional for small shapes, fixed once the shape is large enough
+        Some(3) => {
+            let fixed = roundness
+                .get("value")
+                .and_then(|v| v.as_f64())
+                .unwrap_or(ADAPTIVE_CORNER_RADIUS);
+            if size <= fixed / PROPORTIONAL_CORNER_RATIO {
+                size * PROPORTIONAL_CORNER_RATIO
+            } else {
+                fixed
+            }
+        }
+        // Legacy and proportional roundness scale with the shape
+        _ => size * PROPORTIONAL_CORNER_RATIO,
+    };
+    if radius <= 0.0 {
+        return String::new();
+    }
+    format!(r#" rx="{}" ry="{}""#, radius, radius)
+}
+
+// Split #rrggbbaa into #rrggbb and its alpha, since some SVG renderers ignore the alpha digits.
+// Every other colour is returned unchanged.
+fn split_alpha(color: &str) -> (&str, Option<f64>) {
+    match color.strip_prefix('#') {
+        Some(hex) if hex.len() == 8 && hex.is_ascii() => match u8::from_str_radix(&hex[6..], 16) {
+            Ok(alpha) => (&color[..7], Some(f64::from(alpha) / 255.0)),
+            Err(_) => (color, None),
+        },
+        _ => (color, None),
+    }
+}
+
+// ` stroke-opacity="…"`-style attribute for a split-off alpha, or nothing when fully opaque
+fn opacity_attribute(name: &str, alpha: Option<f64>) -> String {
+    match alpha {
+        Some(alpha) if alpha < 1.0 => {
+            format!(r#" {}="{}""#, name, (alpha * 1000.0).round() / 1000.0)
+        }
+        _ => String::new(),
+    }
+}
+
+// SVG font-family of a text element: numbered fonts by name, a family name as given, and
+// Virgil when none is set, as in Excalidraw
+fn font_family(element: &Value) -> Cow<'static, str> {
+    match element.get("fontFamily") {
+        Some(Value::String(family)) if !family.trim().is_empty() => {
+            Cow::Owned(escape_xml(family.trim()))
+        }
+        Some(Value::Number(id)) => Cow::Borrowed(
+            EXCALIDRAW_FONTS
+                .iter()
+                .find(|(font_id, _)| id.as_i64() == Some(*font_id))
+                .map_or("sans-serif", |(_, name)| name),
+        ),
+        _ => Cow::Borrowed("Virgil"),
+    }
+}
+
+// Freedraws made with the highlighter tool, marked by their stroke style or a `highlighter` flag
+fn is_highlighter(element: &Value) -> bool {
+    element.get("strokeStyle").and_then(|v| v.as_str()) == Some("highlighter")
+        || element
+            .get("highlighter")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+}
+
+// stroke-dasharray attribute for dashed and dotted strokes, scaled so thick lines keep their rhythm
+fn stroke_dasharray(element: &Value, stroke_width: f64) -> String {
+    let (dash, gap) = match element.get("strokeStyle").and_then(|v| v.as_str()) {
+        Some("dashed") => (8.0, 4.0),
+        Some("dotted") => (2.0, 4.0),
+        _ => return String::new(),
+    };
+    format!(
+        r#" stroke-dasharray="{},{}""#,
+        dash * stroke_width,
+        gap * stroke_width
+    )
+}
+
+// Pattern id, fill style and colour for elements filled with hachure or cross-hatch lines
+fn hatch_fill(element: &Value) -> Option<(String, &str, &str)> {
+    let style = element.get("fillStyle")?.as_str()?;
+    let color = element.get("backgroundColor")?.as_str()?;
+    if !matches!(style, "hachure" | "cross-hatch") || color == "transparent" {
+        return None;
+    }
+    let color_id: String = color
+        .trim_start_matches('#')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    Some((format!("{}-{}", style, color_id), style, color))
+}
+
+// Diagonal lines in the element's background colour; cross-hatch adds a perpendicular set
+fn hatch_pattern(id: &str, style: &str, color: &str) -> String {
+    let (color, alpha) = split_alpha(color);
+    let opacity = opacity_attribute("stroke-opacity", alpha);
+    let mut lines = format!(
+        r#"<line x1="0" y1="0" x2="0" y2="8" stroke="{}"{} stroke-width="1"/>"#,
+        color, opacity
+    );
+    if style == "cross-hatch" {
+        lines.push_str(&format!(
+            r#"<line x1="0" y1="0" x2="8" y2="0" stroke="{}"{} stroke-width="1"/>"#,
+            color, opacity
+        ));
+    }
+    format!(
+        r#"<pattern id="{}" width="8" height="8" patternUnits="userSpaceOnUse" patternTransform="rotate(-45)">{}</pattern>"#,
+        id, lines
+    )
+}
+
+fn format_points(points: &[(f64, f64)]) -> String {
+    points
+        .iter()
+        .map(|(px, py)| format!("{},{}", px, py))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Whether a line encloses an area: flagged as a polygon, or ending where it starts
+fn is_closed_line(element: &Value, points: &[(f64, f64)]) -> bool {
+    let flagged = element
+        .get("polygon")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    if flagged {
+        return points.len() >= 3;
+    }
+    // Three distinct corners plus the repeated start
+    points.len() >= 4 && points.first() == points.last()
+}
+
+// Path through the points smoothed at the midpoints: each inner point becomes the control
+// point of a quadratic curve, so both ends keep the direction of their first and last segments
+fn smooth_path(points: &[(f64, f64)]) -> String {
+    let mut path = String::new();
+    let Some(((start_x, start_y), rest)) = points.split_first() else {
+        return path;
+    };
+    path.push_str(&format!("M {} {}", start_x, start_y));
+    for pair in rest.windows(2) {
+        let (control_x, control_y) = pair[0];
+        let (next_x, next_y) = pair[1];
+        path.push_str(&format!(
+            " Q {} {} {} {}",
+            control_x,
+            control_y,
+            (control_x + next_x) / 2.0,
+            (control_y + next_y) / 2.0
+        ));
+    }
+    if let Some((end_x, end_y)) = rest.last() {
+        path.push_str(&format!(" L {} {}", end_x, end_y));
+    }
+    path
+}
+
+// Append a single element to canvas
+#[utoipa::path(
+    post,
+    path = "/canvas/element",
+    tag = "elements",
+    request_body = AddElementPayload,
+    responses(
+        (status = 200, description = "Element appended"),
+        (status = 400, description = "Element failed validation"),
+        (status = 409, description = "An element with this id already exists")
+    )
+)]
+// The id isn't validated yet, so the span may carry an empty one for rejected elements
+#[instrument(
+    name = "element_add",
+    skip_all,
+    fields(
+        scene = %scene,
+        id = payload.element.get("id").and_then(|v| v.as_str()).unwrap_or_default(),
+    )
+)]
+async fn add_element(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<AddElementPayload>,
+) -> Result<Json<Value>, ApiError> {
+    validate_element(&payload.element).map_err(ApiError::InvalidElements)?;
+    // Validation guarantees a string id
+    let element_id = payload.element["id"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+
+    info!(
+        target: "canvas_element",
+        action = "add_element_start",
+        element_id = %element_id,
+        "添加元素"
+    );
+
+    let element = {
+        let mut scenes = state.scenes.write().await;
+        let existing = stored_elements(&scenes, &scene);
+        let exists = existing
+            .iter()
+            .any(|element| element.get("id").and_then(|v| v.as_str()) == Some(&element_id));
+        if exists {
+            return Err(ApiError::ElementExists(element_id));
+        }
+        check_element_limit(&state, existing.len(), existing.len() + 1)?;
+
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        let mut element = payload.element.clone();
+        stamp_element(&mut element, None);
+        elements_mut(canvas).push(element.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::AddElement);
+        element
+    };
+
+    let element_payload = ElementEventPayload {
+        id: element_id.clone(),
+        element: Some(element),
+    };
+
+    // Only the new element goes to the frontend, which adds it to what it has
+    emit_event(&state, &scene, EVENT_ELEMENT_ADDED, &element_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "add_element_success",
+        element_id = %element_id,
+        "元素已添加"
+    );
+    Ok(Json(json!({"success": true, "id": element_id})))
+}
+
+// Apply several add/update/remove operations atomically
+async fn batch_elements(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<BatchPayload>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_element",
+        action = "batch_start",
+        op_count = payload.ops.len(),
+        "开始批量操作"
+    );
+
+    let updated_elements = {
+        let mut scenes = state.scenes.write().await;
+
+        // Work on a copy so a failing operation leaves the canvas untouched
+        let mut elements: Vec<Value> = scenes
+            .get(&scene)
+            .and_then(|canvas| canvas.elements.as_ref())
+            .and_then(|elements| elements.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let current = elements.len();
+        for (index, op) in payload.ops.iter().enumerate() {
+            apply_batch_operation(&mut elements, op)
+                .map_err(|reason| ApiError::BatchFailed { index, reason })?;
+        }
+        check_element_limit(&state, current, elements.len())?;
+
+        let updated_elements = Value::Array(elements);
+        let canvas = scenes
+            .entry(scene.clone())
+            .or_insert_with(CanvasData::empty);
+        canvas.elements = Some(updated_elements.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::Batch);
+        updated_elements
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(updated_elements),
+        app_state: None,
+        files: None,
+    };
+
+    // Emit a single update for the whole batch
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "batch_success",
+        op_count = payload.ops.len(),
+        "批量操作已完成"
+    );
+    Ok(Json(json!({"success": true, "applied": payload.ops.len()})))
+}
+
+// Append elements sent as NDJSON, one element per line, under a single lock and with one
+// event. Lines that don't parse, fail validation or reuse an id are skipped and reported
+// by their 1-based line number; blank lines are ignored.
+async fn stream_elements(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    body: Bytes,
+) -> Result<Json<Value>, ApiError> {
+    let body = std::str::from_utf8(&body)
+        .map_err(|err| ApiError::InvalidBody(format!("Body is not valid UTF-8: {}", err)))?;
+    info!(
+        target: "canvas_element",
+        action = "stream_elements_start",
+        bytes = body.len(),
+        "开始批量追加元素"
+    );
+
+    let (updated_elements, added, skipped) = {
+        let mut scenes = state.scenes.write().await;
+        let existing = stored_elements(&scenes, &scene);
+        let mut ids: HashSet<String> = existing
+            .iter()
+            .filter_map(|element| element.get("id").and_then(|v| v.as_str()))
+            .map(str::to_string)
+            .collect();
+        let mut new_elements = Vec::new();
+        let mut skipped = Vec::new();
+        for (index, line) in body.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let element = serde_json::from_str::<Value>(line)
+                .ok()
+                .filter(|element| validate_element(element).is_ok());
+            // Validation guarantees a string id
+            match element {
+                Some(mut element)
+                    if ids.insert(element["id"].as_str().unwrap_or_default().to_string()) =>
+                {
+                    stamp_element(&mut element, None);
+                    new_elements.push(element);
+                }
+                _ => skipped.push(index + 1),
+            }
+        }
+
+        let current = existing.len();
+        check_element_limit(&state, current, current + new_elements.len())?;
+        let added = new_elements.len();
+        // Nothing is stored, and no scene created, when every line was skipped
+        let updated_elements = (added > 0).then(|| {
+            let canvas = scenes
+                .entry(scene.clone())
+                .or_insert_with(CanvasData::empty);
+            let elements_array = elements_mut(canvas);
+            elements_array.extend(new_elements);
+            let updated_elements = Value::Array(elements_array.clone());
+            canvas.updated_at = chrono::Utc::now().to_rfc3339();
+            commit_canvas(&state, &scene, canvas, ChangeKind::Stream);
+            updated_elements
+        });
+        (updated_elements, added, skipped)
+    };
+
+    if let Some(updated_elements) = updated_elements {
+        let draw_payload = DrawPayload {
+            elements: Some(updated_elements),
+            app_state: None,
+            files: None,
+        };
+        emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+    }
+
+    info!(
+        target: "canvas_element",
+        action = "stream_elements_success",
+        added = added,
+        skipped = skipped.len(),
+        "批量追加元素完成"
+    );
+    Ok(Json(json!({"added": added, "skipped": skipped})))
+}
+
+// A scene's elements as stored, empty for unknown scenes or a missing array. Checks run
+// against this so a rejected request leaves the canvas exactly as it was.
+fn stored_elements<'a>(scenes: &'a HashMap<String, CanvasData>, scene: &str) -> &'a [Value] {
+    scenes
+        .get(scene)
+        .and_then(|canvas| canvas.elements.as_ref())
+        .and_then(|elements| elements.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+}
+
+// The canvas's elements to append to, starting an array if there is none yet
+fn elements_mut(canvas: &mut CanvasData) -> &mut Vec<Value> {
+    let elements = canvas.elements.get_or_insert_with(|| json!([]));
+    if !elements.is_array() {
+        *elements = json!([]);
+    }
+    elements.as_array_mut().unwrap()
+}
+
+fn apply_batch_operation(elements: &mut Vec<Value>, op: &BatchOperation) -> Result<(), String> {
+    let find = |elements: &[Value], id: &str| {
+        elements
+            .iter()
+            .position(|element| element.get("id").and_then(|v| v.as_str()) == Some(id))
+    };
+
+    match op.action {
+        BatchAction::Add => {
+            let element = op.element.as_ref().ok_or("add requires an element")?;
+            validate_element(element).map_err(|problems| problems.join("; "))?;
+            let id = element["id"].as_str().unwrap_or_default();
+            if find(elements, id).is_some() {
+                return Err(format!("Element with ID '{}' already exists", id));
+            }
+            let mut element = element.clone();
+            stamp_element(&mut element, None);
+            elements.push(element);
+        }
+        BatchAction::Update => {
+            let element = op.element.as_ref().ok_or("update requires an element")?;
+            validate_element(element).map_err(|problems| problems.join("; "))?;
+            let id = op
+                .id
+                .as_deref()
+                .unwrap_or_else(|| element["id"].as_str().unwrap_or_default());
+            let position =
+                find(elements, id).ok_or_else(|| format!("Element with ID '{}' not found", id))?;
+            let mut element = element.clone();
+            stamp_element(&mut element, Some(&elements[position]));
+            elements[position] = element;
+        }
+        BatchAction::Remove => {
+            let id = op.id.as_deref().ok_or("remove requires an id")?;
+            let position =
+                find(elements, id).ok_or_else(|| format!("Element with ID '{}' not found", id))?;
+            elements.remove(position);
+        }
+    }
+    Ok(())
+}
+
+// List elements, optionally only those intersecting a region of the canvas
+async fn query_elements(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    Query(query): Query<RegionQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let region = match (query.x, query.y, query.w, query.h) {
+        (Some(x), Some(y), Some(w), Some(h)) => {
+            Some((x.min(x + w), y.min(y + h), x.max(x + w), y.max(y + h)))
+        }
+        (None, None, None, None) => None,
+        _ => return Err(ApiError::IncompleteRegion),
+    };
+
+    let scenes = state.scenes.read().await;
+    let canvas = scenes
+        .get(&scene)
+        .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+    let elements = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    let matching: Vec<&Value> = match region {
+        Some((left, top, right, bottom)) => elements
+            .iter()
+            .filter(|element| match element_rect(element) {
+                Some((el_left, el_top, el_right, el_bottom)) => {
+                    el_left <= right && el_right >= left && el_top <= bottom && el_bottom >= top
+                }
+                None => query.include_unplaced,
+            })
+            .collect(),
+        None => elements.iter().collect(),
+    };
+
+    Ok(Json(json!({
+        "elements": matching,
+        "total": elements.len(),
+    })))
+}
+
+// Element version from an If-Match header, accepting both `3` and `"3"`
+fn if_match_version(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get(header::IF_MATCH)?
+        .to_str()
+        .ok()?
+        .trim()
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .ok()
+}
+
+// Stamp a validated element with the server time of its last change in milliseconds,
+// always moving past the previous stamp so clients can spot changes between polls
+fn stamp_element(element: &mut Value, previous: Option<&Value>) {
+    let now = chrono::Utc::now().timestamp_millis();
+    let stamp = match previous
+        .and_then(|previous| previous.get("updated"))
+        .and_then(|v| v.as_i64())
+    {
+        Some(previous) => now.max(previous.saturating_add(1)),
+        None => now,
+    };
+    element["updated"] = json!(stamp);
+}
+
+// Find elements whose text contains the query, ignoring case
+async fn search_elements(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let needle = query.q.trim().to_lowercase();
+    if needle.is_empty() {
+        return Err(ApiError::EmptySearch);
+    }
+
+    let scenes = state.scenes.read().await;
+    let canvas = scenes
+        .get(&scene)
+        .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+    let results: Vec<Value> = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|element| {
+            element.get("type").and_then(|v| v.as_str()) == Some(query.element_type.as_str())
+        })
+        .filter_map(|element| {
+            let text = element.get("text").and_then(|v| v.as_str())?;
+            text.to_lowercase().contains(&needle).then(|| {
+                json!({
+                    "id": element.get("id"),
+                    "type": element.get("type"),
+                    "x": element.get("x"),
+                    "y": element.get("y"),
+                    "text": text,
+                })
+            })
+        })
+        .collect();
+
+    Ok(Json(json!({
+        "results": results,
+        "count": results.len(),
+    })))
+}
+
+// Get element by ID
+#[utoipa::path(
+    get,
+    path = "/canvas/element/{id}",
+    tag = "elements",
+    params(("id" = String, Path, description = "Element id")),
+    responses(
+        (status = 200, description = "The element"),
+        (status = 404, description = "Unknown scene or element")
+    )
+)]
+#[instrument(name = "element_get", skip_all, fields(scene = %scene, id = %element_id))]
+async fn get_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+) -> Result<Json<Value>, ApiError> {
+    let scenes = state.scenes.read().await;
+    let canvas = scenes
+        .get(&scene)
+        .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+    let element = canvas
+        .elements
+        .as_ref()
+        .and_then(|elements| elements.as_array())
+        .and_then(|elements_array| {
+            elements_array
+                .iter()
+                .find(|element| element.get("id").and_then(|v| v.as_str()) == Some(&element_id))
+        });
+
+    match element {
+        Some(element) => Ok(Json(json!({"element": element}))),
+        None => Err(ApiError::ElementNotFound(element_id)),
+    }
+}
+
+// Remove element by ID
+#[utoipa::path(
+    delete,
+    path = "/canvas/element/{id}",
+    tag = "elements",
+    params(("id" = String, Path, description = "Element id")),
+    responses(
+        (status = 200, description = "Element removed"),
+        (status = 404, description = "Unknown scene or element")
+    )
+)]
+#[instrument(name = "element_remove", skip_all, fields(scene = %scene, id = %element_id))]
+async fn remove_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_element",
+        action = "remove_element_start",
+        element_id = %element_id,
+        "🗑️ 移除元素"
+    );
+
+    let (removed, cascaded) = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+        let (removed, others_changed) = remove_with_bound_text(elements_array, &element_id);
+        if removed.is_empty() {
+            return Err(ApiError::ElementNotFound(element_id));
+        }
+        // Anything beyond the one element changing needs the whole set redrawn
+        let cascaded =
+            (removed.len() > 1 || others_changed).then(|| Value::Array(elements_array.clone()));
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::RemoveElement);
+        (removed, cascaded)
+    };
+
+    // Emit update event to frontend
+    match cascaded {
+        Some(elements) => {
+            let draw_payload = DrawPayload {
+                elements: Some(elements),
+                app_state: None,
+                files: None,
+            };
+            emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+        }
+        None => {
+            let element_payload = ElementEventPayload {
+                id: element_id.clone(),
+                element: None,
+            };
+            emit_event(&state, &scene, EVENT_ELEMENT_REMOVED, &element_payload).await?;
+        }
+    }
+
+    info!(
+        target: "canvas_element",
+        action = "remove_element_success",
+        element_id = %element_id,
+        removed = removed.len(),
+        "✅ 元素已移除"
+    );
+    Ok(Json(json!({
+        "success": true,
+        "message": format!("Element '{}' removed", element_id),
+        "removed": removed,
+    })))
+}
+
+// Remove an element and, as Excalidraw does, the text bound to it: labels listed in its
+// boundElements and any text whose containerId points at it. Bound arrows are kept.
+// Removed ids are dropped from the boundElements of what remains; returns the removed ids,
+// in canvas order, and whether any remaining element had to change.
+fn remove_with_bound_text(elements: &mut Vec<Value>, id: &str) -> (Vec<String>, bool) {
+    fn element_id(element: &Value) -> Option<&str> {
+        element.get("id").and_then(|v| v.as_str())
+    }
+    let Some(target) = elements
+        .iter()
+        .find(|element| element_id(element) == Some(id))
+    else {
+        return (Vec::new(), false);
+    };
+
+    let mut doomed = HashSet::from([id.to_string()]);
+    let labels = target
+        .get("boundElements")
+        .and_then(|v| v.as_array())
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .filter(|bound| bound.get("type").and_then(|v| v.as_str()) == Some("text"));
+    doomed.extend(labels.filter_map(|bound| element_id(bound).map(str::to_string)));
+    doomed.extend(
+        elements
+            .iter()
+            .filter(|element| element.get("containerId").and_then(|v| v.as_str()) == Some(id))
+            .filter_map(|element| element_id(element).map(str::to_string)),
+    );
+
+    let mut removed = Vec::new();
+    elements.retain(|element| match element_id(element) {
+        Some(removed_id) if doomed.contains(removed_id) => {
+            removed.push(removed_id.to_string());
+            false
+        }
+        _ => true,
+    });
+
+    let mut others_changed = false;
+    for element in elements.iter_mut() {
+        let references_removed = element
+            .get("boundElements")
+            .and_then(|v| v.as_array())
+            .is_some_and(|bound| {
+                bound
+                    .iter()
+                    .any(|bound| element_id(bound).is_some_and(|id| doomed.contains(id)))
+            });
+        if !references_removed {
+            continue;
+        }
+        let previous = element.clone();
+        if let Some(bound) = element
+            .get_mut("boundElements")
+            .and_then(|v| v.as_array_mut())
+        {
+            bound.retain(|bound| !element_id(bound).is_some_and(|id| doomed.contains(id)));
+        }
+        stamp_element(element, Some(&previous));
+        others_changed = true;
+    }
+    (removed, others_changed)
+}
+
+// Update element by ID
+#[utoipa::path(
+    put,
+    path = "/canvas/element/{id}",
+    tag = "elements",
+    params(
+        ("id" = String, Path, description = "Element id"),
+        ("If-Match" = Option<i64>, Header, description = "Expected element version")
+    ),
+    request_body = UpdateElementPayload,
+    responses(
+        (status = 200, description = "Element replaced"),
+        (status = 400, description = "Element failed validation or its id differs from the path"),
+        (status = 404, description = "Unknown scene or element"),
+        (status = 409, description = "Stale version; the body carries the current element")
+    )
+)]
+#[instrument(name = "element_update", skip_all, fields(scene = %scene, id = %element_id))]
+async fn update_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+    headers: HeaderMap,
+    JsonBody(payload): JsonBody<UpdateElementPayload>,
+) -> Result<Json<Value>, ApiError> {
+    validate_element(&payload.element).map_err(ApiError::InvalidElements)?;
+    // A different id would rename the element onto another one, which dedupe then drops
+    if payload.element.get("id").and_then(|v| v.as_str()) != Some(&element_id) {
+        return Err(ApiError::InvalidElements(vec![
+            "'id' cannot be changed".to_string()
+        ]));
+    }
+    let expected_version = payload.version.or_else(|| if_match_version(&headers));
+
+    info!(
+        target: "canvas_element",
+        action = "update_element_start",
+        element_id = %element_id,
+        element = %payload.element,
+        "🔄 更新元素"
+    );
+
+    let mut updated_elements = Vec::new();
+    let mut updated_element = None;
+
+    {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        if let Some(elements) = &canvas.elements {
+            if let Some(elements_array) = elements.as_array() {
+                for element in elements_array {
+                    if let Some(id) = element.get("id").and_then(|v| v.as_str()) {
+                        if id == element_id {
+                            if let Some(expected) = expected_version {
+                                if element.get("version").and_then(|v| v.as_i64()) != Some(expected)
+                                {
+                                    return Err(ApiError::VersionConflict {
+                                        id: element_id,
+                                        expected,
+                                        current: element.clone(),
+                                    });
+                                }
+                            }
+                            let mut updated = payload.element.clone();
+                            stamp_element(&mut updated, Some(element));
+                            updated_elements.push(updated.clone());
+                            updated_element = Some(updated);
+                        } else {
+                            updated_elements.push(element.clone());
+                        }
+                    } else {
+                        updated_elements.push(element.clone());
+                    }
+                }
+            }
+        }
+        // Write back under the same guard so concurrent edits aren't lost
+        if updated_element.is_some() {
+            canvas.elements = Some(json!(updated_elements));
+            canvas.updated_at = chrono::Utc::now().to_rfc3339();
+            commit_canvas(&state, &scene, canvas, ChangeKind::UpdateElement);
+        }
+    }
+
+    let Some(updated_element) = updated_element else {
+        return Err(ApiError::ElementNotFound(element_id));
+    };
+
+    let element_payload = ElementEventPayload {
+        id: element_id.clone(),
+        element: Some(updated_element),
+    };
+
+    // Emit update event to frontend
+    emit_event(&state, &scene, EVENT_ELEMENT_UPDATED, &element_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "update_element_success",
+        element_id = %element_id,
+        "✅ 元素已更新"
+    );
+    Ok(Json(
+        json!({"success": true, "message": format!("Element '{}' updated", element_id)}),
+    ))
+}
+
+// Merge some fields into an element; `null` removes a field
+#[instrument(name = "element_patch", skip_all, fields(scene = %scene, id = %element_id))]
+async fn patch_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+    headers: HeaderMap,
+    JsonBody(patch): JsonBody<Value>,
+) -> Result<Json<Value>, ApiError> {
+    let Value::Object(fields) = patch else {
+        return Err(ApiError::InvalidElements(vec![
+            "patch must be a JSON object".to_string(),
+        ]));
+    };
+    if fields
+        .get("id")
+        .is_some_and(|id| id.as_str() != Some(&element_id))
+    {
+        return Err(ApiError::InvalidElements(vec![
+            "'id' cannot be changed".to_string()
+        ]));
+    }
+    let expected_version = if_match_version(&headers);
+
+    info!(
+        target: "canvas_element",
+        action = "patch_element_start",
+        element_id = %element_id,
+        fields = fields.len(),
+        "修改元素字段"
+    );
+
+    let patched = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+        let element = elements_array
+            .iter_mut()
+            .find(|element| element.get("id").and_then(|v| v.as_str()) == Some(&element_id))
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+        if let Some(expected) = expected_version {
+            if element.get("version").and_then(|v| v.as_i64()) != Some(expected) {
+                return Err(ApiError::VersionConflict {
+                    id: element_id,
+                    expected,
+                    current: element.clone(),
+                });
+            }
+        }
+
+        let mut patched = element.clone();
+        if let Some(object) = patched.as_object_mut() {
+            for (field, value) in fields {
+                if value.is_null() {
+                    object.remove(&field);
+                } else {
+                    object.insert(field, value);
+                }
+            }
+        }
+        validate_element(&patched).map_err(ApiError::InvalidElements)?;
+        stamp_element(&mut patched, Some(element));
+        *element = patched.clone();
+
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::PatchElement);
+        patched
+    };
+
+    let element_payload = ElementEventPayload {
+        id: element_id.clone(),
+        element: Some(patched),
+    };
+
+    // Emit update event to frontend
+    emit_event(&state, &scene, EVENT_ELEMENT_UPDATED, &element_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "patch_element_success",
+        element_id = %element_id,
+        "元素字段已修改"
+    );
+    Ok(Json(
+        json!({"success": true, "message": format!("Element '{}' updated", element_id)}),
+    ))
+}
+
+// Append a copy of an element under a fresh id, optionally offset from the original
+#[instrument(name = "element_duplicate", skip_all, fields(scene = %scene, id = %element_id))]
+async fn duplicate_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+    body: Bytes,
+) -> Result<Json<Value>, ApiError> {
+    // No body means no offset, but a body that is sent has to parse
+    let offset = if body.iter().all(u8::is_ascii_whitespace) {
+        DuplicatePayload::default()
+    } else {
+        Json::<DuplicatePayload>::from_bytes(&body)?.0
+    };
+    info!(
+        target: "canvas_element",
+        action = "duplicate_element_start",
+        element_id = %element_id,
+        dx = offset.dx,
+        dy = offset.dy,
+        "复制元素"
+    );
+
+    let duplicate = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+        let mut duplicate = elements_array
+            .iter()
+            .find(|element| element.get("id").and_then(|v| v.as_str()) == Some(&element_id))
+            .cloned()
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+
+        check_element_limit(&state, elements_array.len(), elements_array.len() + 1)?;
+        duplicate["id"] = json!(uuid::Uuid::new_v4().to_string());
+        for (field, offset) in [("x", offset.dx), ("y", offset.dy)] {
+            if let Some(value) = duplicate.get(field).and_then(|v| v.as_f64()) {
+                duplicate[field] = json!(value + offset);
+            }
+        }
+        stamp_element(&mut duplicate, None);
+        elements_array.push(duplicate.clone());
+
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::DuplicateElement);
+        duplicate
+    };
+
+    let element_payload = ElementEventPayload {
+        id: duplicate["id"].as_str().unwrap_or_default().to_string(),
+        element: Some(duplicate.clone()),
+    };
+
+    // Emit update event to frontend
+    emit_event(&state, &scene, EVENT_ELEMENT_ADDED, &element_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "duplicate_element_success",
+        element_id = %element_id,
+        duplicate_id = %duplicate["id"],
+        "元素已复制"
+    );
+    Ok(Json(json!({"success": true, "element": duplicate})))
+}
+
+// Move an element within the array, which is also its rendering order
+#[instrument(name = "element_reorder", skip_all, fields(scene = %scene, id = %element_id))]
+async fn reorder_element(
+    State(state): State<AppState>,
+    Path(ElementPath {
+        scene,
+        id: element_id,
+    }): Path<ElementPath>,
+    JsonBody(payload): JsonBody<OrderPayload>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_element",
+        action = "reorder_element_start",
+        element_id = %element_id,
+        order = %payload.action,
+        "调整元素层级"
+    );
+
+    let (updated_elements, index) = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+        let position = elements_array
+            .iter()
+            .position(|element| element.get("id").and_then(|v| v.as_str()) == Some(&element_id))
+            .ok_or_else(|| ApiError::ElementNotFound(element_id.clone()))?;
+
+        let index = match payload.action.as_str() {
+            "front" => {
+                let element = elements_array.remove(position);
+                elements_array.push(element);
+                elements_array.len() - 1
+            }
+            "back" => {
+                let element = elements_array.remove(position);
+                elements_array.insert(0, element);
+                0
+            }
+            "forward" if position + 1 < elements_array.len() => {
+                elements_array.swap(position, position + 1);
+                position + 1
+            }
+            "backward" if position > 0 => {
+                elements_array.swap(position, position - 1);
+                position - 1
+            }
+            // Already at the top or bottom
+            "forward" | "backward" => position,
+            _ => return Err(ApiError::InvalidOrderAction(payload.action)),
+        };
+
+        let updated_elements = Value::Array(elements_array.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::ReorderElement);
+        (updated_elements, index)
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(updated_elements),
+        app_state: None,
+        files: None,
+    };
+
+    // Emit update event to frontend
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "reorder_element_success",
+        element_id = %element_id,
+        index = index,
+        "元素层级已调整"
+    );
+    Ok(Json(json!({"success": true, "index": index})))
+}
+
+fn in_group(element: &Value, group_id: &str) -> bool {
+    element
+        .get("groupIds")
+        .and_then(|v| v.as_array())
+        .is_some_and(|group_ids| group_ids.iter().any(|id| id.as_str() == Some(group_id)))
+}
+
+// Remove every element belonging to a group
+#[instrument(name = "group_remove", skip_all, fields(scene = %scene, group_id = %group_id))]
+async fn remove_group(
+    State(state): State<AppState>,
+    Path(GroupPath {
+        scene,
+        id: group_id,
+    }): Path<GroupPath>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_element",
+        action = "remove_group_start",
+        group_id = %group_id,
+        "移除元素组"
+    );
+
+    let (updated_elements, removed) = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::GroupNotFound(group_id.clone()))?;
+
+        let before = elements_array.len();
+        elements_array.retain(|element| !in_group(element, &group_id));
+        let removed = before - elements_array.len();
+        if removed == 0 {
+            return Err(ApiError::GroupNotFound(group_id));
+        }
+
+        let updated_elements = Value::Array(elements_array.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::RemoveGroup);
+        (updated_elements, removed)
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(updated_elements),
+        app_state: None,
+        files: None,
+    };
+
+    // Emit a single update for the whole group
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "remove_group_success",
+        group_id = %group_id,
+        removed = removed,
+        "元素组已移除"
+    );
+    Ok(Json(json!({"success": true, "removed": removed})))
+}
+
+// Move every element of a group by the same offset
+#[instrument(name = "group_translate", skip_all, fields(scene = %scene, group_id = %group_id))]
+async fn translate_group(
+    State(state): State<AppState>,
+    Path(GroupPath {
+        scene,
+        id: group_id,
+    }): Path<GroupPath>,
+    JsonBody(payload): JsonBody<TranslatePayload>,
+) -> Result<Json<Value>, ApiError> {
+    info!(
+        target: "canvas_element",
+        action = "translate_group_start",
+        group_id = %group_id,
+        dx = payload.dx,
+        dy = payload.dy,
+        "移动元素组"
+    );
+
+    let (updated_elements, moved) = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let elements_array = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+            .ok_or_else(|| ApiError::GroupNotFound(group_id.clone()))?;
+
+        let mut moved = 0;
+        for element in elements_array
+            .iter_mut()
+            .filter(|element| in_group(element, &group_id))
+        {
+            let previous = element.clone();
+            for (field, offset) in [("x", payload.dx), ("y", payload.dy)] {
+                if let Some(value) = element.get(field).and_then(|v| v.as_f64()) {
+                    element[field] = json!(value + offset);
+                }
+            }
+            stamp_element(element, Some(&previous));
+            moved += 1;
+        }
+        if moved == 0 {
+            return Err(ApiError::GroupNotFound(group_id));
+        }
+
+        let updated_elements = Value::Array(elements_array.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::TranslateGroup);
+        (updated_elements, moved)
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(updated_elements),
+        app_state: None,
+        files: None,
+    };
+
+    // Emit a single update for the whole group
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_element",
+        action = "translate_group_success",
+        group_id = %group_id,
+        moved = moved,
+        "元素组已移动"
+    );
+    Ok(Json(json!({"success": true, "moved": moved})))
+}
+
+// Move and rescale the whole canvas, e.g. to fix an import placed at the wrong origin
+async fn transform_canvas(
+    State(state): State<AppState>,
+    Path(ScenePath { scene }): Path<ScenePath>,
+    JsonBody(payload): JsonBody<TransformPayload>,
+) -> Result<Json<Value>, ApiError> {
+    if !(payload.scale.is_finite() && payload.scale > 0.0) {
+        return Err(ApiError::InvalidTransformScale(payload.scale));
+    }
+    info!(
+        target: "canvas_update",
+        action = "transform_canvas_start",
+        scene = %scene,
+        dx = payload.dx,
+        dy = payload.dy,
+        scale = payload.scale,
+        "变换整个画布"
+    );
+
+    let (updated_elements, transformed) = {
+        let mut scenes = state.scenes.write().await;
+        let canvas = scenes
+            .get_mut(&scene)
+            .ok_or_else(|| ApiError::SceneNotFound(scene.clone()))?;
+        let Some(elements_array) = canvas
+            .elements
+            .as_mut()
+            .and_then(|elements| elements.as_array_mut())
+        else {
+            return Ok(Json(json!({"success": true, "transformed": 0})));
+        };
+
+        for element in elements_array.iter_mut() {
+            let previous = element.clone();
+            transform_element(element, &payload);
+            stamp_element(element, Some(&previous));
+        }
+
+        let transformed = elements_array.len();
+        let updated_elements = Value::Array(elements_array.clone());
+        canvas.updated_at = chrono::Utc::now().to_rfc3339();
+        commit_canvas(&state, &scene, canvas, ChangeKind::Transform);
+        (updated_elements, transformed)
+    };
+
+    let draw_payload = DrawPayload {
+        elements: Some(updated_elements),
+        app_state: None,
+        files: None,
+    };
+    emit_event(&state, &scene, EVENT_DRAW, &draw_payload).await?;
+
+    info!(
+        target: "canvas_update",
+        action = "transform_canvas_success",
+        scene = %scene,
+        transformed = transformed,
+        "画布已变换"
+    );
+    Ok(Json(json!({"success": true, "transformed": transformed})))
+}
+
+// Points are relative to the element's x/y, so they only scale
+fn transform_element(element: &mut Value, transform: &TransformPayload) {
+    for (field, offset) in [("x", transform.dx), ("y", transform.dy)] {
+        if let Some(value) = element.get(field).and_then(|v| v.as_f64()) {
+            element[field] = json!(value * transform.scale + offset);
+        }
+    }
+    for field in ["width", "height", "fontSize", "strokeWidth"] {
+        if let Some(value) = element.get(field).and_then(|v| v.as_f64()) {
+            element[field] = json!(value * transform.scale);
+        }
+    }
+    if let Some(points) = element
+        .get_mut("points")
+        .and_then(|points| points.as_array_mut())
+    {
+        for point in points.iter_mut().filter_map(|point| point.as_array_mut()) {
+            for coordinate in point.iter_mut() {
+                if let Some(value) = coordinate.as_f64() {
+                    *coordinate = json!(value * transform.scale);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    // An SVG that draws nothing, so every rendered pixel starts out transparent
+    const EMPTY_SVG: &str =
+        r#"<svg width="4" height="4" viewBox="0 0 4 4" xmlns="http://www.w3.org/2000/svg"></svg>"#;
+
+    fn rectangle(id: &str) -> Value {
+        json!({
+            "id": id,
+            "type": "rectangle",
+            "x": 10.0,
+            "y": 20.0,
+            "width": 100.0,
+            "height": 50.0,
+            "strokeColor": "#1e1e1e",
+            "backgroundColor": "transparent",
+            "strokeWidth": 2,
+        })
+    }
+
+    async fn error_response(error: ApiError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("error body");
+        (
+            status,
+            serde_json::from_slice(&body).expect("error body is JSON"),
+        )
+    }
+
+    #[tokio::test]
+    async fn missing_element_error_shape() {
+        let (status, body) = error_response(ApiError::ElementNotFound("a".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "code": "element_not_found",
+                    "message": "Element with ID 'a' not found",
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn emit_failure_error_shape() {
+        let (status, body) = error_response(ApiError::EmitFailed(EVENT_DRAW.to_string())).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            body,
+            json!({
+                "error": {
+                    "code": "emit_failed",
+                    "message": "Failed to emit excalidraw_draw event",
+                }
+            })
+        );
+    }
+
+    fn element_svg(element: &Value) -> Option<String> {
+        convert_element_to_svg(element, None, &UnknownTypes::default())
+    }
+
+    #[test]
+    fn freedraw_renders_its_points_translated() {
+        let svg = element_svg(&json!({
+            "id": "f",
+            "type": "freedraw",
+            "x": 10.0,
+            "y": 20.0,
+            "width": 20.0,
+            "height": 5.0,
+            "strokeColor": "#1e1e1e",
+            "backgroundColor": "transparent",
+            "strokeWidth": 2,
+            "points": [[0.0, 0.0], [5.0, 5.0], [20.0, 0.0]],
+        }))
+        .expect("freedraw renders");
+        assert!(svg.starts_with("<polyline"));
+        assert!(svg.contains(r#"points="10,20 15,25 30,20""#));
+        assert!(svg.contains(r#"fill="none""#));
+        assert!(svg.contains(r#"stroke-linecap="round""#));
+    }
+
+    #[test]
+    fn freedraw_without_two_points_is_skipped() {
+        let single = json!({
+            "id": "f",
+            "type": "freedraw",
+            "x": 10.0,
+            "y": 20.0,
+            "width": 0.0,
+            "height": 0.0,
+            "strokeColor": "#1e1e1e",
+            "backgroundColor": "transparent",
+            "strokeWidth": 2,
+            "points": [[0.0, 0.0]],
+        });
+        assert_eq!(element_svg(&single), None);
+
+        let mut missing = single;
+        missing.as_object_mut().unwrap().remove("points");
+        assert_eq!(element_svg(&missing), None);
+    }
+
+    #[test]
+    fn rotation_wraps_the_shape_around_its_center() {
+        let mut rotated = rectangle("r");
+        rotated["angle"] = json!(std::f64::consts::FRAC_PI_2);
+        let svg = element_svg(&rotated).expect("rectangle renders");
+        assert!(svg.starts_with(r#"<g transform="rotate(90 60 45)"><rect"#));
+        assert!(svg.ends_with("</g>"));
+
+        // No rotation and full opacity leave the shape unwrapped
+        let svg = element_svg(&rectangle("r")).expect("rectangle renders");
+        assert!(svg.starts_with("<rect"));
+    }
+
+    #[test]
+    fn opacity_is_scaled_to_a_fraction() {
+        let mut faded = rectangle("e");
+        faded["type"] = json!("ellipse");
+        faded["opacity"] = json!(50);
+        let svg = element_svg(&faded).expect("ellipse renders");
+        assert!(svg.starts_with(r#"<g opacity="0.5"><ellipse"#));
+    }
+
+    #[test]
+    fn png_export_starts_with_png_signature() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let svg = generate_svg(
+            &json!([rectangle("a")]),
+            "white",
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        let bytes =
+            render_raster(&svg, "png", 80, 60, DEFAULT_EXPORT_QUALITY).expect("PNG renders");
+        assert!(bytes.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let image = image::load_from_memory(&bytes).expect("PNG decodes");
+        assert_eq!((image.width(), image.height()), (80, 60));
+    }
+
+    #[test]
+    fn png_keeps_transparent_pixels() {
+        let bytes =
+            render_raster(EMPTY_SVG, "png", 4, 4, DEFAULT_EXPORT_QUALITY).expect("PNG renders");
+        let image = image::load_from_memory(&bytes)
+            .expect("PNG decodes")
+            .to_rgba8();
+        assert!(image.pixels().all(|pixel| pixel[3] == 0));
+    }
+
+    #[test]
+    fn jpeg_flattens_transparent_pixels_to_white() {
+        let bytes =
+            render_raster(EMPTY_SVG, "jpeg", 4, 4, DEFAULT_EXPORT_QUALITY).expect("JPEG renders");
+        assert!(bytes.starts_with(&[0xFF, 0xD8, 0xFF]));
+
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg)
+            .expect("JPEG decodes")
+            .to_rgb8();
+        assert!(image
+            .pixels()
+            .all(|pixel| pixel.0.iter().all(|&channel| channel >= 250)));
+    }
+
+    #[test]
+    fn webp_export_is_a_riff_webp_file() {
+        let bytes =
+            render_raster(EMPTY_SVG, "webp", 4, 4, DEFAULT_EXPORT_QUALITY).expect("WebP renders");
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(&bytes[8..12], b"WEBP");
+    }
+
+    #[test]
+    fn raster_rejects_unknown_formats() {
+        assert!(render_raster(EMPTY_SVG, "gif", 4, 4, DEFAULT_EXPORT_QUALITY).is_err());
+    }
+
+    #[test]
+    fn diamond_points_are_edge_midpoints() {
+        let mut diamond = rectangle("d");
+        diamond["type"] = json!("diamond");
+        diamond["backgroundColor"] = json!("#ffc9c9");
+        let svg = element_svg(&diamond).expect("diamond renders");
+        assert!(svg.starts_with("<polygon"));
+        assert!(svg.contains(r#"points="60,20 110,45 60,70 10,45""#));
+        assert!(svg.contains(r##"fill="#ffc9c9""##));
+    }
+
+    #[test]
+    fn bounds_cover_every_placed_element() {
+        let mut far = rectangle("far");
+        far["x"] = json!(500.0);
+        far["y"] = json!(500.0);
+        let mut negative = rectangle("negative");
+        negative["x"] = json!(-40.0);
+        negative["y"] = json!(-10.0);
+        let unplaced = json!({ "id": "u", "type": "rectangle" });
+
+        assert_eq!(
+            element_bounds(&json!([far.clone()])),
+            Some((500.0, 500.0, 600.0, 550.0))
+        );
+        assert_eq!(
+            element_bounds(&json!([far, negative, unplaced])),
+            Some((-40.0, -10.0, 600.0, 550.0))
+        );
+        assert_eq!(element_bounds(&json!([])), None);
+    }
+
+    #[test]
+    fn svg_uses_the_given_view_box() {
+        let view_box = ViewBox {
+            x: 480.0,
+            y: 480.0,
+            width: 140.0,
+            height: 90.0,
+        };
+        let svg = generate_svg(
+            &json!([]),
+            "white",
+            None,
+            140,
+            90,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(svg.contains(r#"width="140" height="90" viewBox="480 480 140 90""#));
+        assert!(svg.contains(r#"<rect x="480" y="480" width="140" height="90" fill="white"/>"#));
+    }
+
+    #[test]
+    fn valid_elements_pass_validation() {
+        let mut extra = rectangle("b");
+        extra["customData"] = json!({ "anything": true });
+        assert_eq!(validate_elements(&json!([rectangle("a"), extra])), Ok(()));
+    }
+
+    #[test]
+    fn element_without_id_is_reported() {
+        let mut element = rectangle("a");
+        element.as_object_mut().unwrap().remove("id");
+        assert_eq!(
+            validate_elements(&json!([rectangle("ok"), element])),
+            Err(vec!["elements[1]: 'id' must be a string".to_string()])
+        );
+    }
+
+    #[test]
+    fn non_numeric_position_is_reported() {
+        let mut element = rectangle("a");
+        element["x"] = json!("10");
+        assert_eq!(
+            validate_element(&element),
+            Err(vec!["'x' must be a number".to_string()])
+        );
+        assert_eq!(
+            validate_elements(&json!({ "elements": [] })),
+            Err(vec!["elements must be an array".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn validation_errors_carry_details() {
+        let problems = vec!["elements[0]: 'id' must be a string".to_string()];
+        let (status, body) = error_response(ApiError::InvalidElements(problems.clone())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_elements");
+        assert_eq!(body["error"]["details"], json!(problems));
+    }
+
+    async fn allowed_origin(allowed_origins: &[String], origin: &str) -> Option<HeaderValue> {
+        let router = Router::new()
+            .route("/health", get(health))
+            .layer(cors_layer(allowed_origins));
+        let request = axum::http::Request::builder()
+            .uri("/health")
+            .header(header::ORIGIN, origin)
+            .body(Body::empty())
+            .expect("valid request");
+        let response = router.oneshot(request).await.expect("router is infallible");
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn cors_only_allows_configured_origins() {
+        let allowed = vec!["http://tablet.local:1420".to_string()];
+        assert_eq!(
+            allowed_origin(&allowed, "http://tablet.local:1420").await,
+            Some(HeaderValue::from_static("http://tablet.local:1420"))
+        );
+        assert_eq!(
+            allowed_origin(&allowed, "http://elsewhere.test").await,
+            None
+        );
+
+        // Without configured origins every origin is still allowed
+        assert_eq!(
+            allowed_origin(&[], "http://elsewhere.test").await,
+            Some(HeaderValue::from_static("*"))
+        );
+    }
+
+    fn batch(ops: Value) -> BatchPayload {
+        serde_json::from_value(json!({ "ops": ops })).expect("valid batch")
+    }
+
+    fn ids(elements: &[Value]) -> Vec<&str> {
+        elements
+            .iter()
+            .filter_map(|element| element["id"].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn mixed_batch_applies_in_order() {
+        let mut moved = rectangle("a");
+        moved["x"] = json!(99.0);
+        let payload = batch(json!([
+            { "action": "add", "element": rectangle("c") },
+            { "action": "update", "id": "a", "element": moved },
+            { "action": "remove", "id": "b" },
+        ]));
+
+        let mut elements = vec![rectangle("a"), rectangle("b")];
+        for op in &payload.ops {
+            apply_batch_operation(&mut elements, op).expect("op applies");
+        }
+        assert_eq!(ids(&elements), ["a", "c"]);
+        assert_eq!(elements[0]["x"], 99.0);
+    }
+
+    #[test]
+    fn bad_batch_operations_are_rejected() {
+        let payload = batch(json!([
+            { "action": "remove", "id": "missing" },
+            { "action": "add", "element": rectangle("a") },
+            { "action": "update", "id": "a" },
+        ]));
+        let mut elements = vec![rectangle("a")];
+
+        assert_eq!(
+            apply_batch_operation(&mut elements, &payload.ops[0]),
+            Err("Element with ID 'missing' not found".to_string())
+        );
+        assert_eq!(
+            apply_batch_operation(&mut elements, &payload.ops[1]),
+            Err("Element with ID 'a' already exists".to_string())
+        );
+        assert_eq!(
+            apply_batch_operation(&mut elements, &payload.ops[2]),
+            Err("update requires an element".to_string())
+        );
+        assert_eq!(elements, vec![rectangle("a")]);
+    }
+
+    fn linear(kind: &str, id: &str) -> Value {
+        json!({
+            "id": id,
+            "type": kind,
+            "x": 100.0,
+            "y": 100.0,
+            "width": 100.0,
+            "height": 50.0,
+            "strokeColor": "#1e1e1e",
+            "backgroundColor": "transparent",
+            "strokeWidth": 2,
+            "points": [[0.0, 0.0], [50.0, 50.0], [100.0, 0.0]],
+        })
+    }
+
+    #[test]
+    fn line_follows_all_of_its_points() {
+        let svg = element_svg(&linear("line", "l")).expect("line renders");
+        assert!(svg.starts_with("<polyline"));
+        assert!(svg.contains(r#"points="100,100 150,150 200,100""#));
+        assert!(!svg.contains("marker"));
+    }
+
+    #[test]
+    fn arrow_gets_an_end_arrowhead() {
+        let svg = element_svg(&linear("arrow", "a 1")).expect("arrow renders");
+        assert!(svg.contains(r#"<marker id="arrowhead-a-1""#));
+        assert!(svg.contains(r#"points="100,100 150,150 200,100""#));
+        assert!(svg.contains(r#"marker-end="url(#arrowhead-a-1)""#));
+        assert!(!svg.contains("marker-start"));
+
+        let mut headless = linear("arrow", "b");
+        headless["endArrowhead"] = Value::Null;
+        let svg = element_svg(&headless).expect("arrow renders");
+        assert!(!svg.contains("<marker"));
+    }
+
+    #[test]
+    fn line_without_points_spans_its_box() {
+        let mut line = linear("line", "l");
+        line.as_object_mut().unwrap().remove("points");
+        let svg = element_svg(&line).expect("line renders");
+        assert!(svg.contains(r#"points="100,100 200,150""#));
+    }
+
+    #[test]
+    fn server_status_reports_not_ready_until_set() {
+        let status = ServerStatus::default();
+        let info = status.info();
+        assert!(!info.running);
+        assert_eq!(info.port, 0);
+        assert!(info.address.is_empty());
+
+        status.set(ServerInfo {
+            running: true,
+            port: DEFAULT_PORT,
+            address: format!("http://127.0.0.1:{}", DEFAULT_PORT),
+            error: None,
+        });
+        let info = status.info();
+        assert!(info.running);
+        assert_eq!(info.port, DEFAULT_PORT);
+        assert_eq!(info.address, "http://127.0.0.1:31337");
+    }
+
+    #[test]
+    fn image_embeds_its_file_as_a_data_url() {
+        let mut image = rectangle("i");
+        image["type"] = json!("image");
+        image["fileId"] = json!("file-1");
+        let files = json!({
+            "file-1": { "mimeType": "image/png", "dataURL": "data:image/png;base64,iVBORw0KGgo=" },
+        });
+
+        let svg = convert_element_to_svg(&image, Some(&files), &UnknownTypes::default())
+            .expect("image renders");
+        assert!(svg.contains(r#"<image x="10" y="20" width="100" height="50" href="data:image/png;base64,iVBORw0KGgo=""#));
+
+        // Without the file there's only the placeholder outline
+        let svg = convert_element_to_svg(&image, None, &UnknownTypes::default())
+            .expect("placeholder renders");
+        assert!(svg.contains(r#"stroke-dasharray="5,5""#));
+        assert!(!svg.contains("<image"));
+    }
+
+    #[tokio::test]
+    async fn failed_patch_reports_the_operation() {
+        let patch: json_patch::Patch = serde_json::from_value(json!([
+            { "op": "replace", "path": "/elements/0/x", "value": 42 },
+            { "op": "replace", "path": "/elements/5/x", "value": 42 },
+        ]))
+        .expect("valid patch");
+        let mut document = json!({ "elements": [rectangle("a")] });
+        let err = json_patch::patch(&mut document, &patch).expect_err("second op has no target");
+
+        let (status, body) = error_response(ApiError::PatchFailed {
+            operation: err.operation,
+            reason: err.to_string(),
+        })
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "patch_failed");
+        assert_eq!(body["error"]["operation"], 1);
+    }
+
+    #[test]
+    fn scene_events_carry_the_scene_id() {
+        let event = SceneEvent {
+            scene: "tab-2".to_string(),
+            payload: ClearPayload {
+                updated_at: "2024-01-01T00:00:00Z".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&event).expect("serializable"),
+            json!({ "scene": "tab-2", "updated_at": "2024-01-01T00:00:00Z" })
+        );
+    }
+
+    #[test]
+    fn hachure_fill_references_a_shared_pattern() {
+        let mut first = rectangle("a");
+        first["fillStyle"] = json!("hachure");
+        first["backgroundColor"] = json!("#ffc9c9");
+        let mut second = first.clone();
+        second["id"] = json!("b");
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let svg = generate_svg(
+            &json!([first, second]),
+            "white",
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert_eq!(svg.matches(r#"<pattern id="hachure-ffc9c9""#).count(), 1);
+        assert_eq!(svg.matches(r#"fill="url(#hachure-ffc9c9)""#).count(), 2);
+    }
+
+    #[test]
+    fn cross_hatch_adds_perpendicular_lines() {
+        let mut element = rectangle("a");
+        element["fillStyle"] = json!("cross-hatch");
+        element["backgroundColor"] = json!("#a5d8ff");
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let svg = generate_svg(
+            &json!([element]),
+            "white",
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(svg.contains(r#"<pattern id="cross-hatch-a5d8ff""#));
+        assert_eq!(svg.matches("<line").count(), 2);
+    }
+
+    #[test]
+    fn transparent_hachure_keeps_a_plain_fill() {
+        let mut element = rectangle("a");
+        element["fillStyle"] = json!("hachure");
+        let svg = element_svg(&element).expect("rectangle renders");
+        assert!(svg.contains(r#"fill="transparent""#));
+        assert!(!svg.contains("url(#"));
+    }
+
+    #[test]
+    fn multi_line_text_gets_one_tspan_per_line() {
+        let mut element = rectangle("t");
+        element["type"] = json!("text");
+        element["text"] = json!("first\nsecond <2>");
+        element["fontSize"] = json!(20.0);
+        let svg = element_svg(&element).expect("text renders");
+        assert!(svg.contains(r#"<tspan x="10" dy="0">first</tspan>"#));
+        assert!(svg.contains(r#"<tspan x="10" dy="25">second &lt;2&gt;</tspan>"#));
+        assert_eq!(svg.matches("<tspan").count(), 2);
+    }
+
+    #[test]
+    fn line_height_overrides_the_default_advance() {
+        let mut element = rectangle("t");
+        element["type"] = json!("text");
+        element["text"] = json!("a\nb");
+        element["fontSize"] = json!(20.0);
+        element["lineHeight"] = json!(1.5);
+        let svg = element_svg(&element).expect("text renders");
+        assert!(svg.contains(r#"<tspan x="10" dy="30">b</tspan>"#));
+    }
+
+    #[tokio::test]
+    async fn invalid_import_error_shape() {
+        let (status, body) =
+            error_response(ApiError::InvalidImport("type is 'other'".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_import");
+        assert_eq!(
+            body["error"]["message"],
+            "Not an Excalidraw file: type is 'other'"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_order_action_error_shape() {
+        let (status, body) =
+            error_response(ApiError::InvalidOrderAction("sideways".to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_order_action");
+    }
+
+    #[test]
+    fn dashed_and_dotted_strokes_scale_with_stroke_width() {
+        let mut dashed = rectangle("a");
+        dashed["strokeStyle"] = json!("dashed");
+        let svg = element_svg(&dashed).expect("rectangle renders");
+        assert!(svg.contains(r#"stroke-dasharray="16,8""#));
+
+        let mut dotted = linear("line", "b");
+        dotted["strokeStyle"] = json!("dotted");
+        let svg = element_svg(&dotted).expect("line renders");
+        assert!(svg.contains(r#"stroke-dasharray="4,8""#));
+    }
+
+    #[test]
+    fn solid_strokes_have_no_dasharray() {
+        let svg = element_svg(&rectangle("a")).expect("rectangle renders");
+        assert!(!svg.contains("stroke-dasharray"));
+    }
+
+    #[test]
+    fn element_rect_normalizes_negative_sizes() {
+        let mut flipped = rectangle("a");
+        flipped["width"] = json!(-100.0);
+        flipped["height"] = json!(-50.0);
+        assert_eq!(element_rect(&flipped), Some((-90.0, -30.0, 10.0, 20.0)));
+        assert_eq!(
+            element_rect(&json!({ "id": "u", "type": "rectangle" })),
+            None
+        );
+    }
+
+    fn canvas_with(ids: &[&str]) -> CanvasData {
+        CanvasData {
+            elements: Some(Value::Array(ids.iter().map(|id| rectangle(id)).collect())),
+            ..CanvasData::empty()
+        }
+    }
+
+    fn element_ids_of(canvas: &CanvasData) -> Vec<&str> {
+        canvas
+            .elements
+            .as_ref()
+            .and_then(|elements| elements.as_array())
+            .map(|elements| ids(elements))
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn undo_restores_the_prior_state_and_redo_reapplies() {
+        let mut history = SceneHistory::default();
+        history.record(&canvas_with(&["a"]), 50);
+        history.record(&canvas_with(&["a", "b"]), 50);
+
+        let undone = history.undo().expect("one step back");
+        assert_eq!(element_ids_of(&undone), ["a"]);
+        let redone = history.redo().expect("one step forward");
+        assert_eq!(element_ids_of(&redone), ["a", "b"]);
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn recording_after_undo_drops_the_redo_branch() {
+        let mut history = SceneHistory::default();
+        history.record(&canvas_with(&["a"]), 50);
+        history.undo().expect("back to empty");
+        history.record(&canvas_with(&["b"]), 50);
+        assert!(history.redo().is_none());
+        let undone = history.undo().expect("back to empty");
+        assert!(undone.elements.is_none());
+    }
+
+    #[test]
+    fn history_keeps_at_most_limit_steps() {
+        let mut history = SceneHistory::default();
+        for id in ["a", "b", "c"] {
+            history.record(&canvas_with(&[id]), 2);
+        }
+        assert_eq!(element_ids_of(&history.undo().expect("first")), ["b"]);
+        assert_eq!(element_ids_of(&history.undo().expect("second")), ["a"]);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn background_follows_the_scene_app_state() {
+        assert_eq!(background_color(None), "white");
+        assert_eq!(
+            background_color(Some(&json!({ "viewBackgroundColor": "#1e1e1e" }))),
+            "#1e1e1e"
+        );
+        assert_eq!(
+            background_color(Some(&json!({ "theme": "dark" }))),
+            DARK_BACKGROUND
+        );
+        assert_eq!(
+            background_color(Some(&json!({ "theme": "dark", "viewBackgroundColor": "" }))),
+            DARK_BACKGROUND
+        );
+    }
+
+    #[test]
+    fn svg_background_rect_uses_view_background_color() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let app_state = json!({ "viewBackgroundColor": "#1e1e1e" });
+        let svg = generate_svg(
+            &json!([]),
+            background_color(Some(&app_state)),
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(svg.contains(r##"width="80" height="60" fill="#1e1e1e"/>"##));
+    }
+
+    #[test]
+    fn if_none_match_accepts_lists_weak_tags_and_wildcards() {
+        let etag = "\"2024-01-01T00:00:00Z\"";
+        let with = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+        assert!(etag_matches(&with("\"2024-01-01T00:00:00Z\""), etag));
+        assert!(etag_matches(
+            &with("\"old\", W/\"2024-01-01T00:00:00Z\""),
+            etag
+        ));
+        assert!(etag_matches(&with("*"), etag));
+        assert!(!etag_matches(&with("\"old\""), etag));
+        assert!(!etag_matches(&HeaderMap::new(), etag));
+    }
+
+    #[test]
+    fn updated_stamp_overrides_clients_and_always_increases() {
+        let mut element = rectangle("a");
+        element["updated"] = json!(1);
+        element["version"] = json!(7);
+        stamp_element(&mut element, None);
+        let first = element["updated"].as_i64().expect("stamped");
+        assert!(first > 1);
+        assert_eq!(element["version"], 7);
+
+        let mut updated = element.clone();
+        stamp_element(&mut updated, Some(&element));
+        assert!(updated["updated"].as_i64().expect("stamped") > first);
+
+        // A previous stamp from the future still moves forward
+        let future = json!({ "updated": first + 60_000 });
+        stamp_element(&mut updated, Some(&future));
+        assert_eq!(updated["updated"], first + 60_001);
+    }
+
+    #[test]
+    fn if_match_accepts_bare_and_quoted_versions() {
+        let with = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::IF_MATCH, HeaderValue::from_static(value));
+            headers
+        };
+        assert_eq!(if_match_version(&with("3")), Some(3));
+        assert_eq!(if_match_version(&with("\"3\"")), Some(3));
+        assert_eq!(if_match_version(&with("W/\"3\"")), Some(3));
+        assert_eq!(if_match_version(&with("*")), None);
+        assert_eq!(if_match_version(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn version_conflict_returns_the_stored_element() {
+        let mut current = rectangle("a");
+        current["version"] = json!(4);
+        let (status, body) = error_response(ApiError::VersionConflict {
+            id: "a".to_string(),
+            expected: 3,
+            current: current.clone(),
+        })
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "version_conflict");
+        assert_eq!(body["error"]["current"], current);
+    }
+
+    #[test]
+    fn bind_address_comes_from_extauri_bind() {
+        std::env::set_var("EXTAURI_BIND", "0.0.0.0");
+        let config = ServerConfig::from_env();
+        std::env::set_var("EXTAURI_BIND", "not-an-address");
+        let invalid = ServerConfig::from_env();
+        std::env::remove_var("EXTAURI_BIND");
+
+        let config = config.expect("0.0.0.0 parses");
+        assert_eq!(config.bind_address, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        assert!(!config.bind_address.is_loopback());
+        assert!(invalid.is_err());
+        assert!(ServerConfig::default().bind_address.is_loopback());
+    }
+
+    #[tokio::test]
+    async fn rate_limited_responses_carry_retry_after() {
+        let response = ApiError::RateLimited(2).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let (_, body) = error_response(ApiError::RateLimited(2)).await;
+        assert_eq!(body["error"]["code"], "rate_limited");
+    }
+
+    #[test]
+    fn openapi_spec_covers_the_core_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).expect("spec serializes");
+        let paths = spec["paths"].as_object().expect("paths object");
+        for path in [
+            "/health",
+            "/draw",
+            "/canvas",
+            "/canvas/clear",
+            "/canvas/export",
+            "/canvas/element",
+            "/canvas/element/{id}",
+        ] {
+            assert!(paths.contains_key(path), "missing {}", path);
+        }
+        assert!(paths["/canvas"].get("get").is_some());
+        assert!(paths["/canvas"].get("put").is_some());
+    }
+
+    #[test]
+    fn group_membership_checks_every_group_id() {
+        let mut nested = rectangle("a");
+        nested["groupIds"] = json!(["inner", "outer"]);
+        assert!(in_group(&nested, "inner"));
+        assert!(in_group(&nested, "outer"));
+        assert!(!in_group(&nested, "other"));
+        assert!(!in_group(&rectangle("b"), "inner"));
+    }
+
+    #[test]
+    fn rounded_rectangles_get_a_corner_radius() {
+        let mut proportional = rectangle("a");
+        proportional["roundness"] = json!({ "type": 2 });
+        let svg = element_svg(&proportional).expect("rectangle renders");
+        assert!(svg.contains(r#"rx="12.5" ry="12.5""#));
+
+        let mut adaptive = rectangle("b");
+        adaptive["width"] = json!(400.0);
+        adaptive["height"] = json!(300.0);
+        adaptive["roundness"] = json!({ "type": 3 });
+        let svg = element_svg(&adaptive).expect("rectangle renders");
+        assert!(svg.contains(r#"rx="32" ry="32""#));
+    }
+
+    #[test]
+    fn rectangles_without_roundness_stay_sharp() {
+        let mut sharp = rectangle("a");
+        sharp["roundness"] = Value::Null;
+        let svg = element_svg(&sharp).expect("rectangle renders");
+        assert!(!svg.contains("rx="));
+    }
+
+    #[test]
+    fn preflight_lists_placeholder_types_and_skipped_elements() {
+        let mut sticky = rectangle("s");
+        sticky["type"] = json!("sticky-note");
+        let mut image = rectangle("i");
+        image["type"] = json!("image");
+        image["fileId"] = json!("missing");
+        // A single point has no stroke to draw
+        let broken = json!({ "id": "b", "type": "freedraw", "points": [[0, 0]] });
+        let elements = json!([rectangle("a"), sticky, image, broken]);
+
+        let summary = export_preflight(&elements, None, EMPTY_SVG, "png", &UnknownTypes::default());
+        assert_eq!(summary["valid"], true);
+        assert_eq!(summary["element_count"], 4);
+        assert_eq!(summary["unsupported_types"], json!(["sticky-note"]));
+        let warnings = summary["warnings"].as_array().expect("warnings");
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings
+            .iter()
+            .any(|w| w.as_str().is_some_and(|w| w.contains("'i'"))));
+        assert!(warnings
+            .iter()
+            .any(|w| w.as_str().is_some_and(|w| w.contains("'b'"))));
+    }
+
+    #[test]
+    fn preflight_of_an_empty_canvas_is_invalid() {
+        let summary =
+            export_preflight(&json!([]), None, EMPTY_SVG, "svg", &UnknownTypes::default());
+        assert_eq!(summary["valid"], false);
+        assert_eq!(summary["warnings"], json!(["Canvas has no elements"]));
+    }
+
+    #[test]
+    fn preflight_rejects_unparseable_svg_for_raster_formats() {
+        let summary = export_preflight(
+            &json!([rectangle("a")]),
+            None,
+            "<svg",
+            "png",
+            &UnknownTypes::default(),
+        );
+        assert_eq!(summary["valid"], false);
+        let summary = export_preflight(
+            &json!([rectangle("a")]),
+            None,
+            "<svg",
+            "svg",
+            &UnknownTypes::default(),
+        );
+        assert_eq!(summary["valid"], true);
+    }
+
+    #[test]
+    fn embeddable_renders_a_box_with_its_link() {
+        let mut embed = rectangle("e");
+        embed["type"] = json!("embeddable");
+        embed["width"] = json!(400.0);
+        embed["link"] = json!("https://a.io/?q=<x>");
+        let svg = element_svg(&embed).expect("embed renders");
+        assert!(svg.starts_with("<rect"));
+        assert!(svg.contains(">https://a.io/?q=&lt;x&gt;</text>"));
+    }
+
+    #[test]
+    fn long_links_are_truncated_with_an_ellipsis() {
+        let mut iframe = rectangle("f");
+        iframe["type"] = json!("iframe");
+        iframe["link"] = json!("https://example.com/a/very/long/path");
+        let svg = element_svg(&iframe).expect("iframe renders");
+        assert!(svg.contains(">https://e…</text>"));
+    }
+
+    #[test]
+    fn socket_messages_are_tagged_by_type() {
+        let draw: SocketMessage =
+            serde_json::from_value(json!({ "type": "draw", "elements": [rectangle("a")] }))
+                .expect("draw parses");
+        match draw {
+            SocketMessage::Draw { scene, payload } => {
+                assert_eq!(scene, DEFAULT_SCENE);
+                assert_eq!(payload.elements, Some(json!([rectangle("a")])));
+            }
+            other => panic!("expected a draw, got {:?}", other),
+        }
+
+        let remove: SocketMessage =
+            serde_json::from_value(json!({ "type": "remove", "scene": "s", "id": "a" }))
+                .expect("remove parses");
+        assert!(matches!(
+            remove,
+            SocketMessage::Remove { scene, id } if scene == "s" && id == "a"
+        ));
+
+        assert!(serde_json::from_value::<SocketMessage>(json!({ "type": "explode" })).is_err());
+    }
+
+    #[test]
+    fn socket_error_frames_use_the_http_error_body() {
+        let body = ApiError::InvalidMessage("expected value".to_string()).body();
+        assert_eq!(body["error"]["code"], "invalid_message");
+        assert_eq!(
+            body["error"]["message"],
+            "Invalid socket message: expected value"
+        );
+    }
+
+    fn accepting(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn accept_header_picks_the_export_format() {
+        assert_eq!(format_from_accept(&accepting("image/png")), "png");
+        assert_eq!(format_from_accept(&accepting("image/svg+xml")), "svg");
+        assert_eq!(format_from_accept(&accepting("application/json")), "json");
+        assert_eq!(
+            format_from_accept(&accepting("text/html, image/webp;q=0.9")),
+            "webp"
+        );
+    }
+
+    #[test]
+    fn accept_quality_outranks_order() {
+        assert_eq!(
+            format_from_accept(&accepting("image/svg+xml;q=0.5, image/png")),
+            "png"
+        );
+        assert_eq!(
+            format_from_accept(&accepting("image/jpeg, image/png")),
+            "jpeg"
+        );
+    }
+
+    #[test]
+    fn unknown_or_missing_accept_falls_back_to_svg() {
+        assert_eq!(format_from_accept(&HeaderMap::new()), "svg");
+        assert_eq!(format_from_accept(&accepting("*/*")), "svg");
+        assert_eq!(format_from_accept(&accepting("text/html")), "svg");
+    }
+
+    #[test]
+    fn dedupe_keeps_the_last_element_for_each_id() {
+        let mut moved = rectangle("a");
+        moved["x"] = json!(300.0);
+        let mut elements = json!([rectangle("a"), rectangle("b"), moved.clone()]);
+        assert_eq!(dedupe_elements(&mut elements), 1);
+        assert_eq!(elements, json!([rectangle("b"), moved]));
+    }
+
+    #[test]
+    fn dedupe_leaves_unique_and_id_less_elements_alone() {
+        let mut elements = json!([{ "type": "text" }, rectangle("a"), { "type": "text" }]);
+        let before = elements.clone();
+        assert_eq!(dedupe_elements(&mut elements), 0);
+        assert_eq!(elements, before);
+    }
+
+    #[test]
+    fn doubling_the_output_size_doubles_png_dimensions() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let elements = json!([rectangle("a")]);
+        let svg = generate_svg(
+            &elements,
+            "white",
+            None,
+            160,
+            120,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(svg.contains(r#"viewBox="0 0 80 60""#));
+        let bytes =
+            render_raster(&svg, "png", 160, 120, DEFAULT_EXPORT_QUALITY).expect("PNG renders");
+        let image = image::load_from_memory(&bytes).expect("PNG decodes");
+        assert_eq!((image.width(), image.height()), (160, 120));
+    }
+
+    #[tokio::test]
+    async fn invalid_scale_error_shape() {
+        let (status, body) = error_response(ApiError::InvalidScale(0.0)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_scale");
+    }
+
+    // Collects formatted log output so tests can inspect the records
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl CapturedLogs {
+        fn records(&self) -> Vec<Value> {
+            String::from_utf8_lossy(&self.0.lock().unwrap())
+                .lines()
+                .map(|line| serde_json::from_str(line).expect("JSON log line"))
+                .collect()
+        }
+    }
+
+    async fn logged_requests(path: &str) -> Vec<Value> {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::INFO)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let router = Router::new()
+            .route("/health", get(health))
+            .route("/canvas", get(health))
+            .layer(middleware::from_fn(log_request));
+        let request = axum::http::Request::builder()
+            .uri(path)
+            .body(Body::empty())
+            .expect("valid request");
+        router.oneshot(request).await.expect("router is infallible");
+
+        logs.records()
+            .into_iter()
+            .filter(|record| record["target"] == "http_request")
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_with_status_and_latency() {
+        let records = logged_requests("/canvas?scene=a").await;
+        assert_eq!(records.len(), 1);
+        let fields = &records[0]["fields"];
+        assert_eq!(fields["method"], "GET");
+        assert_eq!(fields["uri"], "/canvas?scene=a");
+        assert_eq!(fields["status"], 200);
+        assert!(fields["latency_ms"].as_f64().expect("numeric latency") > 0.0);
+    }
+
+    #[tokio::test]
+    async fn health_probes_stay_below_info() {
+        assert!(logged_requests("/health").await.is_empty());
+    }
+
+    #[test]
+    fn bound_text_wraps_to_its_container_width() {
+        let mut label = rectangle("t");
+        label["type"] = json!("text");
+        label["containerId"] = json!("box");
+        label["fontSize"] = json!(20.0);
+        label["text"] = json!("alpha beta gamma");
+        let svg = element_svg(&label).expect("text renders");
+        assert_eq!(svg.matches("<tspan").count(), 3);
+        assert!(svg.contains(">beta</tspan>"));
+
+        // Free text keeps its own line breaks only
+        label["containerId"] = Value::Null;
+        let svg = element_svg(&label).expect("text renders");
+        assert_eq!(svg.matches("<tspan").count(), 1);
+    }
+
+    #[test]
+    fn wrap_splits_words_longer_than_a_line() {
+        assert_eq!(wrap_line("abcdefgh ij", 3), ["abc", "def", "gh", "ij"]);
+        assert_eq!(wrap_line("", 3), [""]);
+    }
+
+    #[test]
+    fn frames_render_an_open_box_and_their_name() {
+        let mut frame = rectangle("f");
+        frame["type"] = json!("frame");
+        frame["name"] = json!("Login & signup");
+        let svg = element_svg(&frame).expect("frame renders");
+        assert!(svg.contains(r#"<rect x="10" y="20" width="100" height="50" fill="none""#));
+        assert!(svg.contains(r#"<text x="10" y="13""#));
+        assert!(svg.contains(">Login &amp; signup</text>"));
+
+        frame["name"] = Value::Null;
+        let svg = element_svg(&frame).expect("frame renders");
+        assert!(svg.contains(">Frame</text>"));
+    }
+
+    #[test]
+    fn avif_export_is_an_isobmff_avif_file() {
+        let bytes =
+            render_raster(EMPTY_SVG, "avif", 4, 4, DEFAULT_EXPORT_QUALITY).expect("AVIF renders");
+        assert_eq!(&bytes[4..12], b"ftypavif");
+    }
+
+    #[test]
+    fn jpeg_quality_trades_size_for_fidelity() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 120.0,
+            height: 90.0,
+        };
+        let mut elements = Vec::new();
+        for i in 0..6 {
+            let mut element = rectangle(&format!("r{}", i));
+            element["x"] = json!(i * 15);
+            element["strokeColor"] = json!(format!("#{:02x}40c0", i * 40));
+            elements.push(element);
+        }
+        let svg = generate_svg(
+            &Value::Array(elements),
+            "white",
+            None,
+            120,
+            90,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        let low = render_raster(&svg, "jpeg", 120, 90, 5).expect("JPEG renders");
+        let high = render_raster(&svg, "jpeg", 120, 90, 100).expect("JPEG renders");
+        assert!(low.len() < high.len());
+    }
+
+    #[test]
+    fn rounded_arrow_is_a_smooth_path_with_its_arrowhead() {
+        let mut arrow = linear("arrow", "a");
+        arrow["roundness"] = json!({ "type": 2 });
+        let svg = element_svg(&arrow).expect("arrow renders");
+        assert!(svg.contains(r#"<path d="M 100 100 Q 150 150 175 125 L 200 100""#));
+        assert!(svg.contains("marker-end="));
+        assert!(!svg.contains("<polyline"));
+    }
+
+    #[test]
+    fn straight_and_two_point_lines_stay_polylines() {
+        let svg = element_svg(&linear("line", "a")).expect("line renders");
+        assert!(svg.contains("<polyline"));
+
+        let mut two_points = linear("line", "b");
+        two_points["roundness"] = json!({ "type": 2 });
+        two_points["points"] = json!([[0, 0], [50, 50]]);
+        let svg = element_svg(&two_points).expect("line renders");
+        assert!(svg.contains("<polyline"));
+    }
+
+    #[test]
+    fn transparent_background_omits_the_background_rect() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let svg = generate_svg(
+            &json!([]),
+            "transparent",
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(!svg.contains("<rect"));
+
+        let svg = generate_svg(
+            &json!([]),
+            "#ffeedd",
+            None,
+            80,
+            60,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        assert!(svg.contains(r##"<rect x="0" y="0" width="80" height="60" fill="#ffeedd"/>"##));
+    }
+
+    #[test]
+    fn transparent_png_export_has_real_alpha() {
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 80.0,
+            height: 60.0,
+        };
+        let svg = generate_svg(
+            &json!([]),
+            "transparent",
+            None,
+            8,
+            6,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        let bytes = render_raster(&svg, "png", 8, 6, DEFAULT_EXPORT_QUALITY).expect("PNG renders");
+        let image = image::load_from_memory(&bytes)
+            .expect("PNG decodes")
+            .to_rgba8();
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+    }
+
+    #[test]
+    fn background_accepts_names_and_hex_colours_only() {
+        for valid in [
+            "transparent",
+            "white",
+            "rebeccapurple",
+            "#fff",
+            "#ffff",
+            "#1e1e1e",
+            "#1e1e1e80",
+        ] {
+            assert!(is_color(valid), "{} should be accepted", valid);
+        }
+        for invalid in ["", "#12", "#ggg", "url(#x)", "red;", "#1e1e1e1"] {
+            assert!(!is_color(invalid), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn removal_events_carry_only_the_id() {
+        let removed = ElementEventPayload {
+            id: "a".to_string(),
+            element: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&removed).expect("serializable"),
+            json!({ "id": "a" })
+        );
+    }
+
+    async fn post_json_body(content_type: Option<&str>, body: &'static str) -> (StatusCode, Value) {
+        let router = Router::new().route(
+            "/translate",
+            post(|JsonBody(payload): JsonBody<TranslatePayload>| async move {
+                Json(json!({ "dx": payload.dx, "dy": payload.dy }))
+            }),
+        );
+        let mut request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/translate");
+        if let Some(content_type) = content_type {
+            request = request.header(header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(Body::from(body)).expect("valid request");
+        let response = router.oneshot(request).await.expect("router is infallible");
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body is readable");
+        (status, serde_json::from_slice(&bytes).expect("JSON body"))
+    }
+
+    #[tokio::test]
+    async fn malformed_json_gets_a_structured_400() {
+        let (status, body) = post_json_body(Some("application/json"), "{not json}").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "invalid_json");
+        let message = body["error"]["message"].as_str().expect("message");
+        assert!(message.contains("line 1 column 2"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn mistyped_json_gets_a_structured_422() {
+        let (status, body) =
+            post_json_body(Some("application/json"), r#"{"dx": 1, "dy": 2}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "dx": 1.0, "dy": 2.0 }));
+
+        let (status, body) = post_json_body(Some("application/json"), r#"{"dx": "far"}"#).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn bodies_without_a_json_content_type_get_415() {
+        let (status, body) = post_json_body(None, "{}").await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["error"]["code"], "unsupported_media_type");
+    }
+
+    #[test]
+    fn eight_digit_hex_is_split_into_colour_and_opacity() {
+        let mut element = rectangle("a");
+        element["strokeColor"] = json!("#1e1e1e80");
+        element["backgroundColor"] = json!("#ffc9c9ff");
+        let svg = element_svg(&element).expect("rectangle renders");
+        assert!(svg.contains(r##"stroke="#1e1e1e" stroke-opacity="0.502""##));
+        assert!(svg.contains(r##"fill="#ffc9c9" stroke"##));
+        assert!(!svg.contains("fill-opacity"));
+    }
+
+    #[test]
+    fn other_colours_pass_through_unchanged() {
+        assert_eq!(split_alpha("#1e1e1e"), ("#1e1e1e", None));
+        assert_eq!(split_alpha("transparent"), ("transparent", None));
+        assert_eq!(split_alpha("#1e1e1ezz"), ("#1e1e1ezz", None));
+        assert_eq!(split_alpha("#00000000"), ("#000000", Some(0.0)));
+    }
+
+    #[tokio::test]
+    async fn bind_and_serve_failures_are_told_apart() {
+        let taken = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("a free port");
+        let address = taken.local_addr().expect("bound address");
+        let source = tokio::net::TcpListener::bind(address)
+            .await
+            .expect_err("the port is taken");
+
+        let failure = ServerError::Bind { address, source }.failure();
+        assert_eq!(failure.stage, "bind");
+        assert!(failure
+            .message
+            .starts_with(&format!("could not listen on {}", address)));
+
+        let failure = ServerError::Serve(std::io::Error::other("reset")).failure();
+        assert_eq!(failure.stage, "serve");
+        assert_eq!(failure.message, "server stopped unexpectedly: reset");
+    }
+
+    #[test]
+    fn content_hash_ignores_key_order_but_not_content() {
+        let a: Value = serde_json::from_str(r#"[{"id":"a","x":1,"y":2}]"#).expect("JSON");
+        let b: Value = serde_json::from_str(r#"[{"y":2,"x":1,"id":"a"}]"#).expect("JSON");
+        let moved: Value = serde_json::from_str(r#"[{"id":"a","x":5,"y":2}]"#).expect("JSON");
+
+        let hash = content_hash(Some(&a));
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, content_hash(Some(&a)));
+        assert_eq!(hash, content_hash(Some(&b)));
+        assert_ne!(hash, content_hash(Some(&moved)));
+        assert_ne!(content_hash(None), content_hash(Some(&json!([]))));
+    }
+
+    #[test]
+    fn content_hash_keeps_drawing_order() {
+        let forward = json!([rectangle("a"), rectangle("b")]);
+        let reversed = json!([rectangle("b"), rectangle("a")]);
+        assert_ne!(content_hash(Some(&forward)), content_hash(Some(&reversed)));
+    }
+
+    #[tokio::test]
+    async fn region_exports_use_the_region_as_view_box() {
+        let params: ExportQuery = serde_json::from_value(json!({})).expect("defaults apply");
+        let region = ViewBox {
+            x: 200.0,
+            y: 100.0,
+            width: 300.0,
+            height: 150.0,
+        };
+        let response = render_export(
+            params,
+            "svg".to_string(),
+            json!([rectangle("a")]),
+            None,
+            None,
+            Some(region),
+            UnknownTypes::default(),
+        )
+        .await
+        .expect("SVG renders");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body is readable");
+        let svg = String::from_utf8(bytes.to_vec()).expect("UTF-8 SVG");
+        assert!(svg.contains(r#"viewBox="200 100 300 150""#), "{}", svg);
+    }
+
+    #[tokio::test]
+    async fn frame_not_found_error_shape() {
+        let (status, body) = error_response(ApiError::FrameNotFound("f".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"]["code"], "frame_not_found");
+    }
+
+    #[tokio::test]
+    async fn too_many_elements_reports_current_and_max() {
+        let (status, body) = error_response(ApiError::TooManyElements { current: 3, max: 3 }).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "too_many_elements");
+        assert_eq!(body["error"]["current"], 3);
+        assert_eq!(body["error"]["max"], 3);
+    }
+
+    #[test]
+    fn element_counts_ignore_missing_or_non_array_elements() {
+        assert_eq!(count_elements(None), 0);
+        assert_eq!(count_elements(Some(&json!({}))), 0);
+        assert_eq!(
+            count_elements(Some(&json!([rectangle("a"), rectangle("b")]))),
+            2
+        );
+    }
+
+    use crate::events::RecordingSink;
+
+    /// A router over an empty default scene built from `config`, and the sink its events go to.
+    fn router_with(config: ServerConfig) -> (Arc<RecordingSink>, Router) {
+        let sink = Arc::new(RecordingSink::default());
+        (sink.clone(), create_router(AppState::new(sink, config)))
+    }
+
+    /// A router with the default config but no rate limit, so tests can send as many requests
+    /// as they like.
+    fn recording_router() -> (Arc<RecordingSink>, Router) {
+        router_with(ServerConfig {
+            rate_limit: 0,
+            ..ServerConfig::default()
+        })
+    }
+
+    /// A response reduced to what the tests assert on.
+    struct Reply {
+        status: StatusCode,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    }
+
+    impl Reply {
+        fn json(&self) -> Value {
+            serde_json::from_slice(&self.body).expect("response body is not JSON")
+        }
+    }
+
+    /// Send `request` through a clone of `router` and collect the whole response.
+    async fn send(router: &Router, request: Request) -> Reply {
+        let response = router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("response body");
+        Reply {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// `method uri` with an optional JSON body.
+    async fn call(router: &Router, method: &str, uri: &str, body: Option<Value>) -> Reply {
+        let builder = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => builder
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        };
+        send(router, request.expect("valid request")).await
+    }
+
+    #[tokio::test]
+    async fn handlers_emit_their_events() {
+        let (sink, router) = recording_router();
+
+        let drawn = call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+        assert_eq!(drawn.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+
+        let updated = call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+        )
+        .await;
+        assert_eq!(updated.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+
+        let added = call(
+            &router,
+            "POST",
+            "/canvas/element",
+            Some(json!({ "element": rectangle("c") })),
+        )
+        .await;
+        assert_eq!(added.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_ELEMENT_ADDED]);
+
+        let removed = call(&router, "DELETE", "/canvas/element/c", None).await;
+        assert_eq!(removed.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_ELEMENT_REMOVED]);
+
+        let undone = call(&router, "POST", "/canvas/undo", None).await;
+        assert_eq!(undone.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+
+        let cleared = call(&router, "POST", "/canvas/clear", None).await;
+        assert_eq!(cleared.status, StatusCode::OK);
+        assert_eq!(sink.take_names(), [EVENT_CLEAR]);
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_emit_nothing() {
+        let (sink, router) = recording_router();
+        let removed = call(&router, "DELETE", "/canvas/element/missing", None).await;
+        assert_eq!(removed.status, StatusCode::NOT_FOUND);
+        assert_eq!(removed.headers[header::CONTENT_TYPE], "application/json");
+        assert_eq!(removed.json()["error"]["code"], "element_not_found");
+        assert!(sink.take().is_empty());
+    }
+
+    /// Ids of the elements currently on the default scene.
+    async fn canvas_element_ids(router: &Router) -> Vec<String> {
+        let canvas = call(router, "GET", "/canvas", None).await.json();
+        canvas["canvas"]["elements"]
+            .as_array()
+            .map(|elements| {
+                elements
+                    .iter()
+                    .filter_map(|element| element["id"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn removing_a_container_removes_its_bound_text() {
+        let (sink, router) = recording_router();
+        let mut container = rectangle("box");
+        container["boundElements"] = json!([{ "id": "label", "type": "text" }]);
+        let label = json!({
+            "id": "label",
+            "type": "text",
+            "x": 20.0,
+            "y": 30.0,
+            "text": "hi",
+            "containerId": "box",
+        });
+        let drawn = call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [container, label, rectangle("other")] })),
+        )
+        .await;
+        assert_eq!(drawn.status, StatusCode::OK);
+        sink.take();
+
+        let removed = call(&router, "DELETE", "/canvas/element/box", None).await;
+        assert_eq!(removed.status, StatusCode::OK);
+        assert_eq!(removed.json()["removed"], json!(["box", "label"]));
+        assert_eq!(canvas_element_ids(&router).await, ["other"]);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+    }
+
+    #[tokio::test]
+    async fn removing_bound_text_unlinks_it_from_its_container() {
+        let (_, router) = recording_router();
+        let mut container = rectangle("box");
+        container["boundElements"] = json!([{ "id": "label", "type": "text" }]);
+        let label = json!({
+            "id": "label",
+            "type": "text",
+            "x": 20.0,
+            "y": 30.0,
+            "text": "hi",
+            "containerId": "box",
+        });
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [container, label] })),
+        )
+        .await;
+
+        let removed = call(&router, "DELETE", "/canvas/element/label", None).await;
+        assert_eq!(removed.status, StatusCode::OK);
+        let canvas = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(canvas["canvas"]["elements"][0]["id"], "box");
+        assert_eq!(canvas["canvas"]["elements"][0]["boundElements"], json!([]));
+    }
+
+    async fn stream(router: &Router, body: impl Into<Body>) -> Reply {
+        let request = Request::builder()
+            .method("POST")
+            .uri("/canvas/elements/stream")
+            .header("content-type", "application/x-ndjson")
+            .body(body.into())
+            .expect("valid request");
+        send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn stream_skips_malformed_lines() {
+        let (sink, router) = recording_router();
+        let body = format!(
+            "{}\n{}\n{{\"id\": \"broken\",\n{}\n",
+            rectangle("a"),
+            rectangle("b"),
+            rectangle("c")
+        );
+
+        let streamed = stream(&router, body).await;
+        assert_eq!(streamed.status, StatusCode::OK);
+        assert_eq!(streamed.json(), json!({ "added": 3, "skipped": [3] }));
+        assert_eq!(canvas_element_ids(&router).await, ["a", "b", "c"]);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+    }
+
+    #[tokio::test]
+    async fn stream_rejects_a_body_that_is_not_utf8() {
+        let (_, router) = recording_router();
+        let streamed = stream(&router, vec![b'{', 0xff, 0xfe, b'}']).await;
+        assert_eq!(streamed.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(streamed.json()["error"]["code"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn wrong_method_gets_a_structured_405_with_allow() {
+        let (_, router) = recording_router();
+        let cleared = call(&router, "GET", "/canvas/clear", None).await;
+        assert_eq!(cleared.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(cleared.headers[header::ALLOW], "POST");
+        assert_eq!(cleared.json()["error"]["code"], "method_not_allowed");
+
+        let scoped = call(&router, "GET", "/scene/s/canvas/clear", None).await;
+        assert_eq!(scoped.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(scoped.headers[header::ALLOW], "POST");
+    }
+
+    #[tokio::test]
+    async fn fitted_exports_are_padded_on_every_side() {
+        let (_, router) = recording_router();
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+
+        let padded = call(
+            &router,
+            "GET",
+            "/canvas/export?format=svg&fit=true&padding=50",
+            None,
+        )
+        .await;
+        assert_eq!(padded.status, StatusCode::OK);
+        let svg = String::from_utf8(padded.body.to_vec()).expect("svg is UTF-8");
+        assert!(svg.contains(r#"viewBox="-40 -30 200 150""#), "{}", svg);
+
+        let negative = call(
+            &router,
+            "GET",
+            "/canvas/export?format=svg&fit=true&padding=-1",
+            None,
+        )
+        .await;
+        assert_eq!(negative.status, StatusCode::BAD_REQUEST);
+        assert_eq!(negative.json()["error"]["code"], "invalid_padding");
+    }
+
+    #[tokio::test]
+    async fn only_whitelisted_app_state_keys_are_stored() {
+        let (_, router) = recording_router();
+        for (method, uri) in [("PUT", "/canvas"), ("POST", "/draw")] {
+            let stored = call(
+                &router,
+                method,
+                uri,
+                Some(json!({
+                    "elements": [rectangle("a")],
+                    "appState": {
+                        "viewBackgroundColor": "#fafafa",
+                        "zoom": { "value": 2 },
+                        "collaborators": { "peer": {} },
+                        "openMenu": "canvas",
+                    },
+                })),
+            )
+            .await;
+            assert_eq!(stored.status, StatusCode::OK);
+
+            let canvas = call(&router, "GET", "/canvas", None).await.json();
+            assert_eq!(
+                canvas["canvas"]["appState"],
+                json!({ "viewBackgroundColor": "#fafafa", "zoom": { "value": 2 } }),
+                "{} {}",
+                method,
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn a_missing_whitelist_stores_app_state_as_sent() {
+        let (_, router) = router_with(ServerConfig {
+            rate_limit: 0,
+            app_state_keys: None,
+            ..ServerConfig::default()
+        });
+        let app_state = json!({ "viewBackgroundColor": "#fafafa", "collaborators": {} });
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [], "appState": app_state })),
+        )
+        .await;
+
+        let canvas = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(canvas["canvas"]["appState"], app_state);
+    }
+
+    #[tokio::test]
+    async fn optimized_svg_is_smaller_and_still_parses() {
+        let (_, router) = recording_router();
+        let mut shifted = rectangle("b");
+        shifted["x"] = json!(12.345678);
+        shifted["y"] = json!(-3.45678);
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("a"), shifted, linear("arrow", "c")] })),
+        )
+        .await;
+
+        let plain = call(&router, "GET", "/canvas/export?format=svg", None).await;
+        let optimized = call(
+            &router,
+            "GET",
+            "/canvas/export?format=svg&optimize=true",
+            None,
+        )
+        .await;
+        assert_eq!(optimized.status, StatusCode::OK);
+        assert!(optimized.body.len() < plain.body.len());
+
+        let svg = String::from_utf8(optimized.body.to_vec()).expect("svg is UTF-8");
+        assert!(svg.contains(r#"x="12.35""#), "{}", svg);
+        usvg::Tree::from_str(&svg, &svg_options()).expect("optimized SVG parses");
+    }
+
+    #[test]
+    fn rounding_handles_signs_and_packed_path_numbers() {
+        assert_eq!(round_numbers("M1.005-2.5L3.0 4.4444", 2), "M1-2.5L3 4.44");
+        assert_eq!(round_numbers("-0.001", 2), "0");
+        assert_eq!(
+            round_numbers("translate(1.23456 7)", 3),
+            "translate(1.235 7)"
+        );
+    }
+
+    #[tokio::test]
+    async fn events_carry_increasing_seq() {
+        let (sink, router) = recording_router();
+        for id in ["a", "b"] {
+            let added = call(
+                &router,
+                "POST",
+                "/canvas/element",
+                Some(json!({ "element": rectangle(id) })),
+            )
+            .await;
+            assert_eq!(added.status, StatusCode::OK);
+        }
+
+        let seqs: Vec<_> = sink
+            .take()
+            .into_iter()
+            .map(|(_, payload)| payload["seq"].as_u64().expect("seq"))
+            .collect();
+        assert_eq!(seqs, [1, 2]);
+    }
+
+    #[tokio::test]
+    async fn canvas_reports_the_latest_event_seq() {
+        let (_, router) = recording_router();
+        let before = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(before["seq"], 0);
+        call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+        let after = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(after["seq"], 1);
+    }
+
+    #[test]
+    fn closed_lines_render_as_filled_polygons() {
+        let mut line = linear("line", "a");
+        line["points"] = json!([[0, 0], [50, 50], [100, 0], [0, 0]]);
+        line["backgroundColor"] = json!("#ffc9c9");
+        let svg = element_svg(&line).expect("closed line renders");
+        assert!(svg.starts_with("<polygon points="), "{}", svg);
+        assert!(svg.contains(r##"fill="#ffc9c9""##), "{}", svg);
+        // The repeated start point is implied by the polygon closing itself
+        assert_eq!(svg.matches(',').count(), 3, "{}", svg);
+    }
+
+    #[test]
+    fn polygon_flag_closes_a_line_without_a_repeated_end() {
+        let mut line = linear("line", "a");
+        line["polygon"] = json!(true);
+        let svg = element_svg(&line).expect("flagged line renders");
+        assert!(svg.starts_with("<polygon "), "{}", svg);
+
+        let open = element_svg(&linear("line", "b")).expect("open line renders");
+        assert!(open.contains("<polyline "), "{}", open);
+        assert!(open.contains(r#"fill="none""#), "{}", open);
+    }
+
+    /// A router whose admin routes accept `key`, and the token `/admin/shutdown` cancels.
+    fn admin_router(key: Option<&str>) -> (CancellationToken, Router) {
+        let mut state = AppState::new(
+            Arc::new(RecordingSink::default()),
+            ServerConfig {
+                rate_limit: 0,
+                api_key: key.map(str::to_string),
+                ..ServerConfig::default()
+            },
+        );
+        let shutdown = CancellationToken::new();
+        state.shutdown = Some(shutdown.clone());
+        (shutdown, create_router(state))
+    }
+
+    async fn shutdown_with(router: &Router, key: Option<&str>) -> Reply {
+        let mut request = Request::builder().method("POST").uri("/admin/shutdown");
+        if let Some(key) = key {
+            request = request.header(API_KEY_HEADER, key);
+        }
+        send(router, request.body(Body::empty()).expect("valid request")).await
+    }
+
+    #[tokio::test]
+    async fn admin_shutdown_with_the_key_cancels_the_server() {
+        let (shutdown, router) = admin_router(Some("secret"));
+        let accepted = shutdown_with(&router, Some("secret")).await;
+        assert_eq!(accepted.status, StatusCode::ACCEPTED);
+        assert!(shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn admin_shutdown_rejects_wrong_or_missing_keys() {
+        let (shutdown, router) = admin_router(Some("secret"));
+        for key in [None, Some("guess!"), Some("secreT")] {
+            let rejected = shutdown_with(&router, key).await;
+            assert_eq!(rejected.status, StatusCode::UNAUTHORIZED, "{:?}", key);
+            assert_eq!(rejected.json()["error"]["code"], "invalid_api_key");
+        }
+        assert!(!shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn admin_shutdown_is_forbidden_without_a_configured_key() {
+        let (shutdown, router) = admin_router(None);
+        let forbidden = shutdown_with(&router, Some("anything")).await;
+        assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+        assert_eq!(forbidden.json()["error"]["code"], "admin_disabled");
+        assert!(!shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn element_update_logs_carry_the_element_id() {
+        let (_, router) = recording_router();
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::INFO)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let mut moved = rectangle("a");
+        moved["x"] = json!(40.0);
+        let updated = call(
+            &router,
+            "PUT",
+            "/canvas/element/a",
+            Some(json!({ "element": moved })),
+        )
+        .await;
+        assert_eq!(updated.status, StatusCode::OK);
+
+        let records = logs.records();
+        let element_logs: Vec<_> = records
+            .iter()
+            .filter(|record| record["target"] == "canvas_element")
+            .collect();
+        assert!(!element_logs.is_empty());
+        for record in element_logs {
+            assert_eq!(record["span"]["name"], "element_update", "{}", record);
+            assert_eq!(record["span"]["id"], "a", "{}", record);
+            assert_eq!(record["spans"][0]["name"], "http_request", "{}", record);
+        }
+    }
+
+    #[test]
+    fn skipped_types_produce_no_svg() {
+        let mut selection = rectangle("s");
+        selection["type"] = json!("selection");
+        assert_eq!(element_svg(&selection), None);
+
+        let unknown = UnknownTypes {
+            skip: vec!["rectangle".to_string()],
+            best_effort: Vec::new(),
+        };
+        assert_eq!(
+            convert_element_to_svg(&rectangle("a"), None, &unknown),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_placeholders_unless_best_effort() {
+        let mut widget = rectangle("w");
+        widget["type"] = json!("widget");
+        let placeholder = element_svg(&widget).expect("placeholder renders");
+        assert!(placeholder.contains("stroke-dasharray"), "{}", placeholder);
+
+        let unknown = UnknownTypes {
+            best_effort: vec!["widget".to_string()],
+            ..UnknownTypes::default()
+        };
+        let drawn = convert_element_to_svg(&widget, None, &unknown).expect("box renders");
+        assert!(drawn.starts_with("<rect "), "{}", drawn);
+        assert!(drawn.contains(r##"stroke="#1e1e1e""##), "{}", drawn);
+    }
+
+    #[test]
+    fn magic_frames_render_like_frames() {
+        let mut frame = rectangle("f");
+        frame["type"] = json!("frame");
+        frame["name"] = json!("Ideas");
+        let mut magic = frame.clone();
+        magic["type"] = json!("magicframe");
+        assert_eq!(element_svg(&magic), element_svg(&frame));
+    }
+
+    #[tokio::test]
+    async fn transform_moves_and_scales_every_element() {
+        let (sink, router) = recording_router();
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("a"), linear("line", "b")] })),
+        )
+        .await;
+        sink.take();
+
+        let transformed = call(
+            &router,
+            "POST",
+            "/canvas/transform",
+            Some(json!({ "dx": 5, "dy": -10, "scale": 2 })),
+        )
+        .await;
+        assert_eq!(transformed.status, StatusCode::OK);
+        assert_eq!(transformed.json()["transformed"], 2);
+        assert_eq!(sink.take_names(), [EVENT_DRAW]);
+
+        let canvas = call(&router, "GET", "/canvas", None).await.json();
+        let rect = &canvas["canvas"]["elements"][0];
+        assert_eq!(
+            [&rect["x"], &rect["y"], &rect["width"], &rect["height"]],
+            [&json!(25.0), &json!(30.0), &json!(200.0), &json!(100.0)]
+        );
+        assert_eq!(rect["strokeWidth"], 4.0);
+        let line = &canvas["canvas"]["elements"][1];
+        assert_eq!(
+            line["points"],
+            json!([[0.0, 0.0], [100.0, 100.0], [200.0, 0.0]])
+        );
+    }
+
+    #[tokio::test]
+    async fn transform_rejects_non_positive_scale() {
+        let (_, router) = recording_router();
+        let rejected = call(
+            &router,
+            "POST",
+            "/canvas/transform",
+            Some(json!({ "scale": 0 })),
+        )
+        .await;
+        assert_eq!(rejected.status, StatusCode::BAD_REQUEST);
+        assert_eq!(rejected.json()["error"]["code"], "invalid_scale");
+    }
+
+    #[tokio::test]
+    async fn a_prefix_namespaces_emitted_events() {
+        let (sink, router) = router_with(ServerConfig {
+            rate_limit: 0,
+            event_prefix: Some("embed".to_string()),
+            ..ServerConfig::default()
+        });
+        call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+        call(&router, "POST", "/canvas/clear", None).await;
+        assert_eq!(
+            sink.take_names(),
+            ["embed:excalidraw_draw", "embed:excalidraw_clear"]
+        );
+    }
+
+    /// Fails the first `failures` emits, then records like `RecordingSink`.
+    #[derive(Default)]
+    struct FlakySink {
+        failures: std::sync::atomic::AtomicU32,
+        delivered: RecordingSink,
+    }
+
+    impl EventSink for FlakySink {
+        fn emit(&self, event: &str, payload: &Value) -> anyhow::Result<()> {
+            let remaining = self.failures.load(Ordering::SeqCst);
+            if remaining > 0 {
+                self.failures.store(remaining - 1, Ordering::SeqCst);
+                anyhow::bail!("webview is reloading");
+            }
+            self.delivered.emit(event, payload)
+        }
+    }
+
+    async fn draw_through(failures: u32) -> (Arc<FlakySink>, Router, Reply) {
+        let sink = Arc::new(FlakySink {
+            failures: failures.into(),
+            ..FlakySink::default()
+        });
+        let config = ServerConfig {
+            rate_limit: 0,
+            ..ServerConfig::default()
+        };
+        let router = create_router(AppState::new(sink.clone(), config));
+        let drawn = call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+        .await;
+        (sink, router, drawn)
+    }
+
+    #[tokio::test]
+    async fn emits_are_retried_until_delivered() {
+        let (sink, _, drawn) = draw_through(EMIT_ATTEMPTS - 1).await;
+        assert_eq!(drawn.status, StatusCode::OK);
+        assert_eq!(sink.delivered.take_names(), [EVENT_DRAW]);
+    }
+
+    #[tokio::test]
+    async fn exhausted_retries_fail_but_keep_the_change() {
+        let (sink, router, drawn) = draw_through(EMIT_ATTEMPTS).await;
+        assert_eq!(drawn.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(drawn.json()["error"]["code"], "emit_failed");
+        assert!(sink.delivered.take().is_empty());
+        assert_eq!(canvas_element_ids(&router).await, ["a"]);
+    }
+
+    fn indexed(id: &str, index: Value) -> Value {
+        let mut element = rectangle(id);
+        element["strokeColor"] = json!(format!("#00000{}", id));
+        element["index"] = index;
+        element
+    }
+
+    fn painted_ids(elements: Value) -> Vec<String> {
+        draw_order(elements.as_array().expect("array"))
+            .into_iter()
+            .map(|element| element["id"].as_str().expect("id").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn svg_paints_elements_in_index_order() {
+        let elements = json!([
+            indexed("1", json!("a2")),
+            indexed("2", json!("a0")),
+            indexed("3", json!("a1")),
+        ]);
+        let view_box = ViewBox {
+            x: 0.0,
+            y: 0.0,
+            width: 200.0,
+            height: 100.0,
+        };
+        let svg = generate_svg(
+            &elements,
+            "white",
+            None,
+            200,
+            100,
+            view_box,
+            &UnknownTypes::default(),
+        );
+        let position = |id: &str| svg.find(&format!("#00000{}", id)).expect("element drawn");
+        assert!(position("2") < position("3") && position("3") < position("1"));
+    }
+
+    #[test]
+    fn draw_order_is_stable_and_needs_every_index() {
+        assert_eq!(
+            painted_ids(json!([
+                indexed("1", json!(2)),
+                indexed("2", json!(1)),
+                indexed("3", json!(1)),
+            ])),
+            ["2", "3", "1"]
+        );
+        // One element without an index leaves the array order alone
+        assert_eq!(
+            painted_ids(json!([
+                indexed("1", json!("a1")),
+                rectangle("2"),
+                indexed("3", json!("a0"))
+            ])),
+            ["1", "2", "3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn replace_clears_what_put_would_keep() {
+        let (_, router) = recording_router();
+        let files =
+            json!({ "f": { "mimeType": "image/png", "dataURL": "data:image/png;base64,AA==" } });
+        let with_files = json!({ "elements": [rectangle("a")], "files": files });
+
+        call(&router, "PUT", "/canvas", Some(with_files.clone())).await;
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("b")] })),
+        )
+        .await;
+        let merged = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(merged["canvas"]["files"], files);
+
+        let replaced = call(
+            &router,
+            "POST",
+            "/canvas/replace",
+            Some(json!({ "elements": [rectangle("c")] })),
+        )
+        .await;
+        assert_eq!(replaced.status, StatusCode::OK);
+        let canvas = call(&router, "GET", "/canvas", None).await.json();
+        assert_eq!(canvas["canvas"]["files"], Value::Null);
+        assert_eq!(canvas_element_ids(&router).await, ["c"]);
+
+        let metrics = call(&router, "GET", "/metrics", None).await;
+        let text = String::from_utf8(metrics.body.to_vec()).expect("metrics are UTF-8");
+        assert!(text.contains(
+            "extauri_http_requests_total{method=\"POST\",route=\"/canvas/replace\",status=\"200\"} 1\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn precision_rounds_long_decimal_coordinates() {
+        let (_, router) = recording_router();
+        let mut element = rectangle("a");
+        element["x"] = json!(123.45678901234);
+        element["width"] = json!(99.999999);
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [element] })),
+        )
+        .await;
+
+        let exported = call(
+            &router,
+            "GET",
+            "/canvas/export?format=svg&precision=2",
+            None,
+        )
+        .await;
+        let svg = String::from_utf8(exported.body.to_vec()).expect("svg is UTF-8");
+        assert!(svg.contains(r#"x="123.46""#), "{}", svg);
+        assert!(svg.contains(r#"width="100""#), "{}", svg);
+        assert!(!svg.contains("123.4567"), "{}", svg);
+    }
+
+    #[tokio::test]
+    async fn json_precision_leaves_version_and_seed_alone() {
+        let (_, router) = recording_router();
+        let mut element = rectangle("a");
+        element["x"] = json!(1.23456);
+        element["points"] = json!([[0.0, 0.0], [10.987654, 2.5]]);
+        element["seed"] = json!(1_234_567_891);
+        element["versionNonce"] = json!(987_654_321);
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [element] })),
+        )
+        .await;
+        let stored = call(&router, "GET", "/canvas", None).await.json();
+        let version = stored["canvas"]["elements"][0]["version"].clone();
+
+        let exported = call(
+            &router,
+            "GET",
+            "/canvas/export?format=json&elements_only=true&precision=1",
+            None,
+        )
+        .await
+        .json();
+        let element = &exported[0];
+        assert_eq!(element["x"], 1.2);
+        assert_eq!(element["points"], json!([[0.0, 0.0], [11.0, 2.5]]));
+        assert_eq!(element["seed"], 1_234_567_891);
+        assert_eq!(element["versionNonce"], 987_654_321);
+        assert_eq!(element["version"], version);
+    }
+
+    fn freedraw(extra: Value) -> Value {
+        let mut element = json!({
+            "id": "f",
+            "type": "freedraw",
+            "x": 10.0,
+            "y": 20.0,
+            "width": 20.0,
+            "height": 5.0,
+            "strokeColor": "#1e1e1e",
+            "backgroundColor": "transparent",
+            "strokeWidth": 2,
+            "points": [[0.0, 0.0], [5.0, 5.0], [20.0, 0.0]],
+        });
+        for (key, value) in extra.as_object().expect("object") {
+            element[key] = value.clone();
+        }
+        element
+    }
+
+    #[test]
+    fn highlighter_freedraw_is_wide_and_translucent() {
+        for marker in [
+            json!({ "strokeStyle": "highlighter" }),
+            json!({ "highlighter": true }),
+        ] {
+            let svg = element_svg(&freedraw(marker)).expect("highlighter renders");
+            assert!(svg.contains(r#"stroke-opacity="0.4""#), "{}", svg);
+            assert!(svg.contains(r#"stroke-width="8""#), "{}", svg);
+            assert!(svg.contains(r#"stroke-linecap="round""#), "{}", svg);
+        }
+
+        let plain = element_svg(&freedraw(json!({}))).expect("freedraw renders");
+        assert!(!plain.contains("stroke-opacity"), "{}", plain);
+        assert!(plain.contains(r#"stroke-width="2""#), "{}", plain);
+    }
+
+    #[tokio::test]
+    async fn ready_once_health_answers() {
+        let sink = Arc::new(RecordingSink::default());
+        let state = AppState::new(sink.clone(), ServerConfig::default());
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("bind an ephemeral port");
+        let addr = listener.local_addr().expect("bound address");
+        assert!(!state.server_info().running);
+
+        let shutdown = CancellationToken::new();
+        let (ready_tx, ready) = oneshot::channel();
+        let server = tokio::spawn(serve_http(
+            state.clone(),
+            listener,
+            addr,
+            shutdown.clone(),
+            ready_tx,
+        ));
+
+        let port = ready
+            .await
+            .expect("readiness sent")
+            .expect("server started");
+        assert_eq!(port, addr.port());
+        let info = state.server_info();
+        assert!(info.running);
+        assert_eq!(info.port, addr.port());
+        // Readiness means the very next request is answered
+        assert!(probe_health(addr).await.expect("health request"));
+
+        shutdown.cancel();
+        server.await.expect("server task").expect("clean shutdown");
+        assert!(!state.server_info().running);
+        assert!(sink.take_names().is_empty());
+    }
+
+    #[test]
+    fn text_without_a_size_still_renders() {
+        let svg = element_svg(&json!({
+            "id": "t",
+            "type": "text",
+            "x": 10.0,
+            "y": 20.0,
+            "text": "hello",
+            "fontSize": 20,
+        }))
+        .expect("text renders");
+        assert!(svg.contains("hello"), "{}", svg);
+    }
+
+    #[test]
+    fn missing_sizes_are_derived_from_content() {
+        let (width, height) = element_size(&json!({
+            "type": "text",
+            "text": "ab\nabcd",
+            "fontSize": 10,
+            "lineHeight": 1.5,
+        }));
+        assert!((width - 4.0 * 10.0 * AVG_GLYPH_WIDTH_EM).abs() < 1e-9);
+        assert!((height - 30.0).abs() < 1e-9);
+
+        let freedraw = json!({ "type": "freedraw", "points": [[0, 0], [-5, 8], [20, 3]] });
+        assert_eq!(element_size(&freedraw), (25.0, 8.0));
+        // Given sizes win over derived ones
+        assert_eq!(element_size(&rectangle("a")), (100.0, 50.0));
+    }
+
+    #[test]
+    fn freedraw_without_a_size_or_style_still_renders() {
+        let svg = element_svg(&json!({
+            "id": "f",
+            "type": "freedraw",
+            "x": 0.0,
+            "y": 0.0,
+            "points": [[0.0, 0.0], [5.0, 5.0], [20.0, 0.0]],
+        }))
+        .expect("freedraw renders");
+        assert!(svg.starts_with("<polyline"), "{}", svg);
+        assert!(svg.contains(r##"stroke="#000000""##), "{}", svg);
+    }
+
+    #[tokio::test]
+    async fn history_lists_a_draw_then_a_clear() {
+        let (_, router) = recording_router();
+        call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+        )
+        .await;
+        call(&router, "POST", "/canvas/clear", None).await;
+
+        let history = call(&router, "GET", "/canvas/history", None).await;
+        assert_eq!(history.status, StatusCode::OK);
+        let changes = history.json();
+        let changes = changes.as_array().expect("list of changes");
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0]["kind"], "draw");
+        assert_eq!(changes[0]["element_count"], 2);
+        assert_eq!(changes[1]["kind"], "clear");
+        assert_eq!(changes[1]["element_count"], 0);
+        assert_eq!(changes[0]["revision"], 1);
+        assert_eq!(changes[1]["revision"], 2);
+        assert!(changes[0].get("elements").is_none());
+    }
+
+    #[tokio::test]
+    async fn history_of_an_unknown_scene_is_not_found() {
+        let (_, router) = recording_router();
+        let history = call(&router, "GET", "/scene/nope/canvas/history", None).await;
+        assert_eq!(history.status, StatusCode::NOT_FOUND);
+    }
+
+    fn text_with_font(font_family: Value) -> Value {
+        json!({
+            "id": "t",
+            "type": "text",
+            "x": 0.0,
+            "y": 0.0,
+            "text": "hi",
+            "fontSize": 20,
+            "fontFamily": font_family,
+        })
+    }
+
+    #[test]
+    fn newer_font_ids_map_to_their_names() {
+        let svg = element_svg(&text_with_font(json!(5))).expect("text renders");
+        assert!(svg.contains("Excalifont"), "{}", svg);
+        let unknown = element_svg(&text_with_font(json!(42))).expect("text renders");
+        assert!(unknown.contains("sans-serif"), "{}", unknown);
+        assert!(!unknown.contains("Virgil"), "{}", unknown);
+    }
+
+    #[test]
+    fn font_family_names_are_used_as_given_and_escaped() {
+        let svg = element_svg(&text_with_font(json!(" Fira Code "))).expect("text renders");
+        assert!(svg.contains("Fira Code"), "{}", svg);
+        assert!(!svg.contains(" Fira Code "), "{}", svg);
+        let sneaky = element_svg(&text_with_font(json!("A\"B"))).expect("text renders");
+        assert!(sneaky.contains("A&quot;B"), "{}", sneaky);
+        let default = element_svg(&text_with_font(Value::Null)).expect("text renders");
+        assert!(default.contains("Virgil"), "{}", default);
+    }
+
+    #[tokio::test]
+    async fn cancelling_shutdown_lets_in_flight_requests_finish() {
+        let state = AppState::new(Arc::new(RecordingSink::default()), ServerConfig::default());
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("bind an ephemeral port");
+        let addr = listener.local_addr().expect("bound address");
+        let shutdown = CancellationToken::new();
+        let (ready_tx, ready) = oneshot::channel();
+        let server = tokio::spawn(serve_http(
+            state.clone(),
+            listener,
+            addr,
+            shutdown.clone(),
+            ready_tx,
+        ));
+        ready
+            .await
+            .expect("readiness sent")
+            .expect("server started");
+
+        // Send the headers but hold back the body, so the request is mid-flight on cancel
+        let body = json!({ "elements": [rectangle("a")] }).to_string();
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        let head = format!(
+            "POST /draw HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+            addr,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.expect("send head");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        stream.write_all(body.as_bytes()).await.expect("send body");
+        let mut response = Vec::new();
+        stream
+            .read_to_end(&mut response)
+            .await
+            .expect("read response");
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("serve resolves after cancel")
+            .expect("server task")
+            .expect("clean shutdown");
+        let scenes = state.scenes.read().await;
+        assert_eq!(count_elements(scenes[DEFAULT_SCENE].elements.as_ref()), 1);
+    }
+
+    #[tokio::test]
+    async fn socket_edits_are_rate_limited() {
+        let config = ServerConfig {
+            rate_limit: 1,
+            ..ServerConfig::default()
+        };
+        let state = AppState::new(Arc::new(RecordingSink::default()), config);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let draw = json!({ "type": "draw", "elements": [] }).to_string();
+
+        apply_socket_message(&state, client, &draw)
+            .await
+            .expect("first edit fits the limit");
+        let limited = apply_socket_message(&state, client, &draw).await;
+        assert!(matches!(limited, Err(ApiError::RateLimited(_))));
+    }
+
+    #[tokio::test]
+    async fn element_ids_count_elements_without_an_id_separately() {
+        let state = AppState::new(Arc::new(RecordingSink::default()), ServerConfig::default());
+        // Validation keeps id-less elements out of the API, so store one directly
+        let mut unkeyed = rectangle("x");
+        unkeyed.as_object_mut().unwrap().remove("id");
+        state.scenes.write().await.insert(
+            DEFAULT_SCENE.to_string(),
+            CanvasData {
+                elements: Some(json!([
+                    rectangle("a"),
+                    rectangle("b"),
+                    unkeyed,
+                    rectangle("c")
+                ])),
+                ..CanvasData::empty()
+            },
+        );
+        let router = create_router(state);
+
+        let listed = call(&router, "GET", "/canvas/elements/ids", None).await;
+        assert_eq!(listed.status, StatusCode::OK);
+        assert_eq!(
+            listed.json(),
+            json!({ "ids": ["a", "b", "c"], "count": 3, "unkeyed": 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn idle_scenes_clear_once_after_the_ttl() {
+        let ttl = Duration::from_millis(400);
+        let sink = Arc::new(RecordingSink::default());
+        let state = AppState::new(sink.clone(), ServerConfig::default());
+        let draw = DrawPayload {
+            elements: Some(json!([rectangle("a")])),
+            app_state: None,
+            files: None,
+        };
+        let elements = |state: AppState| async move {
+            let scenes = state.scenes.read().await;
+            count_elements(scenes[DEFAULT_SCENE].elements.as_ref())
+        };
+        let shutdown = CancellationToken::new();
+        tokio::spawn(clear_idle_scenes(state.clone(), ttl, shutdown.clone()));
+
+        apply_draw(&state, DEFAULT_SCENE, &draw)
+            .await
+            .expect("draw");
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        // A change restarts the TTL
+        apply_draw(&state, DEFAULT_SCENE, &draw)
+            .await
+            .expect("draw");
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(elements(state.clone()).await, 1);
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(elements(state.clone()).await, 0);
+        // Later ticks leave the already-empty scene alone
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        shutdown.cancel();
+        let names = sink.take_names();
+        assert_eq!(names.iter().filter(|name| *name == EVENT_CLEAR).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_taken_port_is_reported_as_a_bind_failure() {
+        // Whoever holds the port, this test or another process, the server can't bind it
+        let _taken = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, DEFAULT_PORT))
+            .await
+            .ok();
+        let sink = Arc::new(RecordingSink::default());
+        let state = AppState::new(sink.clone(), ServerConfig::default());
+        let (ready_tx, ready) = oneshot::channel();
+
+        let result = start_http_server(state.clone(), CancellationToken::new(), ready_tx).await;
+        assert!(matches!(result, Err(ServerError::Bind { .. })));
+        let reason = ready
+            .await
+            .expect("readiness sent")
+            .expect_err("the server did not start");
+        assert!(
+            reason.starts_with("could not listen on 127.0.0.1:"),
+            "{}",
+            reason
+        );
+
+        let error = state.server_info().error.expect("failure recorded");
+        assert_eq!(error.stage, "bind");
+        let events = sink.take();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, EVENT_SERVER_FAILED);
+        assert_eq!(events[0].1["stage"], "bind");
+    }
+
+    #[tokio::test]
+    async fn draw_events_carry_only_the_retained_app_state() {
+        let (sink, router) = recording_router();
+        for (method, uri) in [("PUT", "/canvas"), ("POST", "/draw")] {
+            call(
+                &router,
+                method,
+                uri,
+                Some(json!({
+                    "elements": [rectangle("a")],
+                    "appState": {
+                        "viewBackgroundColor": "#fafafa",
+                        "collaborators": { "peer": {} },
+                    },
+                })),
+            )
+            .await;
+
+            let events = sink.take();
+            assert_eq!(events.len(), 1, "{} {}", method, uri);
+            assert_eq!(
+                events[0].1["appState"],
+                json!({ "viewBackgroundColor": "#fafafa" }),
+                "{} {}",
+                method,
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_shutdown_rejects_remote_clients_even_with_the_key() {
+        let (shutdown, router) = admin_router(Some("secret"));
+        let mut request = Request::builder()
+            .method("POST")
+            .uri("/admin/shutdown")
+            .header(API_KEY_HEADER, "secret")
+            .body(Body::empty())
+            .expect("valid request");
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([192, 168, 1, 20], 51000))));
+
+        let rejected = send(&router, request).await;
+        assert_eq!(rejected.status, StatusCode::FORBIDDEN);
+        assert_eq!(rejected.json()["error"]["code"], "admin_not_local");
+        assert!(!shutdown.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn svg_coordinates_are_only_rounded_on_request() {
+        let (_, router) = recording_router();
+        let mut element = rectangle("a");
+        element["x"] = json!(123.45678901234);
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [element] })),
+        )
+        .await;
+
+        let plain = call(&router, "GET", "/canvas/export?format=svg", None).await;
+        let svg = String::from_utf8(plain.body.to_vec()).expect("svg is UTF-8");
+        assert!(svg.contains("123.45678901234"), "{}", svg);
+
+        let optimized = call(
+            &router,
+            "GET",
+            "/canvas/export?format=svg&optimize=true",
+            None,
+        )
+        .await;
+        let svg = String::from_utf8(optimized.body.to_vec()).expect("svg is UTF-8");
+        assert!(svg.contains(r#"x="123.46""#), "{}", svg);
+    }
 }
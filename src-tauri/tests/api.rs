@@ -0,0 +1,417 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use extauri_lib::ServerConfig;
+use serde_json::json;
+use tower::ServiceExt;
+
+use common::{call, element_ids, rectangle, router, router_with, send};
+
+#[tokio::test]
+async fn draw_then_get_canvas() {
+    let router = router();
+
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::OK);
+    assert_eq!(drawn.json(), json!({ "success": true }));
+
+    let canvas = call(&router, "GET", "/canvas", None).await;
+    assert_eq!(canvas.status, StatusCode::OK);
+    assert!(canvas.headers.contains_key("etag"));
+    let body = canvas.json();
+    assert_eq!(body["canvas"]["elements"][0], rectangle("a"));
+    assert_eq!(element_ids(&router).await, ["a", "b"]);
+}
+
+#[tokio::test]
+async fn draw_rejects_invalid_elements() {
+    let router = router();
+
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [{ "type": "rectangle" }] })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::BAD_REQUEST);
+    assert_eq!(drawn.json()["error"]["code"], "invalid_elements");
+    assert!(element_ids(&router).await.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_reads_see_whole_writes() {
+    let router = router();
+
+    // Write k elements named after k, so a read can tell a torn canvas from a whole one
+    let writer = {
+        let router = router.clone();
+        tokio::spawn(async move {
+            for round in 1..=20 {
+                let elements: Vec<_> = (0..round)
+                    .map(|index| rectangle(&format!("w{}-{}", round, index)))
+                    .collect();
+                let written = call(
+                    &router,
+                    "PUT",
+                    "/canvas",
+                    Some(json!({ "elements": elements })),
+                )
+                .await;
+                assert_eq!(written.status, StatusCode::OK);
+            }
+        })
+    };
+
+    let readers: Vec<_> = (0..50)
+        .map(|_| {
+            let router = router.clone();
+            tokio::spawn(async move {
+                let read = call(&router, "GET", "/canvas", None).await;
+                assert_eq!(read.status, StatusCode::OK);
+                let canvas = read.json();
+                let ids: Vec<&str> = canvas["canvas"]["elements"]
+                    .as_array()
+                    .map(|elements| elements.iter().filter_map(|e| e["id"].as_str()).collect())
+                    .unwrap_or_default();
+                if let Some(first) = ids.first() {
+                    let round = first[1..].split('-').next().expect("round");
+                    assert_eq!(ids.len().to_string(), round);
+                    assert!(ids.iter().all(|id| id.starts_with(&format!("w{}-", round))));
+                }
+            })
+        })
+        .collect();
+
+    for reader in readers {
+        reader.await.expect("reader task");
+    }
+    writer.await.expect("writer task");
+}
+
+#[tokio::test]
+async fn patch_replaces_a_field_in_place() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+
+    let patched = call(
+        &router,
+        "PATCH",
+        "/canvas",
+        Some(json!([{ "op": "replace", "path": "/elements/0/x", "value": 42 }])),
+    )
+    .await;
+    assert_eq!(patched.status, StatusCode::OK);
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    assert_eq!(canvas["canvas"]["elements"][0]["x"], 42);
+    assert_eq!(canvas["canvas"]["elements"][0]["y"], rectangle("a")["y"]);
+}
+
+#[tokio::test]
+async fn patch_with_a_missing_path_leaves_the_canvas_unchanged() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    let before = call(&router, "GET", "/canvas", None).await;
+
+    let patched = call(
+        &router,
+        "PATCH",
+        "/canvas",
+        Some(json!([
+            { "op": "replace", "path": "/elements/0/x", "value": 42 },
+            { "op": "replace", "path": "/elements/5/x", "value": 42 },
+        ])),
+    )
+    .await;
+    assert_eq!(patched.status, StatusCode::BAD_REQUEST);
+    assert_eq!(patched.json()["error"]["code"], "patch_failed");
+    assert_eq!(patched.json()["error"]["operation"], 1);
+
+    let after = call(&router, "GET", "/canvas", None).await;
+    assert_eq!(after.json(), before.json());
+}
+
+#[tokio::test]
+async fn bodies_over_the_limit_get_413() {
+    let router = router_with(ServerConfig {
+        max_body_bytes: 1024,
+        ..ServerConfig::default()
+    });
+    let files = json!({ "image": { "dataURL": "x".repeat(2048) } });
+
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")], "files": files })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(drawn.json()["error"]["code"], "body_rejected");
+    assert!(element_ids(&router).await.is_empty());
+
+    let small = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    assert_eq!(small.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn stats_count_elements_by_type() {
+    let router = router();
+    let empty = call(&router, "GET", "/canvas/stats", None).await;
+    assert_eq!(empty.status, StatusCode::OK);
+    assert_eq!(empty.json()["total"], 0);
+    assert_eq!(empty.json()["by_type"], json!({}));
+    assert_eq!(empty.json()["approx_bytes"], 0);
+
+    let text = json!({
+        "id": "t",
+        "type": "text",
+        "x": 0.0,
+        "y": 0.0,
+        "width": 40.0,
+        "height": 20.0,
+        "text": "hi",
+    });
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b"), text] })),
+    )
+    .await;
+
+    let stats = call(&router, "GET", "/canvas/stats", None).await.json();
+    assert_eq!(stats["total"], 3);
+    assert_eq!(stats["by_type"], json!({ "rectangle": 2, "text": 1 }));
+    assert!(stats["approx_bytes"]
+        .as_u64()
+        .is_some_and(|bytes| bytes > 0));
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    assert_eq!(stats["updated_at"], canvas["canvas"]["updated_at"]);
+}
+
+#[tokio::test]
+async fn health_stays_plain_and_detailed_health_reports_the_server() {
+    let router = router();
+    let health = call(&router, "GET", "/health", None).await;
+    assert_eq!(health.status, StatusCode::OK);
+    assert_eq!(&health.body[..], b"ok");
+
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    let detailed = call(&router, "GET", "/health/detailed", None).await.json();
+    assert_eq!(detailed["version"], env!("CARGO_PKG_VERSION"));
+    assert!(detailed["uptime_secs"]
+        .as_f64()
+        .is_some_and(|secs| secs >= 0.0));
+    assert_eq!(detailed["element_count"], 1);
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    assert_eq!(detailed["updated_at"], canvas["canvas"]["updated_at"]);
+}
+
+#[tokio::test]
+async fn emptying_an_unknown_scene_is_not_found() {
+    let router = router();
+
+    let reset = call(&router, "DELETE", "/scene/nope/canvas", None).await;
+    assert_eq!(reset.status, StatusCode::NOT_FOUND);
+    assert_eq!(reset.json()["error"]["code"], "scene_not_found");
+
+    let cleared = call(&router, "POST", "/scene/nope/canvas/clear", None).await;
+    assert_eq!(cleared.status, StatusCode::NOT_FOUND);
+    assert_eq!(cleared.json()["error"]["code"], "scene_not_found");
+
+    // Neither call created the scene
+    let canvas = call(&router, "GET", "/scene/nope/canvas", None).await;
+    assert_eq!(canvas.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reset_and_clear_empty_an_existing_scene() {
+    let router = router();
+    for (method, uri) in [("POST", "/canvas/clear"), ("DELETE", "/canvas")] {
+        let drawn = call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")], "appState": { "viewBackgroundColor": "#fff" } })),
+        )
+        .await;
+        assert_eq!(drawn.status, StatusCode::OK);
+
+        let emptied = call(&router, method, uri, None).await;
+        assert_eq!(emptied.status, StatusCode::OK);
+        assert!(element_ids(&router).await.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn large_canvas_streams_without_holding_the_lock() {
+    let router = router();
+    let data_url = format!("data:image/png;base64,{}", "A".repeat(256 * 1024));
+    let files: serde_json::Map<String, serde_json::Value> = (0..32)
+        .map(|index| {
+            let id = format!("file-{}", index);
+            let file = json!({ "id": id, "mimeType": "image/png", "dataURL": data_url });
+            (id, file)
+        })
+        .collect();
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")], "files": files })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::OK);
+
+    let request = Request::builder()
+        .uri("/canvas")
+        .body(Body::empty())
+        .expect("valid request");
+    let pending = router
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("router is infallible");
+    assert_eq!(pending.status(), StatusCode::OK);
+
+    // The body is still unread, yet a writer gets the lock straight away
+    let written = tokio::time::timeout(
+        Duration::from_secs(5),
+        call(
+            &router,
+            "PUT",
+            "/canvas",
+            Some(json!({ "elements": [rectangle("b")] })),
+        ),
+    )
+    .await
+    .expect("write waited on the pending read");
+    assert_eq!(written.status, StatusCode::OK);
+
+    let body = axum::body::to_bytes(pending.into_body(), usize::MAX)
+        .await
+        .expect("response body");
+    let canvas: serde_json::Value = serde_json::from_slice(&body).expect("JSON body");
+    assert_eq!(canvas["canvas"]["elements"][0]["id"], "a");
+    assert_eq!(
+        canvas["canvas"]["files"]
+            .as_object()
+            .map(|files| files.len()),
+        Some(32)
+    );
+}
+
+#[tokio::test]
+async fn matching_if_none_match_gets_304() {
+    let router = router();
+    let first = call(&router, "GET", "/canvas", None).await;
+    assert_eq!(first.status, StatusCode::OK);
+    let etag = first.headers["etag"].clone();
+
+    let conditional = |etag| {
+        let request = Request::get("/canvas")
+            .header("if-none-match", etag)
+            .body(Body::empty())
+            .expect("valid request");
+        send(&router, request)
+    };
+    let unchanged = conditional(etag.clone()).await;
+    assert_eq!(unchanged.status, StatusCode::NOT_MODIFIED);
+    assert!(unchanged.body.is_empty());
+    assert_eq!(unchanged.headers["etag"], etag);
+
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    let changed = conditional(etag.clone()).await;
+    assert_eq!(changed.status, StatusCode::OK);
+    assert_ne!(changed.headers["etag"], etag);
+}
+
+#[tokio::test]
+async fn writes_past_the_element_cap_get_422() {
+    let router = router_with(ServerConfig {
+        max_elements: Some(2),
+        ..ServerConfig::default()
+    });
+    let three = json!([rectangle("a"), rectangle("b"), rectangle("c")]);
+
+    let drawn = call(&router, "POST", "/draw", Some(json!({ "elements": three }))).await;
+    assert_eq!(drawn.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(drawn.json()["error"]["code"], "too_many_elements");
+    assert_eq!(drawn.json()["error"]["max"], 2);
+    let put = call(
+        &router,
+        "PUT",
+        "/canvas",
+        Some(json!({ "elements": three })),
+    )
+    .await;
+    assert_eq!(put.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let imported = call(
+        &router,
+        "POST",
+        "/canvas/import",
+        Some(json!({ "type": "excalidraw", "elements": three })),
+    )
+    .await;
+    assert_eq!(imported.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(element_ids(&router).await.is_empty());
+
+    let within = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+    )
+    .await;
+    assert_eq!(within.status, StatusCode::OK);
+    let added = call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("c") })),
+    )
+    .await;
+    assert_eq!(added.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(added.json()["error"]["current"], 2);
+    assert_eq!(element_ids(&router).await, ["a", "b"]);
+}
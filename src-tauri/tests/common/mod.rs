@@ -0,0 +1,96 @@
+//! Helpers shared by the API tests, which drive the router in-process through `oneshot`.
+
+// Each test binary compiles this module and uses only part of it
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::Router;
+use extauri_lib::{create_router, test_router, AppState, RecordingSink, ServerConfig};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+/// A router over an empty canvas, for tests that only look at HTTP responses.
+pub fn router() -> Router {
+    test_router(Arc::new(RecordingSink::default()))
+}
+
+/// A router over an empty canvas served with `config`.
+pub fn router_with(config: ServerConfig) -> Router {
+    create_router(AppState::new(Arc::new(RecordingSink::default()), config))
+}
+
+/// A response reduced to what the tests assert on.
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("response body is not JSON")
+    }
+}
+
+/// Send `request` through a clone of `router` and collect the whole response.
+pub async fn send(router: &Router, request: Request<Body>) -> TestResponse {
+    let response = router
+        .clone()
+        .oneshot(request)
+        .await
+        .expect("router is infallible");
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("response body");
+    TestResponse {
+        status,
+        headers,
+        body,
+    }
+}
+
+/// `method uri` with an optional JSON body.
+pub async fn call(router: &Router, method: &str, uri: &str, body: Option<Value>) -> TestResponse {
+    let builder = Request::builder().method(method).uri(uri);
+    let request = match body {
+        Some(body) => builder
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string())),
+        None => builder.body(Body::empty()),
+    };
+    send(router, request.expect("valid request")).await
+}
+
+/// A minimal rectangle that passes element validation.
+pub fn rectangle(id: &str) -> Value {
+    json!({
+        "id": id,
+        "type": "rectangle",
+        "x": 10.0,
+        "y": 20.0,
+        "width": 100.0,
+        "height": 50.0,
+        "strokeColor": "#1e1e1e",
+        "backgroundColor": "transparent",
+        "strokeWidth": 2,
+    })
+}
+
+/// Ids of the elements currently on the default scene.
+pub async fn element_ids(router: &Router) -> Vec<String> {
+    let canvas = call(router, "GET", "/canvas", None).await.json();
+    canvas["canvas"]["elements"]
+        .as_array()
+        .map(|elements| {
+            elements
+                .iter()
+                .filter_map(|element| element["id"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
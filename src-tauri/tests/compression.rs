@@ -0,0 +1,64 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use extauri_lib::ServerConfig;
+use serde_json::json;
+
+use common::{call, rectangle, router, router_with, send, TestResponse};
+
+async fn large_canvas(router: &Router) {
+    let elements: Vec<_> = (0..200).map(|i| rectangle(&format!("r{}", i))).collect();
+    let drawn = call(
+        router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": elements })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::OK);
+}
+
+async fn get_gzip(router: &Router, uri: &str) -> TestResponse {
+    let request = Request::get(uri)
+        .header("accept-encoding", "gzip")
+        .body(Body::empty())
+        .expect("valid request");
+    send(router, request).await
+}
+
+#[tokio::test]
+async fn large_canvases_are_gzipped_on_request() {
+    let router = router();
+    large_canvas(&router).await;
+
+    let canvas = get_gzip(&router, "/canvas").await;
+    assert_eq!(canvas.status, StatusCode::OK);
+    assert_eq!(canvas.headers["content-encoding"], "gzip");
+    assert!(canvas.body.starts_with(&[0x1f, 0x8b]));
+}
+
+#[tokio::test]
+async fn png_exports_are_not_compressed_again() {
+    let router = router();
+    large_canvas(&router).await;
+
+    let png = get_gzip(&router, "/canvas/export?format=png&width=50&height=50").await;
+    assert_eq!(png.status, StatusCode::OK);
+    assert!(!png.headers.contains_key("content-encoding"));
+}
+
+#[tokio::test]
+async fn compression_can_be_turned_off() {
+    let router = router_with(ServerConfig {
+        compression: false,
+        ..ServerConfig::default()
+    });
+    large_canvas(&router).await;
+
+    let canvas = get_gzip(&router, "/canvas").await;
+    assert_eq!(canvas.status, StatusCode::OK);
+    assert!(!canvas.headers.contains_key("content-encoding"));
+    assert!(canvas.json()["canvas"]["elements"].is_array());
+}
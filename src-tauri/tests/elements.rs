@@ -0,0 +1,536 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use extauri_lib::ServerConfig;
+use serde_json::json;
+
+use common::{call, element_ids, rectangle, router, router_with, send, TestResponse};
+
+#[tokio::test]
+async fn add_appends_one_element() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+
+    let added = call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("b") })),
+    )
+    .await;
+    assert_eq!(added.status, StatusCode::OK);
+    assert_eq!(added.json(), json!({ "success": true, "id": "b" }));
+    assert_eq!(element_ids(&router).await, ["a", "b"]);
+}
+
+#[tokio::test]
+async fn add_rejects_an_element_without_an_id() {
+    let router = router();
+
+    let mut element = rectangle("a");
+    element.as_object_mut().unwrap().remove("id");
+    let added = call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": element })),
+    )
+    .await;
+    assert_eq!(added.status, StatusCode::BAD_REQUEST);
+    assert_eq!(added.json()["error"]["code"], "invalid_elements");
+}
+
+#[tokio::test]
+async fn add_rejects_a_taken_id() {
+    let router = router();
+    let add = || {
+        call(
+            &router,
+            "POST",
+            "/canvas/element",
+            Some(json!({ "element": rectangle("a") })),
+        )
+    };
+
+    assert_eq!(add().await.status, StatusCode::OK);
+    let again = add().await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+    assert_eq!(again.json()["error"]["code"], "element_exists");
+    assert_eq!(element_ids(&router).await, ["a"]);
+}
+
+async fn stream(router: &Router, body: impl Into<Body>) -> TestResponse {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/canvas/elements/stream")
+        .header("content-type", "application/x-ndjson")
+        .body(body.into())
+        .expect("valid request");
+    send(router, request).await
+}
+
+#[tokio::test]
+async fn rejected_adds_leave_the_canvas_untouched() {
+    let router = router_with(ServerConfig {
+        max_elements: Some(0),
+        ..ServerConfig::default()
+    });
+    let before = call(&router, "GET", "/canvas", None).await;
+    assert!(before.json()["canvas"]["elements"].is_null());
+
+    let added = call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("a") })),
+    )
+    .await;
+    assert_eq!(added.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(added.json()["error"]["code"], "too_many_elements");
+
+    let streamed = stream(&router, format!("{}\n", rectangle("b"))).await;
+    assert_eq!(streamed.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let after = call(&router, "GET", "/canvas", None).await;
+    assert!(after.json()["canvas"]["elements"].is_null());
+    assert_eq!(after.headers["etag"], before.headers["etag"]);
+    assert_eq!(after.json()["hash"], before.json()["hash"]);
+}
+
+#[tokio::test]
+async fn stream_of_only_bad_lines_changes_nothing() {
+    let router = router();
+    let before = call(&router, "GET", "/canvas", None).await;
+
+    let streamed = stream(&router, "not json\n{\"type\": \"rectangle\"}\n").await;
+    assert_eq!(streamed.status, StatusCode::OK);
+    assert_eq!(streamed.json(), json!({ "added": 0, "skipped": [1, 2] }));
+
+    let after = call(&router, "GET", "/canvas", None).await;
+    assert!(after.json()["canvas"]["elements"].is_null());
+    assert_eq!(after.headers["etag"], before.headers["etag"]);
+}
+
+#[tokio::test]
+async fn get_returns_the_stored_element() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("a") })),
+    )
+    .await;
+
+    let fetched = call(&router, "GET", "/canvas/element/a", None).await;
+    assert_eq!(fetched.status, StatusCode::OK);
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    assert_eq!(fetched.json()["element"], canvas["canvas"]["elements"][0]);
+    assert_eq!(fetched.json()["element"]["x"], rectangle("a")["x"]);
+}
+
+#[tokio::test]
+async fn get_on_an_empty_canvas_is_not_found() {
+    let router = router();
+
+    let fetched = call(&router, "GET", "/canvas/element/a", None).await;
+    assert_eq!(fetched.status, StatusCode::NOT_FOUND);
+    assert_eq!(fetched.json()["error"]["code"], "element_not_found");
+}
+
+#[tokio::test]
+async fn order_actions_move_one_element() {
+    for (id, action, expected) in [
+        ("a", "front", ["b", "c", "a"]),
+        ("c", "back", ["c", "a", "b"]),
+        ("a", "forward", ["b", "a", "c"]),
+        ("c", "backward", ["a", "c", "b"]),
+    ] {
+        let router = router();
+        call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a"), rectangle("b"), rectangle("c")] })),
+        )
+        .await;
+
+        let ordered = call(
+            &router,
+            "POST",
+            &format!("/canvas/element/{}/order", id),
+            Some(json!({ "action": action })),
+        )
+        .await;
+        assert_eq!(ordered.status, StatusCode::OK, "{}", action);
+        assert_eq!(element_ids(&router).await, expected, "{}", action);
+    }
+}
+
+#[tokio::test]
+async fn order_rejects_unknown_ids_and_actions() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+    )
+    .await;
+
+    let missing = call(
+        &router,
+        "POST",
+        "/canvas/element/z/order",
+        Some(json!({ "action": "front" })),
+    )
+    .await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert_eq!(missing.json()["error"]["code"], "element_not_found");
+
+    let unknown = call(
+        &router,
+        "POST",
+        "/canvas/element/a/order",
+        Some(json!({ "action": "sideways" })),
+    )
+    .await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+    assert_eq!(unknown.json()["error"]["code"], "invalid_order_action");
+    assert_eq!(element_ids(&router).await, ["a", "b"]);
+}
+
+/// The ids in a `GET /canvas/elements` response.
+async fn queried_ids(router: &Router, uri: &str) -> Vec<String> {
+    let queried = call(router, "GET", uri, None).await;
+    assert_eq!(queried.status, StatusCode::OK);
+    queried.json()["elements"]
+        .as_array()
+        .expect("elements array")
+        .iter()
+        .filter_map(|element| element["id"].as_str().map(str::to_string))
+        .collect()
+}
+
+#[tokio::test]
+async fn region_queries_return_only_intersecting_elements() {
+    let router = router();
+    let mut far = rectangle("far");
+    far["x"] = json!(1000.0);
+    far["y"] = json!(1000.0);
+    // Overlaps the region's corner without lying inside it
+    let mut edge = rectangle("edge");
+    edge["x"] = json!(-50.0);
+    edge["y"] = json!(-30.0);
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("near"), far, edge] })),
+    )
+    .await;
+
+    assert_eq!(
+        queried_ids(&router, "/canvas/elements").await,
+        ["near", "far", "edge"]
+    );
+    assert_eq!(
+        queried_ids(&router, "/canvas/elements?x=0&y=0&w=200&h=200").await,
+        ["near", "edge"]
+    );
+
+    let partial = call(&router, "GET", "/canvas/elements?x=0&y=0", None).await;
+    assert_eq!(partial.status, StatusCode::BAD_REQUEST);
+    assert_eq!(partial.json()["error"]["code"], "incomplete_region");
+}
+
+fn versioned(id: &str, version: i64, x: f64) -> serde_json::Value {
+    let mut element = rectangle(id);
+    element["version"] = json!(version);
+    element["x"] = json!(x);
+    element
+}
+
+#[tokio::test]
+async fn update_with_the_current_version_applies() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": versioned("a", 3, 10.0) })),
+    )
+    .await;
+
+    let updated = call(
+        &router,
+        "PUT",
+        "/canvas/element/a",
+        Some(json!({ "element": versioned("a", 4, 50.0), "version": 3 })),
+    )
+    .await;
+    assert_eq!(updated.status, StatusCode::OK);
+
+    let stored = call(&router, "GET", "/canvas/element/a", None).await.json();
+    assert_eq!(stored["element"]["version"], 4);
+    assert_eq!(stored["element"]["x"], 50.0);
+}
+
+#[tokio::test]
+async fn update_with_a_stale_version_conflicts() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": versioned("a", 4, 10.0) })),
+    )
+    .await;
+
+    let request = Request::builder()
+        .method("PUT")
+        .uri("/canvas/element/a")
+        .header("content-type", "application/json")
+        .header("if-match", "3")
+        .body(Body::from(
+            json!({ "element": versioned("a", 4, 99.0) }).to_string(),
+        ))
+        .expect("valid request");
+    let stale = send(&router, request).await;
+    assert_eq!(stale.status, StatusCode::CONFLICT);
+    let error = stale.json()["error"].clone();
+    assert_eq!(error["code"], "version_conflict");
+    assert_eq!(error["current"]["version"], 4);
+    assert_eq!(error["current"]["x"], 10.0);
+
+    let stored = call(&router, "GET", "/canvas/element/a", None).await.json();
+    assert_eq!(stored["element"]["x"], 10.0);
+}
+
+fn text(id: &str, text: &str) -> serde_json::Value {
+    json!({
+        "id": id,
+        "type": "text",
+        "x": 10.0,
+        "y": 20.0,
+        "width": 100.0,
+        "height": 25.0,
+        "text": text,
+    })
+}
+
+#[tokio::test]
+async fn search_finds_text_ignoring_case() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({
+            "elements": [
+                text("t1", "Login page"),
+                text("t2", "Signup"),
+                text("t3", "LOGIN API"),
+                rectangle("r"),
+            ],
+        })),
+    )
+    .await;
+
+    let found = call(&router, "GET", "/canvas/search?q=login", None).await;
+    assert_eq!(found.status, StatusCode::OK);
+    let found = found.json();
+    assert_eq!(found["count"], 2);
+    let ids: Vec<&str> = found["results"]
+        .as_array()
+        .expect("results array")
+        .iter()
+        .filter_map(|result| result["id"].as_str())
+        .collect();
+    assert_eq!(ids, ["t1", "t3"]);
+    assert_eq!(found["results"][0]["x"], 10.0);
+    assert_eq!(found["results"][0]["text"], "Login page");
+}
+
+#[tokio::test]
+async fn empty_searches_are_rejected() {
+    let router = router();
+
+    let empty = call(&router, "GET", "/canvas/search?q=%20", None).await;
+    assert_eq!(empty.status, StatusCode::BAD_REQUEST);
+    assert_eq!(empty.json()["error"]["code"], "empty_search");
+}
+
+#[tokio::test]
+async fn update_cannot_change_the_id() {
+    let router = router();
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::OK);
+
+    let updated = call(
+        &router,
+        "PUT",
+        "/canvas/element/a",
+        Some(json!({ "element": rectangle("b") })),
+    )
+    .await;
+    assert_eq!(updated.status, StatusCode::BAD_REQUEST);
+    assert_eq!(updated.json()["error"]["code"], "invalid_elements");
+    assert_eq!(element_ids(&router).await, ["a", "b"]);
+}
+
+#[tokio::test]
+async fn draw_keeps_the_last_element_for_a_repeated_id() {
+    let router = router();
+    let mut second = rectangle("a");
+    second["x"] = json!(99.0);
+
+    let drawn = call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b"), second] })),
+    )
+    .await;
+    assert_eq!(drawn.status, StatusCode::OK);
+    assert_eq!(element_ids(&router).await, ["b", "a"]);
+    let stored = call(&router, "GET", "/canvas/element/a", None).await.json();
+    assert_eq!(stored["element"]["x"], 99.0);
+}
+
+#[tokio::test]
+async fn patch_changes_only_the_given_fields() {
+    let router = router();
+    let mut original = rectangle("a");
+    original["link"] = json!("https://example.com");
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": original })),
+    )
+    .await;
+
+    let patched = call(
+        &router,
+        "PATCH",
+        "/canvas/element/a",
+        Some(json!({ "x": 42.0, "link": null })),
+    )
+    .await;
+    assert_eq!(patched.status, StatusCode::OK);
+
+    let stored = call(&router, "GET", "/canvas/element/a", None).await.json()["element"].clone();
+    assert_eq!(stored["x"], 42.0);
+    assert!(stored.get("link").is_none());
+    for (field, value) in rectangle("a").as_object().expect("object") {
+        if field != "x" {
+            assert_eq!(&stored[field], value, "field {}", field);
+        }
+    }
+}
+
+#[tokio::test]
+async fn patching_an_unknown_element_is_not_found() {
+    let router = router();
+
+    let patched = call(
+        &router,
+        "PATCH",
+        "/canvas/element/missing",
+        Some(json!({ "x": 1.0 })),
+    )
+    .await;
+    assert_eq!(patched.status, StatusCode::NOT_FOUND);
+    assert_eq!(patched.json()["error"]["code"], "element_not_found");
+}
+
+#[tokio::test]
+async fn duplicate_copies_under_a_new_id() {
+    let router = router();
+    let added = call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("a") })),
+    )
+    .await;
+    assert_eq!(added.status, StatusCode::OK);
+
+    let duplicated = call(
+        &router,
+        "POST",
+        "/canvas/element/a/duplicate",
+        Some(json!({ "dx": 5, "dy": -5 })),
+    )
+    .await;
+    assert_eq!(duplicated.status, StatusCode::OK);
+    let copy = duplicated.json()["element"].clone();
+    let copy_id = copy["id"].as_str().expect("copy id");
+    assert!(uuid::Uuid::parse_str(copy_id).is_ok());
+    assert_eq!(copy["x"], 15.0);
+    assert_eq!(copy["y"], 15.0);
+
+    let original = rectangle("a");
+    for (field, value) in original.as_object().expect("object") {
+        if !matches!(field.as_str(), "id" | "x" | "y") {
+            assert_eq!(&copy[field], value, "field {}", field);
+        }
+    }
+    assert_eq!(element_ids(&router).await, ["a", copy_id]);
+}
+
+#[tokio::test]
+async fn duplicate_without_body_keeps_position() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("a") })),
+    )
+    .await;
+
+    let duplicated = call(&router, "POST", "/canvas/element/a/duplicate", None).await;
+    assert_eq!(duplicated.status, StatusCode::OK);
+    let copy = duplicated.json()["element"].clone();
+    assert_eq!(copy["x"], 10.0);
+    assert_eq!(copy["y"], 20.0);
+}
+
+#[tokio::test]
+async fn duplicate_rejects_a_malformed_body() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("a") })),
+    )
+    .await;
+
+    let request = Request::builder()
+        .method("POST")
+        .uri("/canvas/element/a/duplicate")
+        .header("content-type", "application/json")
+        .body(Body::from("{not json"))
+        .expect("valid request");
+    let duplicated = send(&router, request).await;
+    assert_eq!(duplicated.status, StatusCode::BAD_REQUEST);
+    assert_eq!(duplicated.json()["error"]["code"], "invalid_json");
+    assert_eq!(element_ids(&router).await, ["a"]);
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use extauri_lib::{create_router, test_router, AppState, RecordingSink, ServerConfig};
+use serde_json::{json, Value};
+use tokio_stream::StreamExt;
+use tower::ServiceExt;
+
+use common::{call, rectangle, router};
+
+#[tokio::test]
+async fn clear_emits_the_clear_event_with_its_timestamp() {
+    let sink = Arc::new(RecordingSink::default());
+    let router = test_router(sink.clone());
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    sink.take();
+
+    let cleared = call(&router, "POST", "/canvas/clear", None).await;
+    assert_eq!(cleared.status, StatusCode::OK);
+    let events = sink.take();
+    assert_eq!(events.len(), 1);
+    let (name, payload) = &events[0];
+    assert_eq!(name, "excalidraw_clear");
+    assert_eq!(payload["scene"], "default");
+
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    assert_eq!(payload["updated_at"], canvas["canvas"]["updated_at"]);
+}
+
+#[tokio::test]
+async fn sse_subscribers_receive_each_change() {
+    let router = router();
+    let subscribed = router
+        .clone()
+        .oneshot(
+            Request::get("/events")
+                .body(Body::empty())
+                .expect("valid request"),
+        )
+        .await
+        .expect("router is infallible");
+    assert_eq!(subscribed.status(), StatusCode::OK);
+    assert_eq!(subscribed.headers()["content-type"], "text/event-stream");
+    let mut frames = subscribed.into_body().into_data_stream();
+
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+
+    let frame = tokio::time::timeout(Duration::from_secs(5), frames.next())
+        .await
+        .expect("an event within 5s")
+        .expect("stream is open")
+        .expect("frame is readable");
+    let frame = String::from_utf8(frame.to_vec()).expect("UTF-8 frame");
+    assert!(frame.starts_with("event: canvas\n"), "{}", frame);
+    let data = frame
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .expect("a data line");
+    let canvas: Value = serde_json::from_str(data).expect("JSON data");
+    assert_eq!(canvas["elements"][0]["id"], "a");
+}
+
+#[tokio::test]
+async fn debounced_draws_coalesce_into_a_few_events() {
+    let sink = Arc::new(RecordingSink::default());
+    let config = ServerConfig {
+        draw_debounce: Some(Duration::from_millis(50)),
+        rate_limit: 0,
+        ..ServerConfig::default()
+    };
+    let router = create_router(AppState::new(sink.clone(), config));
+
+    for x in 0..100 {
+        let mut element = rectangle("a");
+        element["x"] = json!(x);
+        let drawn = call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [element] })),
+        )
+        .await;
+        assert_eq!(drawn.status, StatusCode::OK);
+    }
+    // Leave time for the trailing emit after the last draw
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let events = sink.take();
+    assert!(!events.is_empty());
+    assert!(events.len() < 20, "{} events for 100 draws", events.len());
+    let (name, last) = events.last().expect("a draw event");
+    assert_eq!(name, "excalidraw_draw");
+    assert_eq!(last["elements"][0]["x"], 99);
+}
+
+#[tokio::test]
+async fn element_changes_emit_just_the_element() {
+    let sink = Arc::new(RecordingSink::default());
+    let router = test_router(sink.clone());
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    sink.take();
+
+    call(
+        &router,
+        "POST",
+        "/canvas/element",
+        Some(json!({ "element": rectangle("b") })),
+    )
+    .await;
+    let mut moved = rectangle("b");
+    moved["x"] = json!(300.0);
+    call(
+        &router,
+        "PUT",
+        "/canvas/element/b",
+        Some(json!({ "element": moved })),
+    )
+    .await;
+    call(&router, "DELETE", "/canvas/element/b", None).await;
+
+    let events = sink.take();
+    let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "excalidraw_element_added",
+            "excalidraw_element_updated",
+            "excalidraw_element_removed",
+        ]
+    );
+    assert_eq!(events[0].1["id"], "b");
+    assert_eq!(events[0].1["element"]["x"], 10.0);
+    assert_eq!(events[1].1["element"]["x"], 300.0);
+    assert_eq!(events[2].1["id"], "b");
+    for (_, payload) in &events {
+        assert!(payload.get("elements").is_none(), "{}", payload);
+    }
+}
@@ -0,0 +1,338 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use common::{call, element_ids, rectangle, router};
+
+#[tokio::test]
+async fn oversized_raster_exports_are_rejected() {
+    let router = router();
+
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=png&width=8000&height=8000&scale=2",
+        None,
+    )
+    .await;
+    assert_eq!(export.status, StatusCode::BAD_REQUEST);
+    let error = &export.json()["error"];
+    assert_eq!(error["code"], "export_too_large");
+    assert_eq!(error["width"], 16000);
+    assert_eq!(error["height"], 16000);
+}
+
+#[tokio::test]
+async fn oversized_svg_exports_are_not_limited() {
+    let router = router();
+
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=svg&width=8000&height=8000&scale=2",
+        None,
+    )
+    .await;
+    assert_eq!(export.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn png_export_returns_a_png() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=png&width=40&height=30",
+        None,
+    )
+    .await;
+    assert_eq!(export.status, StatusCode::OK);
+    assert_eq!(export.headers["content-type"], "image/png");
+    assert!(export.body.starts_with(b"\x89PNG\r\n\x1a\n"));
+}
+
+#[tokio::test]
+async fn json_exports_import_back_unchanged() {
+    let source = router();
+    call(
+        &source,
+        "POST",
+        "/draw",
+        Some(json!({
+            "elements": [rectangle("a"), rectangle("b")],
+            "appState": { "viewBackgroundColor": "#ffc9c9" },
+        })),
+    )
+    .await;
+    let exported = call(&source, "GET", "/canvas/export?format=json", None).await;
+    assert_eq!(exported.status, StatusCode::OK);
+
+    let target = router();
+    let imported = call(&target, "POST", "/canvas/import", Some(exported.json())).await;
+    assert_eq!(imported.status, StatusCode::OK);
+
+    let original = call(&source, "GET", "/canvas", None).await.json();
+    let restored = call(&target, "GET", "/canvas", None).await.json();
+    for field in ["elements", "appState", "files"] {
+        assert_eq!(
+            restored["canvas"][field], original["canvas"][field],
+            "{}",
+            field
+        );
+    }
+}
+
+#[tokio::test]
+async fn imports_must_be_excalidraw_files() {
+    let router = router();
+
+    for (file, code) in [
+        (json!({ "type": "other", "elements": [] }), "invalid_import"),
+        (json!({ "elements": [] }), "invalid_import"),
+        (
+            json!({ "type": "excalidraw", "elements": {} }),
+            "invalid_elements",
+        ),
+    ] {
+        let imported = call(&router, "POST", "/canvas/import", Some(file)).await;
+        assert_eq!(imported.status, StatusCode::BAD_REQUEST);
+        assert_eq!(imported.json()["error"]["code"], code);
+    }
+    assert!(element_ids(&router).await.is_empty());
+}
+
+async fn png_size(router: &axum::Router, query: &str) -> (u32, u32) {
+    let export = call(router, "GET", &format!("/canvas/export?{}", query), None).await;
+    assert_eq!(export.status, StatusCode::OK);
+    let png = image::load_from_memory(&export.body).expect("decodable PNG");
+    (png.width(), png.height())
+}
+
+#[tokio::test]
+async fn scale_multiplies_raster_dimensions() {
+    let router = router();
+
+    assert_eq!(
+        png_size(&router, "format=png&width=120&height=80").await,
+        (120, 80)
+    );
+    assert_eq!(
+        png_size(&router, "format=png&width=120&height=80&scale=2").await,
+        (240, 160)
+    );
+}
+
+#[tokio::test]
+async fn scale_resizes_svg_but_keeps_the_view_box() {
+    let router = router();
+
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=svg&width=120&height=80&scale=2",
+        None,
+    )
+    .await;
+    let svg = String::from_utf8(export.body.to_vec()).expect("UTF-8 SVG");
+    assert!(svg.contains(r#"width="240""#), "{}", svg);
+    assert!(svg.contains(r#"height="160""#), "{}", svg);
+    assert!(svg.contains(r#"viewBox="0 0 120 80""#), "{}", svg);
+}
+
+#[tokio::test]
+async fn non_positive_scales_are_rejected() {
+    let router = router();
+
+    for scale in ["0", "-1"] {
+        let export = call(
+            &router,
+            "GET",
+            &format!("/canvas/export?format=png&scale={}", scale),
+            None,
+        )
+        .await;
+        assert_eq!(export.status, StatusCode::BAD_REQUEST);
+        assert_eq!(export.json()["error"]["code"], "invalid_scale");
+    }
+}
+
+#[tokio::test]
+async fn ids_export_only_the_selected_elements() {
+    let router = router();
+    let mut left_out = rectangle("c");
+    left_out["x"] = json!(777.0);
+    left_out["strokeColor"] = json!("#e03131");
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b"), left_out] })),
+    )
+    .await;
+
+    let svg_for = |query: &'static str| {
+        let router = router.clone();
+        async move {
+            let export = call(&router, "GET", query, None).await;
+            assert_eq!(export.status, StatusCode::OK);
+            String::from_utf8(export.body.to_vec()).expect("UTF-8 SVG")
+        }
+    };
+    let all = svg_for("/canvas/export?format=svg").await;
+    assert!(all.contains("#e03131"), "{}", all);
+
+    let selected = svg_for("/canvas/export?format=svg&ids=a,b,missing").await;
+    assert!(selected.contains("#1e1e1e"), "{}", selected);
+    assert!(!selected.contains("#e03131"), "{}", selected);
+    assert!(!selected.contains("777"), "{}", selected);
+
+    let none = svg_for("/canvas/export?format=svg&fit=true&ids=missing").await;
+    assert!(!none.contains("#1e1e1e"), "{}", none);
+}
+
+#[tokio::test]
+async fn webp_exports_are_riff_webp_files() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=webp&width=40&height=30",
+        None,
+    )
+    .await;
+    assert_eq!(export.status, StatusCode::OK);
+    assert_eq!(export.headers["content-type"], "image/webp");
+    assert_eq!(&export.body[..4], b"RIFF");
+    assert_eq!(&export.body[8..12], b"WEBP");
+}
+
+#[tokio::test]
+async fn out_of_range_qualities_are_clamped() {
+    let router = router();
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a")] })),
+    )
+    .await;
+    let jpeg = |quality: &'static str| {
+        let router = router.clone();
+        async move {
+            let uri = format!(
+                "/canvas/export?format=jpeg&width=64&height=64&quality={}",
+                quality
+            );
+            let export = call(&router, "GET", &uri, None).await;
+            assert_eq!(export.status, StatusCode::OK, "quality {}", quality);
+            export.body
+        }
+    };
+
+    assert_eq!(jpeg("-20").await, jpeg("1").await);
+    assert_eq!(jpeg("500").await, jpeg("100").await);
+    assert_ne!(jpeg("1").await, jpeg("100").await);
+}
+
+#[tokio::test]
+async fn elements_only_json_is_a_bare_array() {
+    let router = router();
+
+    let empty = call(
+        &router,
+        "GET",
+        "/canvas/export?format=json&elements_only=true",
+        None,
+    )
+    .await;
+    assert_eq!(empty.status, StatusCode::OK);
+    assert_eq!(empty.json(), json!([]));
+
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({
+            "elements": [rectangle("a")],
+            "appState": { "viewBackgroundColor": "#fff" },
+        })),
+    )
+    .await;
+    let export = call(
+        &router,
+        "GET",
+        "/canvas/export?format=json&elements_only=true",
+        None,
+    )
+    .await;
+    assert_eq!(export.status, StatusCode::OK);
+    assert_eq!(export.headers["content-type"], "application/json");
+    assert!(!export.headers.contains_key("content-disposition"));
+    let elements = export.json();
+    assert_eq!(elements.as_array().map(Vec::len), Some(1));
+    assert_eq!(elements[0]["id"], "a");
+}
+
+#[tokio::test]
+async fn frame_exports_crop_to_the_frame_and_its_contents() {
+    let router = router();
+    let frame = json!({
+        "id": "f",
+        "type": "frame",
+        "x": 100.0,
+        "y": 50.0,
+        "width": 200.0,
+        "height": 100.0,
+        "name": "Panel",
+    });
+    let mut assigned = rectangle("assigned");
+    assigned["frameId"] = json!("f");
+    assigned["strokeColor"] = json!("#2f9e44");
+    let mut inside = rectangle("inside");
+    inside["x"] = json!(150.0);
+    inside["y"] = json!(80.0);
+    inside["width"] = json!(20.0);
+    inside["height"] = json!(20.0);
+    inside["strokeColor"] = json!("#1971c2");
+    let mut outside = rectangle("outside");
+    outside["x"] = json!(600.0);
+    outside["strokeColor"] = json!("#e03131");
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [frame, assigned, inside, outside] })),
+    )
+    .await;
+
+    let export = call(&router, "GET", "/canvas/frame/f/export?format=svg", None).await;
+    assert_eq!(export.status, StatusCode::OK);
+    let svg = String::from_utf8(export.body.to_vec()).expect("UTF-8 SVG");
+    assert!(svg.contains(r#"viewBox="100 50 200 100""#), "{}", svg);
+    assert!(svg.contains("#2f9e44"), "{}", svg);
+    assert!(svg.contains("#1971c2"), "{}", svg);
+    assert!(!svg.contains("#e03131"), "{}", svg);
+    // The frame itself, and so its name, is left out
+    assert!(!svg.contains("Panel"), "{}", svg);
+
+    let missing = call(&router, "GET", "/canvas/frame/nope/export", None).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    assert_eq!(missing.json()["error"]["code"], "frame_not_found");
+}
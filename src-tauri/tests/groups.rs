@@ -0,0 +1,92 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use extauri_lib::{test_router, RecordingSink};
+use serde_json::{json, Value};
+
+use common::{call, element_ids, rectangle, router};
+
+fn grouped(id: &str, x: f64, y: f64, group_ids: &[&str]) -> Value {
+    let mut element = rectangle(id);
+    element["x"] = json!(x);
+    element["y"] = json!(y);
+    element["groupIds"] = json!(group_ids);
+    element
+}
+
+fn seed() -> Value {
+    json!({
+        "elements": [
+            grouped("a", 0.0, 0.0, &["g"]),
+            grouped("b", 100.0, 50.0, &["inner", "g"]),
+            grouped("c", -20.0, 30.0, &["g"]),
+            grouped("other", 500.0, 500.0, &[]),
+        ],
+    })
+}
+
+#[tokio::test]
+async fn translating_a_group_moves_every_member_once() {
+    let sink = Arc::new(RecordingSink::default());
+    let router = test_router(sink.clone());
+    call(&router, "POST", "/draw", Some(seed())).await;
+    sink.take();
+
+    let moved = call(
+        &router,
+        "PUT",
+        "/canvas/group/g/translate",
+        Some(json!({ "dx": 15.0, "dy": -5.0 })),
+    )
+    .await;
+    assert_eq!(moved.status, StatusCode::OK);
+    assert_eq!(sink.take_names(), ["excalidraw_draw"]);
+
+    let canvas = call(&router, "GET", "/canvas", None).await.json();
+    let positions: Vec<(f64, f64)> = canvas["canvas"]["elements"]
+        .as_array()
+        .expect("elements array")
+        .iter()
+        .map(|element| {
+            (
+                element["x"].as_f64().unwrap(),
+                element["y"].as_f64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        positions,
+        [(15.0, -5.0), (115.0, 45.0), (-5.0, 25.0), (500.0, 500.0)]
+    );
+}
+
+#[tokio::test]
+async fn deleting_a_group_removes_only_its_members() {
+    let router = router();
+    call(&router, "POST", "/draw", Some(seed())).await;
+
+    let deleted = call(&router, "DELETE", "/canvas/group/g", None).await;
+    assert_eq!(deleted.status, StatusCode::OK);
+    assert_eq!(element_ids(&router).await, ["other"]);
+}
+
+#[tokio::test]
+async fn unknown_groups_are_not_found() {
+    let router = router();
+    call(&router, "POST", "/draw", Some(seed())).await;
+
+    let deleted = call(&router, "DELETE", "/canvas/group/none", None).await;
+    assert_eq!(deleted.status, StatusCode::NOT_FOUND);
+    assert_eq!(deleted.json()["error"]["code"], "group_not_found");
+    let moved = call(
+        &router,
+        "PUT",
+        "/canvas/group/none/translate",
+        Some(json!({ "dx": 1.0, "dy": 1.0 })),
+    )
+    .await;
+    assert_eq!(moved.status, StatusCode::NOT_FOUND);
+    assert_eq!(element_ids(&router).await.len(), 4);
+}
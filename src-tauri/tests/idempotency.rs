@@ -0,0 +1,81 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+
+use common::{element_ids, rectangle, router, send, TestResponse};
+
+async fn add_with_key(router: &Router, key: &str, element: Value) -> TestResponse {
+    let request = Request::builder()
+        .method("POST")
+        .uri("/canvas/element")
+        .header("content-type", "application/json")
+        .header("idempotency-key", key)
+        .body(Body::from(json!({ "element": element }).to_string()))
+        .expect("valid request");
+    send(router, request).await
+}
+
+#[tokio::test]
+async fn retried_add_is_applied_once() {
+    let router = router();
+
+    let first = add_with_key(&router, "k", rectangle("a")).await;
+    assert_eq!(first.status, StatusCode::OK);
+    let retry = add_with_key(&router, "k", rectangle("a")).await;
+    assert_eq!(retry.status, StatusCode::OK);
+    assert_eq!(retry.headers["idempotent-replayed"], "true");
+    assert_eq!(retry.body, first.body);
+
+    assert_eq!(element_ids(&router).await, ["a"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_retries_are_applied_once() {
+    let router = router();
+
+    let attempts: Vec<_> = (0..8)
+        .map(|_| {
+            let router = router.clone();
+            tokio::spawn(async move { add_with_key(&router, "k", rectangle("a")).await.status })
+        })
+        .collect();
+    for attempt in attempts {
+        let status = attempt.await.expect("request task");
+        assert!(
+            status == StatusCode::OK || status == StatusCode::CONFLICT,
+            "unexpected status {}",
+            status
+        );
+    }
+
+    assert_eq!(element_ids(&router).await, ["a"]);
+}
+
+#[tokio::test]
+async fn reused_key_with_another_body_is_rejected() {
+    let router = router();
+
+    let first = add_with_key(&router, "k", rectangle("a")).await;
+    assert_eq!(first.status, StatusCode::OK);
+    let reused = add_with_key(&router, "k", rectangle("b")).await;
+    assert_eq!(reused.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(reused.json()["error"]["code"], "idempotency_key_reused");
+
+    assert_eq!(element_ids(&router).await, ["a"]);
+}
+
+#[tokio::test]
+async fn failed_attempt_can_be_retried() {
+    let router = router();
+
+    let invalid = add_with_key(&router, "k", json!({ "type": "rectangle" })).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+
+    // The key was freed by the failure, so the same body runs again and fails again
+    let retried = add_with_key(&router, "k", json!({ "type": "rectangle" })).await;
+    assert_eq!(retried.status, StatusCode::BAD_REQUEST);
+    assert!(!retried.headers.contains_key("idempotent-replayed"));
+}
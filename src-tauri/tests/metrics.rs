@@ -0,0 +1,53 @@
+mod common;
+
+use axum::http::StatusCode;
+use axum::Router;
+use serde_json::json;
+
+use common::{call, rectangle, router};
+
+async fn scrape(router: &Router) -> String {
+    let scraped = call(router, "GET", "/metrics", None).await;
+    assert_eq!(scraped.status, StatusCode::OK);
+    String::from_utf8(scraped.body.to_vec()).expect("UTF-8 metrics")
+}
+
+#[tokio::test]
+async fn a_draw_shows_up_in_the_scraped_metrics() {
+    let router = router();
+    let before = scrape(&router).await;
+    assert!(
+        before.contains("extauri_canvas_mutations_total 0\n"),
+        "{}",
+        before
+    );
+
+    call(
+        &router,
+        "POST",
+        "/draw",
+        Some(json!({ "elements": [rectangle("a"), rectangle("b")] })),
+    )
+    .await;
+
+    let after = scrape(&router).await;
+    assert!(
+        after.contains("extauri_canvas_mutations_total 1\n"),
+        "{}",
+        after
+    );
+    assert!(
+        after.contains(
+            "extauri_http_requests_total{method=\"POST\",route=\"/draw\",status=\"200\"} 1\n"
+        ),
+        "{}",
+        after
+    );
+    assert!(
+        after.contains("extauri_canvas_elements{scene=\"default\"} 2\n"),
+        "{}",
+        after
+    );
+    // Scrapes do not count themselves
+    assert!(!after.contains("route=\"/metrics\""), "{}", after);
+}
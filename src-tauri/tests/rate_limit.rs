@@ -0,0 +1,39 @@
+mod common;
+
+use axum::http::StatusCode;
+use extauri_lib::ServerConfig;
+use serde_json::json;
+
+use common::{call, rectangle, router_with};
+
+#[tokio::test]
+async fn mutations_over_the_limit_get_429_with_retry_after() {
+    let router = router_with(ServerConfig {
+        rate_limit: 2,
+        ..ServerConfig::default()
+    });
+    let draw = || {
+        call(
+            &router,
+            "POST",
+            "/draw",
+            Some(json!({ "elements": [rectangle("a")] })),
+        )
+    };
+
+    assert_eq!(draw().await.status, StatusCode::OK);
+    assert_eq!(draw().await.status, StatusCode::OK);
+    let limited = draw().await;
+    assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(limited.json()["error"]["code"], "rate_limited");
+    let retry_after: u64 = limited.headers["retry-after"]
+        .to_str()
+        .expect("ASCII header")
+        .parse()
+        .expect("whole seconds");
+    assert!(retry_after >= 1);
+
+    // Reads are never limited
+    let canvas = call(&router, "GET", "/canvas", None).await;
+    assert_eq!(canvas.status, StatusCode::OK);
+}
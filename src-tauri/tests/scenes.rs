@@ -0,0 +1,42 @@
+mod common;
+
+use axum::http::StatusCode;
+use serde_json::json;
+
+use common::{call, rectangle, router};
+
+#[tokio::test]
+async fn scenes_keep_their_own_elements() {
+    let router = router();
+
+    for (scene, id) in [("one", "a"), ("two", "b")] {
+        let drawn = call(
+            &router,
+            "POST",
+            &format!("/scene/{}/draw", scene),
+            Some(json!({ "elements": [rectangle(id)] })),
+        )
+        .await;
+        assert_eq!(drawn.status, StatusCode::OK);
+    }
+
+    let one = call(&router, "GET", "/scene/one/canvas", None).await.json();
+    let two = call(&router, "GET", "/scene/two/canvas", None).await.json();
+    assert_eq!(one["canvas"]["elements"][0]["id"], "a");
+    assert_eq!(one["canvas"]["elements"].as_array().map(Vec::len), Some(1));
+    assert_eq!(two["canvas"]["elements"][0]["id"], "b");
+    assert_eq!(two["canvas"]["elements"].as_array().map(Vec::len), Some(1));
+
+    // The unprefixed routes are the default scene, which neither write touched
+    let default = call(&router, "GET", "/canvas", None).await.json();
+    assert!(default["canvas"]["elements"].is_null());
+}
+
+#[tokio::test]
+async fn reading_an_unknown_scene_is_not_found() {
+    let router = router();
+
+    let read = call(&router, "GET", "/scene/missing/canvas", None).await;
+    assert_eq!(read.status, StatusCode::NOT_FOUND);
+    assert_eq!(read.json()["error"]["code"], "scene_not_found");
+}
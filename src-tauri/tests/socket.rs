@@ -0,0 +1,76 @@
+mod common;
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use common::{rectangle, router};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serve a fresh router on an ephemeral port and open a socket to its /ws route.
+async fn connect() -> Socket {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind an ephemeral port");
+    let addr = listener.local_addr().expect("bound address");
+    tokio::spawn(async move { axum::serve(listener, router()).await });
+    let (socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+        .await
+        .expect("WebSocket handshake");
+    socket
+}
+
+/// The next text frame as JSON, failing if none arrives within 5s.
+async fn next_frame(socket: &mut Socket) -> Value {
+    loop {
+        let message = tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("a frame within 5s")
+            .expect("socket is open")
+            .expect("frame is readable");
+        if let Message::Text(text) = message {
+            return serde_json::from_str(&text).expect("JSON frame");
+        }
+    }
+}
+
+#[tokio::test]
+async fn a_draw_sent_on_the_socket_comes_back_as_an_update() {
+    let mut socket = connect().await;
+
+    let draw = json!({ "type": "draw", "elements": [rectangle("a")] });
+    socket
+        .send(Message::Text(draw.to_string()))
+        .await
+        .expect("send draw");
+
+    let update = next_frame(&mut socket).await;
+    assert_eq!(update["scene"], "default");
+    assert_eq!(update["elements"][0]["id"], "a");
+}
+
+#[tokio::test]
+async fn malformed_frames_get_an_error_frame_and_keep_the_socket() {
+    let mut socket = connect().await;
+
+    socket
+        .send(Message::Text("not json".to_string()))
+        .await
+        .expect("send garbage");
+    let error = next_frame(&mut socket).await;
+    assert_eq!(error["error"]["code"], "invalid_message");
+
+    // The same connection still applies edits
+    let draw = json!({ "type": "draw", "elements": [rectangle("b")] });
+    socket
+        .send(Message::Text(draw.to_string()))
+        .await
+        .expect("send draw");
+    let update = next_frame(&mut socket).await;
+    assert_eq!(update["elements"][0]["id"], "b");
+}
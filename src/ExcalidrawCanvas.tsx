@@ -17,11 +17,24 @@ console.log('🔍 环境检测:', {
 });
 
 type DrawPayload = {
+  scene?: string;
+  seq?: number;
   elements?: any;
   appState?: any;
   files?: any;
 };
 
+// 单个元素变化的事件，删除事件不带 element
+type ElementEventPayload = {
+  scene?: string;
+  seq?: number;
+  id: string;
+  element?: any;
+};
+
+// 桌面端只显示默认场景，其他场景的事件直接忽略
+const DEFAULT_SCENE = "default";
+
 // 确保 collaborators 是 Map 类型的辅助函数
 const ensureCollaboratorsMap = (appState: any) => {
   if (!appState) return { collaborators: new Map() };
@@ -46,6 +59,7 @@ export function ExcalidrawCanvas() {
   const isUpdatingFromRestore = useRef(false); // 防止数据恢复时触发onChange的标志
   const saveTimeoutRef = useRef<number | null>(null); // 防抖定时器
   const lastSaveDataRef = useRef<string>(''); // 上次保存的数据哈希
+  const lastSeqRef = useRef<number | null>(null); // 最近收到的事件序号
 
   // 初始化IndexedDB并恢复画布数据
   useEffect(() => {
@@ -306,8 +320,58 @@ export function ExcalidrawCanvas() {
     }
   };
 
+  // 在当前画布元素上应用单个元素的变化，其余元素保持不变
+  const handleElementEvent = async (payload: ElementEventPayload) => {
+    if (!apiRef.current) {
+      console.log("❌ apiRef.current 不存在，无法更新画布");
+      return;
+    }
+    const current = apiRef.current.getSceneElements();
+    const newId = payload.element?.id;
+    const index = current.findIndex((element: any) => element.id === payload.id);
+    const elements = current.filter(
+      (element: any) => element.id !== payload.id && element.id !== newId
+    );
+    if (payload.element) {
+      if (index >= 0) {
+        elements.splice(Math.min(index, elements.length), 0, payload.element);
+      } else {
+        elements.push(payload.element);
+      }
+    }
+    await handleCanvasUpdate({ elements, files: apiRef.current.getFiles() });
+  };
+
+  // 事件序号不连续说明漏掉了事件，重新拉取整个画布；返回 true 表示已经同步到最新
+  const resyncIfMissed = async (seq?: number) => {
+    if (seq === undefined) return false;
+    const last = lastSeqRef.current;
+    lastSeqRef.current = Math.max(last ?? 0, seq);
+    if (last === null || seq <= last + 1) return false;
+
+    console.warn(`⚠️ 事件序号从 ${last} 跳到 ${seq}，重新同步画布`);
+    try {
+      const response = await fetch('http://localhost:31337/canvas');
+      if (response.ok) {
+        const data = await response.json();
+        lastSeqRef.current = Math.max(lastSeqRef.current ?? 0, data.seq ?? 0);
+        await handleCanvasUpdate({
+          elements: data.canvas?.elements,
+          appState: data.canvas?.appState,
+          files: data.canvas?.files
+        });
+        return true;
+      }
+    } catch (error) {
+      console.warn('⚠️ 重新同步画布失败:', error);
+    }
+    return false;
+  };
+
   useEffect(() => {
     let unlisten: UnlistenFn | null = null;
+    let unlistenClear: UnlistenFn | null = null;
+    const unlistenElements: UnlistenFn[] = [];
 
     if (!isTauri) {
       console.log("⚠️ 非Tauri环境，启用轮询机制检测后端数据变化");
@@ -354,10 +418,37 @@ export function ExcalidrawCanvas() {
       unlisten = await listen<DrawPayload>("excalidraw_draw", async (event) => {
         console.log("🎨 收到Tauri绘制事件:", event);
         const payload = event.payload as DrawPayload;
+        // 序号在所有场景间共享，先检查再过滤场景
+        if (await resyncIfMissed(payload.seq)) return;
+        if (payload.scene && payload.scene !== DEFAULT_SCENE) return;
 
         // 使用统一的画布更新处理逻辑
         await handleCanvasUpdate(payload);
       });
+
+      // 清除画布使用独立事件，不再依赖空元素数组判断
+      unlistenClear = await listen<{ scene?: string; seq?: number; updated_at: string }>("excalidraw_clear", async (event) => {
+        console.log("🧹 收到Tauri清除事件:", event);
+        if (await resyncIfMissed(event.payload.seq)) return;
+        if (event.payload.scene && event.payload.scene !== DEFAULT_SCENE) return;
+        await handleCanvasUpdate({ elements: [] });
+      });
+
+      // 单个元素的增删改只携带该元素，无需整体重绘
+      for (const name of [
+        "excalidraw_element_added",
+        "excalidraw_element_updated",
+        "excalidraw_element_removed",
+      ]) {
+        unlistenElements.push(
+          await listen<ElementEventPayload>(name, async (event) => {
+            console.log("🧩 收到Tauri元素事件:", name, event);
+            if (await resyncIfMissed(event.payload.seq)) return;
+            if (event.payload.scene && event.payload.scene !== DEFAULT_SCENE) return;
+            await handleElementEvent(event.payload);
+          })
+        );
+      }
       console.log("✅ 事件监听器设置完成");
     })();
     return () => {
@@ -365,6 +456,10 @@ export function ExcalidrawCanvas() {
         console.log("🧹 清理事件监听器");
         unlisten();
       }
+      if (unlistenClear) {
+        unlistenClear();
+      }
+      unlistenElements.forEach((unlistenElement) => unlistenElement());
     };
   }, []);
 